@@ -0,0 +1,213 @@
+use anyhow::Error;
+use std::path::Path;
+
+/// Returns an error if the file has an immutable or append-only flag set, explaining how to clear it. This is checked
+/// before doing any work since otherwise the write would only fail at the very end.
+#[cfg(target_os = "linux")]
+pub fn check_mutable(path: &Path) -> Result<(), Error> {
+    use anyhow::anyhow;
+    use std::os::fd::AsRawFd;
+
+    const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+    const FS_APPEND_FL: libc::c_int = 0x20;
+
+    // failing to open the file is reported later when reading it
+    let Ok(file) = std::fs::File::open(path) else {
+        return Ok(());
+    };
+    let mut flags: libc::c_int = 0;
+    // SAFETY: FS_IOC_GETFLAGS writes a single int to the given pointer.
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        // not every filesystem supports these flags
+        return Ok(());
+    }
+    let p = path.to_string_lossy();
+    if flags & FS_IMMUTABLE_FL != 0 {
+        Err(anyhow!(
+            "{} has the immutable attribute set and can't be modified, clear it with `sudo chattr -i {}` first",
+            p,
+            p
+        ))
+    } else if flags & FS_APPEND_FL != 0 {
+        Err(anyhow!(
+            "{} has the append-only attribute set and can't be modified, clear it with `sudo chattr -a {}` first",
+            p,
+            p
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns an error if the file has an immutable or append-only flag set, explaining how to clear it. This is checked
+/// before doing any work since otherwise the write would only fail at the very end.
+#[cfg(target_os = "macos")]
+pub fn check_mutable(path: &Path) -> Result<(), Error> {
+    use anyhow::anyhow;
+    use std::os::macos::fs::MetadataExt;
+
+    const UF_IMMUTABLE: u32 = 0x2;
+    const UF_APPEND: u32 = 0x4;
+    const SF_IMMUTABLE: u32 = 0x20000;
+    const SF_APPEND: u32 = 0x40000;
+
+    let Ok(meta) = std::fs::metadata(path) else {
+        return Ok(());
+    };
+    let flags = meta.st_flags();
+    let p = path.to_string_lossy();
+    if flags & UF_IMMUTABLE != 0 {
+        Err(anyhow!(
+            "{} has the user immutable flag set and can't be modified, clear it with `sudo chflags nouchg {}` first",
+            p,
+            p
+        ))
+    } else if flags & SF_IMMUTABLE != 0 {
+        Err(anyhow!(
+            "{} has the system immutable flag set and can't be modified, clear it with `sudo chflags noschg {}` first",
+            p,
+            p
+        ))
+    } else if flags & (UF_APPEND | SF_APPEND) != 0 {
+        Err(anyhow!(
+            "{} has an append-only flag set and can't be modified, clear it with `sudo chflags nouappnd,nosappnd {}` first",
+            p,
+            p
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn check_mutable(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Takes an exclusive lock that is held while reading, changing, and writing the file, so that concurrent eha commands
+/// wait for each other instead of losing each other's records. The lock is released when the returned file is closed.
+pub fn lock_for_update(path: &Path) -> Result<std::fs::File, Error> {
+    use anyhow::Context;
+    use std::fs::TryLockError;
+
+    let file = open_lock_file(path).context("failed to open lock file")?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("waiting for another eha command to finish with {}", path.to_string_lossy());
+            file.lock().context("failed to lock input file")?;
+        }
+        Err(TryLockError::Error(e)) => return Err(Error::new(e).context("failed to lock input file")),
+    }
+    Ok(file)
+}
+
+/// The file is replaced by a rename, and on Windows locks block reading and writing through any other handle, so a lock
+/// file next to the file is locked rather than the file itself. Locking the directory instead would also block other
+/// programs that lock it, such as those locking /etc.
+fn open_lock_file(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".eha.lock");
+    std::fs::File::options().create(true).truncate(false).write(true).open(lock)
+}
+
+/// Runs a write to the file with its read-only and hidden attributes cleared, putting them back afterwards. Windows
+/// refuses to write to or replace read-only files, and some corporate images protect the hosts file this way.
+#[cfg(windows)]
+pub fn with_unprotected<T>(path: &Path, write: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetFileAttributesW(file_name: *const u16, attributes: u32) -> i32;
+    }
+
+    let set_attributes = |attributes: u32| -> Result<(), Error> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+        // SAFETY: the name is a valid null terminated wide string that outlives the call.
+        if unsafe { SetFileAttributesW(wide.as_ptr(), attributes) } == 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(Error::new(e).context(format!("failed to set the attributes of {}", path.to_string_lossy())));
+        }
+        Ok(())
+    };
+
+    let Ok(attributes) = std::fs::metadata(path).map(|m| m.file_attributes()) else {
+        return write();
+    };
+    let protection = attributes & (FILE_ATTRIBUTE_READONLY | FILE_ATTRIBUTE_HIDDEN);
+    if protection == 0 {
+        return write();
+    }
+    set_attributes(attributes & !protection)?;
+    let result = write();
+    // a replaced file starts with fresh attributes, so put back the original ones either way
+    set_attributes(std::fs::metadata(path).map_or(attributes, |m| m.file_attributes() | protection))?;
+    result
+}
+
+#[cfg(not(windows))]
+pub fn with_unprotected<T>(_path: &Path, write: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    write()
+}
+
+/// Runs a file operation, retrying it for a couple of seconds while another process has the file open. Windows refuses
+/// to open or replace a file that is being scanned, and Defender scans the hosts file whenever it changes.
+#[cfg(windows)]
+pub fn retry_while_locked<T>(mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e)
+                if attempt < 10
+                    && matches!(
+                        e.raw_os_error(),
+                        Some(ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+                    ) =>
+            {
+                attempt += 1;
+                std::thread::sleep(std::time::Duration::from_millis(50 * attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn retry_while_locked<T>(mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    op()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_regular_file_is_mutable() -> Result<(), Error> {
+        let f = NamedTempFile::new()?;
+        check_mutable(f.path())
+    }
+
+    #[test]
+    fn test_lock_for_update() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("hosts");
+        let lock = lock_for_update(&path)?;
+        assert!(dir.path().join("hosts.eha.lock").exists());
+        assert!(open_lock_file(&path)?.try_lock().is_err());
+        // the directory itself stays unlocked for other programs
+        assert!(std::fs::File::open(dir.path())?.try_lock().is_ok());
+        drop(lock);
+        assert!(open_lock_file(&path)?.try_lock().is_ok());
+        Ok(())
+    }
+}
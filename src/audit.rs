@@ -0,0 +1,205 @@
+use anyhow::{Context, Error};
+use eha::Entry;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// One change to the hosts file, as written to the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub(crate) at: Timestamp,
+    /// The user that ran eha, looking through sudo.
+    pub(crate) user: Option<String>,
+    pub(crate) uid: Option<u32>,
+    pub(crate) host: Option<String>,
+    pub(crate) command: String,
+    pub(crate) target: PathBuf,
+    pub(crate) changes: Vec<NameChange>,
+}
+
+/// What happened to one name in a change.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct NameChange {
+    pub(crate) name: String,
+    pub(crate) action: Action,
+    /// The expiry of the entry before the change, unless it was added.
+    pub(crate) expiry_before: Option<Timestamp>,
+    /// The expiry of the entry after the change, unless it was removed.
+    pub(crate) expiry_after: Option<Timestamp>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Action {
+    Added,
+    Removed,
+    /// Removed because it had expired.
+    Expired,
+    Changed,
+}
+
+/// Returns what happened to each managed name between the entries before and after a write at the given time.
+pub(crate) fn changes(before: &[Entry], after: &[Entry], now: Timestamp) -> Vec<NameChange> {
+    let managed = |entries: &[Entry]| {
+        entries
+            .iter()
+            .filter_map(|e| match e {
+                Entry::Supported { name, meta, .. } => Some((name.clone(), (e.to_string(), meta.expiry))),
+                Entry::Other(_) => None,
+            })
+            .collect::<BTreeMap<String, (String, Option<Timestamp>)>>()
+    };
+    let (before, mut after) = (managed(before), managed(after));
+    let mut changes = Vec::new();
+    for (name, (line, expiry)) in before {
+        match after.remove(&name) {
+            None => changes.push(NameChange {
+                name,
+                action: match expiry {
+                    Some(e) if e <= now => Action::Expired,
+                    _ => Action::Removed,
+                },
+                expiry_before: expiry,
+                expiry_after: None,
+            }),
+            Some((new_line, new_expiry)) if new_line != line => changes.push(NameChange {
+                name,
+                action: Action::Changed,
+                expiry_before: expiry,
+                expiry_after: new_expiry,
+            }),
+            Some(_) => {}
+        }
+    }
+    changes.extend(after.into_iter().map(|(name, (_, expiry))| NameChange {
+        name,
+        action: Action::Added,
+        expiry_before: None,
+        expiry_after: expiry,
+    }));
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    changes
+}
+
+/// Appends a record to the audit log, a file of json lines that is only ever added to.
+pub(crate) fn append(path: &Path, record: &Record) -> Result<(), Error> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        create_dir_all(parent).context("failed to create audit log directory")?;
+    }
+    let mut file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open audit log {}", path.to_string_lossy()))?;
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    file.write_all(&line).context("failed to write audit log")?;
+    file.sync_all().context("failed to sync audit log")
+}
+
+/// Returns the last records of the audit log, oldest first.
+pub(crate) fn tail(path: &Path, count: usize) -> Result<Vec<Record>, Error> {
+    let p = path.to_string_lossy();
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::new(e).context(format!("failed to read audit log {}", p))),
+    };
+    let mut records = VecDeque::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("failed to read audit log {}", p))?;
+        records.push_back(serde_json::from_str(&line).with_context(|| format!("invalid record on line {} of audit log {}", i + 1, p))?);
+        if records.len() > count {
+            records.pop_front();
+        }
+    }
+    Ok(records.into())
+}
+
+/// Describes what happened to the names in a change, like `added foo.local expiring never, expired bar.local at ..`.
+pub(crate) fn describe(changes: &[NameChange]) -> String {
+    let expiry = |e: Option<Timestamp>| e.map_or("never".to_string(), |e| e.to_string());
+    changes
+        .iter()
+        .map(|c| match c.action {
+            Action::Added => format!("added {} expiring {}", c.name, expiry(c.expiry_after)),
+            Action::Removed => format!("removed {} expiring {}", c.name, expiry(c.expiry_before)),
+            Action::Expired => format!("expired {} at {}", c.name, expiry(c.expiry_before)),
+            Action::Changed => format!(
+                "changed {} expiring {} to {}",
+                c.name,
+                expiry(c.expiry_before),
+                expiry(c.expiry_after)
+            ),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Renders a record as a single line for reading in a terminal.
+pub(crate) fn render(record: &Record) -> String {
+    format!(
+        "{}\t{}\t{}\t`eha {}`\t{}",
+        record.at,
+        record.user.as_deref().unwrap_or("unknown"),
+        record.target.to_string_lossy(),
+        record.command,
+        describe(&record.changes)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eha::parse_content;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_changes_and_tail() -> Result<(), Error> {
+        let before = br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}
+127.0.0.1	same.local	# eha {"expiry":null,"comment":null}
+127.0.0.1	old.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}"##;
+        let after = br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-03T00:00:00Z","comment":null}
+127.0.0.1	same.local	# eha {"expiry":null,"comment":null}
+127.0.0.1	new.local	# eha {"expiry":null,"comment":null}"##;
+        let at: Timestamp = "2030-01-01T00:00:00Z".parse()?;
+        let changes = changes(&parse_content(before), &parse_content(after), at);
+        let record = Record {
+            at,
+            user: Some("alice".to_string()),
+            uid: Some(1000),
+            host: None,
+            command: "renew foo.local".to_string(),
+            target: PathBuf::from("/etc/hosts"),
+            changes,
+        };
+        assert_eq!(
+            render(&record),
+            "2030-01-01T00:00:00Z\talice\t/etc/hosts\t`eha renew foo.local`\tremoved bar.local expiring 2030-01-02T00:00:00Z, \
+             changed foo.local expiring 2030-01-02T00:00:00Z to 2030-01-03T00:00:00Z, added new.local expiring never, \
+             expired old.local at 2029-01-01T00:00:00Z"
+        );
+
+        let dir = tempdir()?;
+        let path = dir.path().join("log").join("audit.jsonl");
+        assert_eq!(tail(&path, 10)?, vec![]);
+        for i in 0..3 {
+            append(
+                &path,
+                &Record {
+                    uid: Some(i),
+                    ..record.clone()
+                },
+            )?;
+        }
+        let last = tail(&path, 2)?;
+        assert_eq!(last.iter().map(|r| r.uid).collect::<Vec<Option<u32>>>(), vec![Some(1), Some(2)]);
+        Ok(())
+    }
+}
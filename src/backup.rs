@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Context, Error};
+use jiff::Timestamp;
+use std::path::{Path, PathBuf};
+
+/// The number of backups kept of each hosts file when --backup-count isn't given.
+pub(crate) const DEFAULT_BACKUP_COUNT: usize = 5;
+
+/// Separates the name of the hosts file from the timestamp in the name of a backup.
+const BACKUP_INFIX: &str = ".eha-bak.";
+
+/// Copies the hosts file to `<file>.eha-bak.<timestamp>` next to it before it is changed, then removes all but the
+/// newest `keep` backups of it. A backup made in the same second as an earlier one gets a counter after its timestamp,
+/// like `.1`, rather than replacing it. Returns the path of the new backup.
+pub(crate) fn create(path: &Path, now: Timestamp, keep: usize) -> Result<PathBuf, Error> {
+    let name = |id: &str| {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!("{}{}", BACKUP_INFIX, id));
+        PathBuf::from(backup)
+    };
+    let timestamp = now.strftime("%Y%m%dT%H%M%SZ").to_string();
+    // the hosts file is locked while it is changed, so no other eha command can take the name in the meantime
+    let backup = (0..)
+        .map(|i| match i {
+            0 => name(&timestamp),
+            i => name(&format!("{}.{}", timestamp, i)),
+        })
+        .find(|b| !b.exists())
+        .expect("there is always an unused name");
+    std::fs::copy(path, &backup)
+        .with_context(|| format!("failed to back up {} to {}", path.to_string_lossy(), backup.to_string_lossy()))?;
+    rotate(path, keep)?;
+    Ok(backup)
+}
+
+/// Returns the id and path of each backup of the hosts file, oldest first. The id is the timestamp in its name and any
+/// counter after it, sorted by the timestamp and then the counter.
+pub(crate) fn list(path: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let prefix = format!("{}{}", path.file_name().unwrap_or_default().to_string_lossy(), BACKUP_INFIX);
+    let mut backups = std::fs::read_dir(dir)
+        .context("failed to list backups")?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let id = e.file_name().to_string_lossy().strip_prefix(&prefix)?.to_string();
+            Some((id, e.path()))
+        })
+        .collect::<Vec<(String, PathBuf)>>();
+    backups.sort_by_cached_key(|(id, _)| match id.split_once('.') {
+        Some((timestamp, counter)) => (timestamp.to_string(), counter.parse::<usize>().unwrap_or(usize::MAX)),
+        None => (id.clone(), 0),
+    });
+    Ok(backups)
+}
+
+/// Returns the path of the backup of the hosts file with the given id.
+pub(crate) fn find(path: &Path, id: &str) -> Result<PathBuf, Error> {
+    list(path)?
+        .into_iter()
+        .find_map(|(i, p)| (i == id).then_some(p))
+        .ok_or_else(|| anyhow!("no backup {} of {}, see eha restore --list", id, path.to_string_lossy()))
+}
+
+/// Removes all but the newest `keep` backups of the hosts file.
+fn rotate(path: &Path, keep: usize) -> Result<(), Error> {
+    let backups = list(path)?;
+    for (_, old) in &backups[..backups.len().saturating_sub(keep)] {
+        std::fs::remove_file(old).with_context(|| format!("failed to remove old backup {}", old.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::SignedDuration;
+
+    #[test]
+    fn test_create_and_rotate() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("hosts");
+        let now: Timestamp = "2030-01-01T00:00:00Z".parse()?;
+        for i in 0..4 {
+            std::fs::write(&path, format!("version {}", i))?;
+            create(&path, now + SignedDuration::from_hours(i), 2)?;
+        }
+        std::fs::write(dir.path().join("hosts.other"), "unrelated")?;
+        let backup = create(&path, now + SignedDuration::from_hours(4), 2)?;
+        assert_eq!(backup, dir.path().join("hosts.eha-bak.20300101T040000Z"));
+
+        let mut names = std::fs::read_dir(dir.path())?
+            .map(|e| Ok(e?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<String>, Error>>()?;
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "hosts",
+                "hosts.eha-bak.20300101T030000Z",
+                "hosts.eha-bak.20300101T040000Z",
+                "hosts.other"
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("hosts.eha-bak.20300101T030000Z"))?,
+            "version 3"
+        );
+        assert_eq!(
+            list(&path)?.into_iter().map(|(id, _)| id).collect::<Vec<String>>(),
+            vec!["20300101T030000Z", "20300101T040000Z"]
+        );
+        assert_eq!(find(&path, "20300101T040000Z")?, backup);
+        assert!(find(&path, "20300101T000000Z").is_err());
+
+        // backups within the same second are all kept, in order
+        let later = now + SignedDuration::from_hours(5);
+        for i in 0..11 {
+            std::fs::write(&path, format!("change {}", i))?;
+            create(&path, later, 20)?;
+        }
+        let ids = list(&path)?.into_iter().map(|(id, _)| id).collect::<Vec<String>>();
+        assert_eq!(ids.len(), 13);
+        assert_eq!(&ids[2..4], ["20300101T050000Z", "20300101T050000Z.1"]);
+        assert_eq!(ids[12], "20300101T050000Z.10");
+        assert_eq!(std::fs::read_to_string(find(&path, "20300101T050000Z.10")?)?, "change 10");
+        assert_eq!(std::fs::read_to_string(find(&path, "20300101T050000Z")?)?, "change 0");
+        Ok(())
+    }
+}
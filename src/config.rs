@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Context, Error};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// The system wide config file, read before the user's own.
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/eha.toml";
+
+/// Defaults for flags that would otherwise be passed on every invocation, read from toml config files. Flags given on
+/// the command line always win.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The hosts file to operate on instead of the platform default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_file: Option<String>,
+    /// The expiry of added entries when --expire isn't given and the hosts file header doesn't set one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_expire_minutes: Option<usize>,
+    /// The address added names resolve to when --ip isn't given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_ip: Option<IpAddr>,
+    /// Names must end in one of these suffixes, unless the policy or the hosts file header set their own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_suffixes: Option<Vec<String>>,
+    /// Flush the DNS cache after each change to the hosts file, like --flush-cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_cache: Option<bool>,
+    /// Back up the hosts file before each change to it, like --backup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<bool>,
+    /// The number of backups to keep of each hosts file, like --backup-count.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_count: Option<usize>,
+    /// The file every change is recorded in, like --audit-log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<String>,
+}
+
+/// The settings a config file can hold.
+const KEYS: &[&str] = &[
+    "input_file",
+    "default_expire_minutes",
+    "default_ip",
+    "allowed_suffixes",
+    "flush_cache",
+    "backup",
+    "backup_count",
+    "audit_log",
+];
+
+impl Config {
+    /// Loads the system config file and then the user's, with each setting in the user's replacing the system one.
+    /// Missing files are skipped.
+    pub fn load() -> Result<Self, Error> {
+        let mut paths = vec![PathBuf::from(SYSTEM_CONFIG_FILE)];
+        paths.extend(user_config_file());
+        Self::load_files(&paths)
+    }
+
+    fn load_files(paths: &[PathBuf]) -> Result<Self, Error> {
+        let mut config = Config::default();
+        for path in paths {
+            if let Some(c) = Self::load_file(path)? {
+                config = c.or(config);
+            }
+        }
+        Ok(config)
+    }
+
+    fn load_file(path: &Path) -> Result<Option<Self>, Error> {
+        let p = path.to_string_lossy();
+        match std::fs::read_to_string(path) {
+            Ok(raw) => toml::from_str(&raw)
+                .with_context(|| format!("failed to parse config file {}", p))
+                .map(Some),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::new(e).context(format!("failed to read config file {}", p))),
+        }
+    }
+
+    /// Renders the config as toml.
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string(self).context("failed to render config")
+    }
+
+    /// Returns the value of one setting for printing, or an empty string if it is unset. Lists are comma separated.
+    pub fn get(&self, key: &str) -> Result<String, Error> {
+        check_key(key)?;
+        let table = toml::Table::try_from(self).context("failed to render config")?;
+        Ok(match table.get(key) {
+            None => String::new(),
+            Some(toml::Value::String(s)) => s.clone(),
+            Some(toml::Value::Array(items)) => items
+                .iter()
+                .map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string))
+                .collect::<Vec<String>>()
+                .join(","),
+            Some(v) => v.to_string(),
+        })
+    }
+
+    /// Takes each setting from this config, falling back to the other one where it is unset.
+    fn or(self, other: Self) -> Self {
+        Config {
+            input_file: self.input_file.or(other.input_file),
+            default_expire_minutes: self.default_expire_minutes.or(other.default_expire_minutes),
+            default_ip: self.default_ip.or(other.default_ip),
+            allowed_suffixes: self.allowed_suffixes.or(other.allowed_suffixes),
+            flush_cache: self.flush_cache.or(other.flush_cache),
+            backup: self.backup.or(other.backup),
+            backup_count: self.backup_count.or(other.backup_count),
+            audit_log: self.audit_log.or(other.audit_log),
+        }
+    }
+}
+
+/// Changes one setting in a config file, creating the file if needed and keeping its other settings. Lists are given
+/// comma separated, and the resulting file must still be a valid config.
+pub fn set_in_file(path: &Path, key: &str, value: &str) -> Result<(), Error> {
+    check_key(key)?;
+    let p = path.to_string_lossy();
+    let mut table: toml::Table = match std::fs::read_to_string(path) {
+        Ok(raw) => toml::from_str(&raw).with_context(|| format!("failed to parse config file {}", p))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => toml::Table::new(),
+        Err(e) => return Err(Error::new(e).context(format!("failed to read config file {}", p))),
+    };
+    let parsed = match key {
+        "default_expire_minutes" => toml::Value::Integer(value.parse().map_err(|_| anyhow!("{} must be a whole number of minutes", key))?),
+        "flush_cache" | "backup" => toml::Value::Boolean(value.parse().map_err(|_| anyhow!("{} must be true or false", key))?),
+        "backup_count" => toml::Value::Integer(value.parse().map_err(|_| anyhow!("{} must be a whole number", key))?),
+        "allowed_suffixes" => toml::Value::Array(value.split(',').map(|s| toml::Value::String(s.trim().to_string())).collect()),
+        _ => toml::Value::String(value.to_string()),
+    };
+    table.insert(key.to_string(), parsed);
+    let raw = toml::to_string(&table).context("failed to render config")?;
+    toml::from_str::<Config>(&raw).with_context(|| format!("invalid value for {}", key))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("failed to create directory for config file {}", p))?;
+    }
+    std::fs::write(path, raw).with_context(|| format!("failed to write config file {}", p))
+}
+
+fn check_key(key: &str) -> Result<(), Error> {
+    if KEYS.contains(&key) {
+        Ok(())
+    } else {
+        Err(anyhow!("unknown config key {}, expected one of {}", key, KEYS.join(", ")))
+    }
+}
+
+/// Returns the path of the user's config file, $XDG_CONFIG_HOME/eha/config.toml or ~/.config/eha/config.toml.
+pub fn user_config_file() -> Option<PathBuf> {
+    if let Some(d) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(d).join("eha/config.toml"));
+    }
+    std::env::var_os("HOME")
+        .filter(|d| !d.is_empty())
+        .map(|d| PathBuf::from(d).join(".config/eha/config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_config_files() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let system = dir.path().join("eha.toml");
+        let user = dir.path().join("config.toml");
+        std::fs::write(&system, "default_expire_minutes = 60\ndefault_ip = \"127.0.0.2\"\n")?;
+        std::fs::write(
+            &user,
+            "default_expire_minutes = 120\nallowed_suffixes = [\".test\"]\nbackup_count = 3\n",
+        )?;
+
+        let config = Config::load_files(&[system.clone(), user, dir.path().join("missing.toml")])?;
+        assert_eq!(
+            config,
+            Config {
+                input_file: None,
+                default_expire_minutes: Some(120),
+                default_ip: Some("127.0.0.2".parse()?),
+                allowed_suffixes: Some(vec![".test".to_string()]),
+                flush_cache: None,
+                backup: None,
+                backup_count: Some(3),
+                audit_log: None,
+            }
+        );
+
+        std::fs::write(&system, "unknown = true")?;
+        assert!(Config::load_files(&[system]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_and_set() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("eha").join("config.toml");
+        set_in_file(&path, "default_expire_minutes", "90")?;
+        set_in_file(&path, "allowed_suffixes", ".local, .test")?;
+        assert!(set_in_file(&path, "default_ip", "nope").is_err());
+        assert!(set_in_file(&path, "colour", "blue").is_err());
+        assert!(set_in_file(&path, "flush_cache", "yes").is_err());
+        set_in_file(&path, "flush_cache", "true")?;
+
+        let config = Config::load_files(&[path])?;
+        assert_eq!(config.get("default_expire_minutes")?, "90");
+        assert_eq!(config.get("allowed_suffixes")?, ".local,.test");
+        assert_eq!(config.get("default_ip")?, "");
+        assert_eq!(config.get("flush_cache")?, "true");
+        assert!(config.get("colour").is_err());
+        assert_eq!(
+            config.to_toml()?,
+            "default_expire_minutes = 90\nallowed_suffixes = [\".local\", \".test\"]\nflush_cache = true\n"
+        );
+        Ok(())
+    }
+}
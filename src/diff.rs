@@ -0,0 +1,148 @@
+/// The most lines compared exactly between the unchanged start and end of two files. Larger changes are shown as a
+/// removal of all the old lines followed by all the new ones, rather than building a huge table.
+const MAX_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Returns a unified diff of two texts with the given number of context lines, or an empty string if they have the
+/// same lines.
+pub(crate) fn unified(old_name: &str, new_name: &str, old: &str, new: &str, context: usize) -> String {
+    let ops = edits(&old.lines().collect::<Vec<_>>(), &new.lines().collect::<Vec<_>>());
+    // group the changes into hunks, merging those whose context would overlap
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in ops.iter().enumerate().filter(|(_, (op, _))| *op != Op::Equal) {
+        let (start, end) = (i.saturating_sub(context), (i + 1 + context).min(ops.len()));
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let (mut old_line, mut new_line, mut next) = (0, 0, 0);
+    for (start, end) in hunks {
+        for (op, _) in &ops[next..start] {
+            old_line += usize::from(*op != Op::Insert);
+            new_line += usize::from(*op != Op::Delete);
+        }
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_count = hunk.iter().filter(|(op, _)| *op != Op::Delete).count();
+        out.push_str(&format!("@@ -{} +{} @@\n", range(old_line, old_count), range(new_line, new_count)));
+        for (op, line) in hunk {
+            let prefix = match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            };
+            out.push(prefix);
+            out.push_str(line);
+            out.push('\n');
+        }
+        old_line += old_count;
+        new_line += new_count;
+        next = end;
+    }
+    out
+}
+
+/// Colours a unified diff with ANSI escapes the way git does, with removed lines red, added lines green, and hunk
+/// headers cyan.
+pub(crate) fn colourize(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let code = match line.as_bytes().first() {
+                _ if line.starts_with("--- ") || line.starts_with("+++ ") => "1",
+                Some(b'@') => "36",
+                Some(b'-') => "31",
+                Some(b'+') => "32",
+                _ => return format!("{}\n", line),
+            };
+            format!("\x1b[{}m{}\x1b[0m\n", code, line)
+        })
+        .collect()
+}
+
+/// Formats a hunk range the way diff does, where an empty range refers to the line before it.
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Returns the shortest edit script turning the old lines into the new ones.
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut ops: Vec<(Op, &str)> = old[..prefix].iter().map(|l| (Op::Equal, *l)).collect();
+    if a.len() * b.len() > MAX_CELLS {
+        ops.extend(a.iter().map(|l| (Op::Delete, *l)));
+        ops.extend(b.iter().map(|l| (Op::Insert, *l)));
+    } else {
+        // lengths of the longest common subsequences of every pair of suffixes
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                ops.push((Op::Equal, a[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                ops.push((Op::Delete, a[i]));
+                i += 1;
+            } else {
+                ops.push((Op::Insert, b[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|l| (Op::Equal, *l)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+        let new = "a\nb\nC\nd\ne\nf\ng\nh\ni\nj\nk";
+        assert_eq!(
+            unified("old", "new", old, new, 1),
+            "--- old\n+++ new\n@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n@@ -10 +10,2 @@\n j\n+k\n"
+        );
+        assert_eq!(unified("old", "new", "a\nb", "b", 0), "--- old\n+++ new\n@@ -1 +0,0 @@\n-a\n");
+        assert_eq!(unified("old", "new", old, old, 3), "");
+        assert_eq!(
+            colourize("--- old\n+++ new\n@@ -1 +1 @@\n a\n-b\n+c\n"),
+            "\x1b[1m--- old\x1b[0m\n\x1b[1m+++ new\x1b[0m\n\x1b[36m@@ -1 +1 @@\x1b[0m\n a\n\x1b[31m-b\x1b[0m\n\x1b[32m+c\x1b[0m\n"
+        );
+    }
+}
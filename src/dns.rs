@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The commands that make macOS drop cached lookups, including ones read from the hosts file. Apps that are already
+/// running often keep resolving the old addresses until both have run.
+const MACOS_FLUSH_COMMANDS: &[&[&str]] = &[&["dscacheutil", "-flushcache"], &["killall", "-HUP", "mDNSResponder"]];
+
+/// The command that drops the cache of systemd-resolved.
+const RESOLVED_FLUSH_COMMAND: &[&str] = &["resolvectl", "flush-caches"];
+
+/// The command that drops the cache of the Windows DNS client service, which browsers and .NET apps resolve through.
+const WINDOWS_FLUSH_COMMAND: &[&str] = &["ipconfig", "/flushdns"];
+
+/// Flushes the DNS cache after the hosts file was written, on macOS, Windows, or Linux with systemd-resolved. Failures
+/// only print a warning since the write itself succeeded, and other machines have nothing to flush.
+pub(crate) fn flush_cache() {
+    let Some(commands) = flush_commands(std::env::consts::OS, Path::new("/")) else {
+        eprintln!("--flush-cache only flushes the DNS cache on macOS, Windows, or with systemd-resolved, skipping");
+        return;
+    };
+    for command in commands {
+        // ipconfig prints a banner and a confirmation that aren't useful here
+        match Command::new(command[0]).args(&command[1..]).stdout(Stdio::null()).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("warning: `{}` failed with {}", command.join(" "), status),
+            Err(e) => eprintln!("warning: failed to run `{}`: {}", command.join(" "), e),
+        }
+    }
+    eprintln!("flushed the DNS cache");
+}
+
+/// Returns the commands that flush the DNS cache on the given operating system with the given root directory, or
+/// None if it has no cache to flush.
+fn flush_commands(os: &str, root: &Path) -> Option<&'static [&'static [&'static str]]> {
+    match os {
+        "macos" => Some(MACOS_FLUSH_COMMANDS),
+        "windows" => Some(&[WINDOWS_FLUSH_COMMAND]),
+        "linux" if resolved_active(root) => Some(&[RESOLVED_FLUSH_COMMAND]),
+        _ => None,
+    }
+}
+
+/// Returns true if systemd-resolved is running on the machine with the given root directory.
+pub(crate) fn resolved_active(root: &Path) -> bool {
+    root.join("run/systemd/resolve").is_dir()
+}
+
+/// Returns a warning for each part of the resolver configuration of a Linux machine that would stop names in the hosts
+/// file from resolving, checking the hosts line of /etc/nsswitch.conf and, when systemd-resolved is running, whether
+/// it reads the hosts file itself.
+pub(crate) fn resolver_warnings(root: &Path) -> Vec<String> {
+    let nsswitch = std::fs::read_to_string(root.join("etc/nsswitch.conf")).unwrap_or_default();
+    let mut resolved_conf = std::fs::read_to_string(root.join("etc/systemd/resolved.conf")).unwrap_or_default();
+    // drop-ins are applied in name order and override the main file
+    let mut drop_ins = std::fs::read_dir(root.join("etc/systemd/resolved.conf.d"))
+        .map(|d| {
+            d.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|x| x == "conf"))
+                .collect()
+        })
+        .unwrap_or_else(|_| Vec::new());
+    drop_ins.sort();
+    for path in drop_ins {
+        resolved_conf.push('\n');
+        resolved_conf.push_str(&std::fs::read_to_string(path).unwrap_or_default());
+    }
+    nsswitch_warnings(&nsswitch, resolved_active(root).then_some(&*resolved_conf))
+}
+
+/// Checks the order of the sources on the hosts line of nsswitch.conf, given the configuration of systemd-resolved if
+/// it is running. Names are only looked up in the hosts file once the sources before files have given up.
+fn nsswitch_warnings(nsswitch: &str, resolved_conf: Option<&str>) -> Vec<String> {
+    let Some(line) = nsswitch
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .find_map(|l| l.strip_prefix("hosts:"))
+    else {
+        return Vec::new();
+    };
+    let reads_hosts = resolved_conf.is_some_and(read_etc_hosts);
+    let sources = line.split_whitespace().filter(|s| !s.starts_with('[')).collect::<Vec<&str>>();
+    let files = sources.iter().position(|s| *s == "files");
+    let mut warnings = Vec::new();
+    for source in &sources[..files.unwrap_or(sources.len())] {
+        match *source {
+            "resolve" if resolved_conf.is_some() && !reads_hosts => warnings.push(
+                "warning: systemd-resolved answers before /etc/hosts in the hosts line of /etc/nsswitch.conf, but is configured \
+                 with ReadEtcHosts=no, so entries added by eha are ignored; move files before resolve or enable ReadEtcHosts"
+                    .to_string(),
+            ),
+            "dns" => warnings.push(
+                "warning: dns comes before files in the hosts line of /etc/nsswitch.conf, so names that also exist in DNS \
+                 resolve to their DNS address instead of the one added by eha"
+                    .to_string(),
+            ),
+            s if s.starts_with("mdns") => warnings.push(format!(
+                "warning: {} comes before files in the hosts line of /etc/nsswitch.conf, so .local names added by eha may \
+                 be looked up with mDNS instead",
+                s
+            )),
+            _ => {}
+        }
+    }
+    if files.is_none() && !(sources.contains(&"resolve") && reads_hosts) {
+        warnings.push(
+            "warning: the hosts line of /etc/nsswitch.conf doesn't include files, so nothing reads the entries added by eha".to_string(),
+        );
+    }
+    warnings
+}
+
+/// Returns whether systemd-resolved reads the hosts file, which it does unless the last ReadEtcHosts setting in the
+/// Resolve section turns it off.
+fn read_etc_hosts(conf: &str) -> bool {
+    let mut section = "";
+    let mut enabled = true;
+    for line in conf.lines().map(str::trim) {
+        if line.starts_with('[') {
+            section = line;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| section == "[Resolve]") {
+            if key.trim() == "ReadEtcHosts" {
+                enabled = !matches!(value.trim(), "no" | "false" | "0" | "off");
+            }
+        }
+    }
+    enabled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_nsswitch_warnings() {
+        let ubuntu = "passwd: files systemd\nhosts:          files mdns4_minimal [NOTFOUND=return] dns myhostname\n";
+        assert!(nsswitch_warnings(ubuntu, Some("")).is_empty());
+        assert!(nsswitch_warnings("", None).is_empty());
+
+        let resolve_first = "hosts: mymachines resolve [!UNAVAIL=return] files myhostname dns";
+        assert!(nsswitch_warnings(resolve_first, Some("[Resolve]\nDNS=1.1.1.1")).is_empty());
+        let ignored = nsswitch_warnings(resolve_first, Some("[Resolve]\nReadEtcHosts=no"));
+        assert_eq!(ignored.len(), 1);
+        assert!(ignored[0].contains("ReadEtcHosts=no"));
+        assert!(nsswitch_warnings(resolve_first, None).is_empty());
+
+        let dns_first = nsswitch_warnings("hosts: mdns4_minimal [NOTFOUND=return] dns files", None);
+        assert_eq!(dns_first.len(), 2);
+        assert!(dns_first[0].starts_with("warning: mdns4_minimal comes before files"));
+
+        assert_eq!(nsswitch_warnings("hosts: dns", None).len(), 2);
+        assert!(nsswitch_warnings("hosts: resolve", Some("")).is_empty());
+        assert_eq!(nsswitch_warnings("hosts: resolve", Some("[Resolve]\nReadEtcHosts=no")).len(), 2);
+    }
+
+    #[test]
+    fn test_flush_commands() -> Result<(), Error> {
+        let root = tempdir()?;
+        assert_eq!(
+            flush_commands("macos", root.path()),
+            Some(&[&["dscacheutil", "-flushcache"][..], &["killall", "-HUP", "mDNSResponder"]][..])
+        );
+        assert_eq!(flush_commands("windows", root.path()), Some(&[&["ipconfig", "/flushdns"][..]][..]));
+        assert_eq!(flush_commands("linux", root.path()), None);
+        std::fs::create_dir_all(root.path().join("run/systemd/resolve"))?;
+        assert_eq!(
+            flush_commands("linux", root.path()),
+            Some(&[&["resolvectl", "flush-caches"][..]][..])
+        );
+        assert_eq!(flush_commands("freebsd", root.path()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolver_warnings() -> Result<(), Error> {
+        let root = tempdir()?;
+        std::fs::create_dir_all(root.path().join("etc/systemd/resolved.conf.d"))?;
+        std::fs::write(root.path().join("etc/nsswitch.conf"), "hosts: resolve files\n")?;
+        std::fs::write(
+            root.path().join("etc/systemd/resolved.conf.d/10-no-hosts.conf"),
+            "[Resolve]\nReadEtcHosts=no\n",
+        )?;
+        // without systemd-resolved running, files is still consulted when resolve is unavailable
+        assert!(resolver_warnings(root.path()).is_empty());
+
+        std::fs::create_dir_all(root.path().join("run/systemd/resolve"))?;
+        assert_eq!(resolver_warnings(root.path()).len(), 1);
+        std::fs::write(
+            root.path().join("etc/systemd/resolved.conf.d/20-hosts.conf"),
+            "[Resolve]\nReadEtcHosts=yes\n",
+        )?;
+        assert!(resolver_warnings(root.path()).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::Entry::Supported;
+use crate::{parse_content, read_content};
+use anyhow::{Context, Error};
+use jiff::{SignedDuration, Timestamp};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// A change to the hosts file, written as one line of json.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Event {
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<Timestamp>,
+    pub at: Timestamp,
+}
+
+/// The state of the entries added by eha as of one read of the hosts file, keyed by name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    content: Vec<u8>,
+    entries: BTreeMap<String, (Option<Timestamp>, bool)>,
+}
+
+impl Snapshot {
+    pub fn read(path: &str) -> Result<Self, Error> {
+        let content = read_content(path)?;
+        let entries = parse_content(&content)
+            .into_iter()
+            .filter_map(|e| match e {
+                Supported { name, meta, disabled, .. } => Some((name, (meta.expiry, disabled))),
+                _ => None,
+            })
+            .collect();
+        Ok(Snapshot { content, entries })
+    }
+
+    /// Returns the events that explain how this snapshot, taken at the previous time, became the next one taken now.
+    /// Entries that pass their expiry while still in the file produce an expired event. A change to the file that
+    /// doesn't touch any entry added by eha is reported as externally-modified.
+    pub fn diff(&self, next: &Snapshot, previous: Timestamp, now: Timestamp) -> Vec<Event> {
+        let event = |event, name: &str, expiry| Event {
+            event,
+            name: Some(name.to_string()),
+            expiry,
+            at: now,
+        };
+        let mut events = Vec::new();
+        for (name, (expiry, disabled)) in &next.entries {
+            match self.entries.get(name) {
+                None => events.push(event("added", name, *expiry)),
+                Some(old) if old != &(*expiry, *disabled) => events.push(event("changed", name, *expiry)),
+                Some(_) => {}
+            }
+            if expiry.is_some_and(|e| e > previous && e <= now) {
+                events.push(event("expired", name, *expiry));
+            }
+        }
+        for (name, (expiry, _)) in &self.entries {
+            if !next.entries.contains_key(name) {
+                events.push(event("removed", name, *expiry));
+            }
+        }
+        if events.is_empty() && self.content != next.content {
+            events.push(Event {
+                event: "externally-modified",
+                name: None,
+                expiry: None,
+                at: now,
+            });
+        }
+        events
+    }
+}
+
+/// Writes an added event for every entry currently in the hosts file, then when following, polls the file at the
+/// given interval and writes events for each change until an error occurs.
+pub fn watch(path: &str, follow: bool, interval: SignedDuration, out: &mut impl Write) -> Result<(), Error> {
+    let mut previous = Timestamp::now();
+    let mut snapshot = Snapshot::read(path)?;
+    write_events(out, &Snapshot::default().diff(&snapshot, previous, previous))?;
+    if !follow {
+        return Ok(());
+    }
+    let interval = interval.unsigned_abs();
+    loop {
+        std::thread::sleep(interval);
+        let now = Timestamp::now();
+        let next = Snapshot::read(path)?;
+        write_events(out, &snapshot.diff(&next, previous, now))?;
+        (snapshot, previous) = (next, now);
+    }
+}
+
+fn write_events(out: &mut impl Write, events: &[Event]) -> Result<(), Error> {
+    for e in events {
+        serde_json::to_writer(&mut *out, e).context("failed to write event")?;
+        out.write_all(b"\n").context("failed to write event")?;
+    }
+    out.flush().context("failed to write event")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_diff_snapshots() -> Result<(), Error> {
+        let mut f = NamedTempFile::new()?;
+        f.write_all(
+            br##"127.0.0.1	a.local	# eha {"expiry":"2029-01-01T00:00:30Z","comment":null}
+127.0.0.1	b.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let path = f.path().to_string_lossy().to_string();
+        let before = Snapshot::read(&path)?;
+        std::fs::write(
+            f.path(),
+            r##"127.0.0.1   localhost
+127.0.0.1	a.local	# eha {"expiry":"2029-01-01T00:00:30Z","comment":null}
+# 127.0.0.1	c.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let after = Snapshot::read(&path)?;
+        let (previous, now) = ("2029-01-01T00:00:00Z".parse()?, "2029-01-01T00:01:00Z".parse()?);
+        let events = before.diff(&after, previous, now);
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.event, e.name.as_deref().unwrap_or_default()))
+                .collect::<Vec<_>>(),
+            vec![("expired", "a.local"), ("added", "c.local"), ("removed", "b.local")]
+        );
+        assert_eq!(
+            serde_json::to_string(&events[0])?,
+            r#"{"event":"expired","name":"a.local","expiry":"2029-01-01T00:00:30Z","at":"2029-01-01T00:01:00Z"}"#
+        );
+
+        std::fs::write(f.path(), [after.content.as_slice(), b"\n10.0.0.1    other.name"].concat())?;
+        let edited = Snapshot::read(&path)?;
+        assert_eq!(
+            after.diff(&edited, now, now).iter().map(|e| e.event).collect::<Vec<_>>(),
+            vec!["externally-modified"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::policy::Policy;
+use crate::Entry::{Other, Supported};
+use crate::{validate_name, Entry};
+use anyhow::{anyhow, Error};
+use jiff::Timestamp;
+
+/// Describes how eha parses one line of the hosts file, picked by its 1-based line number or by the name on it, and
+/// what eha would do with it. Lines with invalid eha metadata are explained too, since finding out why a line is
+/// invalid is one of the reasons to use it.
+pub fn explain(content: &str, line: Option<usize>, name: Option<&str>, now: Timestamp, policy: &Policy) -> Result<String, Error> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let (number, raw) = match (line, name) {
+        (Some(n), _) => lines
+            .get(n.wrapping_sub(1))
+            .map(|l| (n, *l))
+            .ok_or_else(|| anyhow!("line {} is not between 1 and {}", n, lines.len()))?,
+        (None, Some(name)) => lines
+            .iter()
+            .enumerate()
+            .find(|(_, l)| match Entry::try_from(**l) {
+                Ok(e @ Supported { .. }) => e.has_name(name),
+                Ok(Other(l)) => l.has_name(name),
+                Err(_) => l.split_whitespace().any(|n| n == name),
+            })
+            .map(|(i, l)| (i + 1, *l))
+            .ok_or_else(|| anyhow!("no line mentions {}", name))?,
+        (None, None) => return Err(anyhow!("either a line number or a name is required")),
+    };
+
+    let mut out = vec![format!("line {}: {}", number, raw)];
+    match Entry::try_from(raw) {
+        Err(e) => {
+            out.push("managed: yes, but the eha metadata is invalid".to_string());
+            out.push(format!("problem: {:#}", e));
+            out.push("eha leaves the line as it is and treats it as unmanaged until it is fixed or removed".to_string());
+        }
+        Ok(Other(_)) => out.push("managed: no, eha never modifies this line".to_string()),
+        Ok(Supported { name, meta, disabled, .. }) => {
+            out.push(format!("managed: yes{}", if disabled { ", disabled" } else { "" }));
+            out.push(format!("name: {}", name));
+            if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&meta) {
+                for (k, v) in fields {
+                    out.push(format!("{}: {}", k, v.as_str().map_or(v.to_string(), str::to_string)));
+                }
+            }
+            match meta.expiry {
+                Some(e) if e <= now => out.push(format!("remove-expired: removes it, it expired at {}", e)),
+                Some(e) => out.push(format!("remove-expired: keeps it until {}", e)),
+                None => out.push("remove-expired: keeps it, it has no expiry".to_string()),
+            }
+            match validate_name(&name, policy) {
+                Ok(()) => out.push("validation: ok".to_string()),
+                Err(e) => out.push(format!("validation: {}", e)),
+            }
+        }
+    }
+    Ok(out.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() -> Result<(), Error> {
+        let content = r##"127.0.0.1   localhost
+# 127.0.0.1	foo.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":"set by hand"}
+127.0.0.1	bad.local	# eha {"expiry":"soon"}"##;
+        let now = "2030-01-01T00:00:00Z".parse()?;
+        let policy = Policy::default();
+        assert_eq!(
+            explain(content, None, Some("foo.local"), now, &policy)?,
+            r##"line 2: # 127.0.0.1	foo.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":"set by hand"}
+managed: yes, disabled
+name: foo.local
+comment: set by hand
+expiry: 2029-01-01T00:00:00Z
+remove-expired: removes it, it expired at 2029-01-01T00:00:00Z
+validation: ok"##
+        );
+        assert_eq!(
+            explain(content, Some(1), None, now, &policy)?,
+            "line 1: 127.0.0.1   localhost\nmanaged: no, eha never modifies this line"
+        );
+        assert!(explain(content, None, Some("bad.local"), now, &policy)?.contains("the eha metadata is invalid"));
+        assert!(explain(content, Some(4), None, now, &policy).is_err());
+        Ok(())
+    }
+}
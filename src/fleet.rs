@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Context, Error};
+use eha::manifest::Manifest;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The outcome of applying a manifest to one remote host.
+struct HostResult {
+    host: String,
+    outcome: Result<String, String>,
+}
+
+/// Applies a manifest to every host listed in the hosts file by running eha on each of them over ssh, with the
+/// manifest on stdin. Returns a report with one line per host, or an error containing the report if any host failed.
+pub(crate) fn apply(
+    manifest_path: &str,
+    hosts_path: &str,
+    ssh_command: &str,
+    remote_command: &str,
+    check: bool,
+    prune: bool,
+) -> Result<String, Error> {
+    let manifest = std::fs::read_to_string(manifest_path).with_context(|| format!("failed to read manifest {}", manifest_path))?;
+    // every host gets the manifest as json, which any version of eha on it can read
+    let manifest = serde_json::to_string(&Manifest::parse(&manifest)?)?;
+    let hosts = std::fs::read_to_string(hosts_path).with_context(|| format!("failed to read hosts list {}", hosts_path))?;
+    let hosts: Vec<&str> = hosts
+        .lines()
+        .map(str::trim)
+        .filter(|h| !h.is_empty() && !h.starts_with('#'))
+        .collect();
+    if hosts.is_empty() {
+        return Err(anyhow!("no hosts listed in {}", hosts_path));
+    }
+    // ssh would take a destination like -oProxyCommand=... as an option and run it locally
+    if let Some(host) = hosts.iter().find(|h| h.starts_with('-')) {
+        return Err(anyhow!(
+            "invalid host {} in {}, ssh destinations can't start with -",
+            host,
+            hosts_path
+        ));
+    }
+
+    let mut remote = remote_command.to_string();
+    if check {
+        remote.push_str(" --check");
+    }
+    if prune {
+        remote.push_str(" --prune");
+    }
+    remote.push_str(" -");
+
+    let results: Vec<HostResult> = std::thread::scope(|s| {
+        let handles: Vec<_> = hosts
+            .iter()
+            .map(|host| {
+                let (manifest, remote) = (&manifest, &remote);
+                s.spawn(move || HostResult {
+                    host: host.to_string(),
+                    outcome: run_on_host(ssh_command, host, remote, manifest),
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .zip(hosts.iter())
+            .map(|(h, host)| {
+                h.join().unwrap_or_else(|_| HostResult {
+                    host: host.to_string(),
+                    outcome: Err("panicked".to_string()),
+                })
+            })
+            .collect()
+    });
+
+    let failed = results.iter().filter(|r| r.outcome.is_err()).count();
+    let report = results
+        .iter()
+        .map(|r| match &r.outcome {
+            Ok(out) => format!("{}\t{}", r.host, out),
+            Err(err) => format!("{}\tfailed: {}", r.host, err),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    if failed > 0 {
+        return Err(anyhow!(report).context(format!("{} of {} hosts failed", failed, results.len())));
+    }
+    Ok(report)
+}
+
+/// Runs the remote command on the host over ssh, writing the manifest to its stdin. Returns the trimmed stdout on
+/// success or the last line of stderr on failure.
+fn run_on_host(ssh_command: &str, host: &str, remote_command: &str, manifest: &str) -> Result<String, String> {
+    let mut parts = ssh_command.split_whitespace();
+    let program = parts.next().ok_or("empty ssh command")?;
+    let mut child = Command::new(program)
+        .args(parts)
+        .arg(host)
+        .arg(remote_command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run {}: {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(manifest.as_bytes())
+            .map_err(|e| format!("failed to send manifest: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to wait for {}: {}", program, e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(stderr.lines().last().unwrap_or("no error output").to_string())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_fleet_apply() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let manifest = dir.path().join("manifest.yaml");
+        std::fs::write(&manifest, "entries:\n  - name: foo.local\n")?;
+        let hosts = dir.path().join("hosts.txt");
+        std::fs::write(&hosts, "# lab machines\ngood\n\nbad\n")?;
+        // a fake ssh which reads the manifest and fails for the bad host
+        let ssh = dir.path().join("ssh");
+        std::fs::write(
+            &ssh,
+            "#!/bin/sh\ngrep -q foo.local || exit 3\n[ \"$1\" = bad ] && echo 'permission denied' >&2 && exit 1\necho \"changed=true $2\"\n",
+        )?;
+        std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755))?;
+
+        let err = apply(
+            &manifest.to_string_lossy(),
+            &hosts.to_string_lossy(),
+            &ssh.to_string_lossy(),
+            "eha apply",
+            true,
+            false,
+        )
+        .expect_err("bad host should fail");
+        assert_eq!(err.to_string(), "1 of 2 hosts failed");
+        assert_eq!(
+            err.root_cause().to_string(),
+            "good\tchanged=true eha apply --check -\nbad\tfailed: permission denied"
+        );
+
+        // a destination that ssh would read as an option is refused before connecting anywhere
+        std::fs::write(&hosts, "good\n-oProxyCommand=touch pwned\n")?;
+        let err = apply(
+            &manifest.to_string_lossy(),
+            &hosts.to_string_lossy(),
+            &ssh.to_string_lossy(),
+            "eha apply",
+            false,
+            false,
+        )
+        .expect_err("an option should be refused as a host");
+        assert!(err.to_string().starts_with("invalid host -oProxyCommand=touch pwned in "));
+        Ok(())
+    }
+}
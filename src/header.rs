@@ -0,0 +1,109 @@
+use anyhow::{Context, Error};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind};
+
+/// The prefix of the optional header comment holding defaults for the hosts file it is in.
+const HEADER_PREFIX: &str = "# eha-config ";
+
+/// The prefix of a comment an administrator can add anywhere in the hosts file to stop eha from changing it.
+const LOCK_PREFIX: &str = "# eha-locked";
+
+/// Defaults read from a `# eha-config {json}` comment in the hosts file, so that the file describes how eha should
+/// treat it wherever it is copied.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Names must end in one of these suffixes, unless the policy sets its own.
+    pub allowed_suffixes: Option<Vec<String>>,
+    /// The expiry of added entries when --expire isn't given.
+    pub default_expire_minutes: Option<usize>,
+    /// Where entries are added when there are none yet.
+    #[serde(default)]
+    pub managed_block: ManagedBlock,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ManagedBlock {
+    /// Entries are added directly after the header comment.
+    AfterHeader,
+    /// Entries are added at the end of the file.
+    #[default]
+    End,
+}
+
+impl FileConfig {
+    /// Reads the header from the leading comment lines of the hosts file. A missing file or header gives the default
+    /// config.
+    pub fn read(path: &str) -> Result<Self, Error> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(FileConfig::default()),
+            Err(e) => return Err(Error::new(e).context(format!("failed to read input file {}", path))),
+        };
+        for (i, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let line = line.with_context(|| format!("failed to read input file {}", path))?;
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if let Some(raw) = line.strip_prefix(HEADER_PREFIX) {
+                return serde_json::from_str(raw).with_context(|| format!("failed to parse eha-config on line {} of {}", i + 1, path));
+            }
+            if !line.is_empty() && !line.starts_with('#') {
+                break;
+            }
+        }
+        Ok(FileConfig::default())
+    }
+
+    /// Finds the lock comment in the hosts file content, returning its line number from 1 and the text after the marker,
+    /// which usually says who locked the file and why.
+    pub fn find_lock(content: &str) -> Option<(usize, &str)> {
+        content.lines().enumerate().find_map(|(i, line)| {
+            let rest = line.trim().strip_prefix(LOCK_PREFIX)?;
+            // the marker must be a whole word, so that other eha-locked-something comments don't count
+            (rest.is_empty() || rest.starts_with([' ', '\t', ':'])).then(|| (i + 1, rest.trim_start_matches(':').trim()))
+        })
+    }
+
+    /// Returns true if the line is the header comment.
+    pub fn is_header(line: &str) -> bool {
+        line.trim().starts_with(HEADER_PREFIX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_read_header() -> Result<(), Error> {
+        let mut f = NamedTempFile::new()?;
+        f.write_all(
+            br##"# static entries
+# eha-config {"allowed_suffixes":[".test"],"default_expire_minutes":60,"managed_block":"after_header"}
+127.0.0.1   localhost"##,
+        )?;
+        let config = FileConfig::read(&f.path().to_string_lossy())?;
+        assert_eq!(config.allowed_suffixes, Some(vec![".test".to_string()]));
+        assert_eq!(config.default_expire_minutes, Some(60));
+        assert_eq!(config.managed_block, ManagedBlock::AfterHeader);
+
+        // the header only counts in the leading comments
+        let mut f = NamedTempFile::new()?;
+        f.write_all(b"127.0.0.1   localhost\n# eha-config {\"default_expire_minutes\":60}")?;
+        assert_eq!(FileConfig::read(&f.path().to_string_lossy())?.default_expire_minutes, None);
+
+        let mut f = NamedTempFile::new()?;
+        f.write_all(b"# eha-config {\"unknown\":1}")?;
+        assert!(FileConfig::read(&f.path().to_string_lossy()).is_err());
+
+        let content = "127.0.0.1   localhost\n  # eha-locked: alice, freezing during the migration\n";
+        assert_eq!(FileConfig::find_lock(content), Some((2, "alice, freezing during the migration")));
+        assert_eq!(FileConfig::find_lock("# eha-locked"), Some((1, "")));
+        assert_eq!(FileConfig::find_lock("# eha-lockedown\n127.0.0.1 localhost"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+/// Returns the name and uid of the user running eha, looking through sudo to the user that invoked it since adding
+/// entries usually needs root. Either may be unknown, such as the uid on platforms without one.
+pub fn invoking_user(env: impl Fn(&str) -> Option<String>) -> (Option<String>, Option<u32>) {
+    let non_empty = |k: &str| env(k).filter(|v| !v.is_empty());
+    if let Some(name) = non_empty("SUDO_USER") {
+        return (Some(name), non_empty("SUDO_UID").and_then(|u| u.parse().ok()));
+    }
+    (non_empty("USER").or_else(|| non_empty("USERNAME")), current_uid())
+}
+
+#[cfg(target_os = "linux")]
+fn current_uid() -> Option<u32> {
+    // SAFETY: getuid has no preconditions and can't fail.
+    Some(unsafe { libc::getuid() })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_uid() -> Option<u32> {
+    None
+}
+
+/// Returns the hostname of this machine, if it can be found.
+#[cfg(target_os = "linux")]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most the given length into the buffer.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).to_string()).filter(|h| !h.is_empty())
+}
+
+/// Returns the hostname of this machine, if it can be found.
+#[cfg(not(target_os = "linux"))]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .filter(|h| !h.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoking_user() {
+        let sudo = |k: &str| match k {
+            "SUDO_USER" => Some("alice".to_string()),
+            "SUDO_UID" => Some("1000".to_string()),
+            "USER" => Some("root".to_string()),
+            _ => None,
+        };
+        assert_eq!(invoking_user(sudo), (Some("alice".to_string()), Some(1000)));
+
+        let plain = |k: &str| (k == "USERNAME").then(|| "bob".to_string());
+        assert_eq!(invoking_user(plain).0.as_deref(), Some("bob"));
+        assert_eq!(invoking_user(|_| None).0, None);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::Entry::Supported;
+use crate::{content_hash, parse_content, parse_lines, read_content};
+use anyhow::{Context, Error};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File, Metadata};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// A sidecar index of the byte offsets of the lines of each entry added by eha, so that an entry can be found in a
+/// very large hosts file without parsing all of it. While the file has the same length, modification time and inode
+/// as when the index was last checked, only the lines at the offsets are read. Otherwise the index is only trusted
+/// while the file has the same content hash, and the lines at the offsets must still hold the entry, or the index is
+/// rebuilt.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Index {
+    path: String,
+    #[serde(default)]
+    stamp: Option<Stamp>,
+    hash: u64,
+    offsets: BTreeMap<String, Vec<u64>>,
+}
+
+/// What the metadata of the hosts file said when the index was last checked against its content.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+struct Stamp {
+    len: u64,
+    modified: Option<Timestamp>,
+    inode: u64,
+    checked_at: Timestamp,
+}
+
+impl Stamp {
+    fn of(metadata: &Metadata, now: Timestamp) -> Self {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Stamp {
+            len: metadata.len(),
+            modified: metadata.modified().ok().and_then(|m| Timestamp::try_from(m).ok()),
+            inode,
+            checked_at: now,
+        }
+    }
+
+    /// Returns whether the file can't have changed since this stamp was taken. A file modified shortly before it was
+    /// checked could have been modified again within the resolution of its modification time, so that one is only
+    /// trusted once its content has been checked again later.
+    fn matches(&self, current: &Stamp) -> bool {
+        (self.len, self.modified, self.inode) == (current.len, current.modified, current.inode)
+            && self.modified.is_some_and(|m| m + SignedDuration::from_secs(2) < self.checked_at)
+    }
+}
+
+/// Returns the path of the index of the hosts file, which each hosts file has one of.
+fn index_path(state_dir: &Path, path: &str) -> PathBuf {
+    state_dir.join(format!("index-{:016x}.json", content_hash(path.as_bytes())))
+}
+
+/// Finds the lines of the entry with the given name or alias in the hosts file using the index in the state directory,
+/// building or rebuilding the index when needed. A dual stack name has more than one line, and a missing name has none.
+pub fn lookup(path: &str, state_dir: &Path, name: &str) -> Result<Vec<String>, Error> {
+    let index_path = index_path(state_dir, path);
+    let now = Timestamp::now();
+    let metadata = std::fs::metadata(path).with_context(|| format!("failed to read input file {}", path))?;
+    let stamp = Stamp::of(&metadata, now);
+    let current = File::open(&index_path)
+        .ok()
+        .and_then(|f| serde_json::from_reader::<_, Index>(f).ok())
+        .filter(|i| i.path == path);
+    if let Some(index) = current.as_ref().filter(|i| i.stamp.is_some_and(|s| s.matches(&stamp))) {
+        let mut file = File::open(path).with_context(|| format!("failed to read input file {}", path))?;
+        if let Some(lines) = index.lines(&mut file, name) {
+            return Ok(lines);
+        }
+    }
+
+    let content = read_content(path)?;
+    let hash = content_hash(&content);
+    if let Some(mut index) = current.filter(|i| i.hash == hash) {
+        if let Some(lines) = index.lines(&mut Cursor::new(&content), name) {
+            // the content is the same, so the next lookup can go by the metadata again
+            index.stamp = Some(stamp);
+            index.store(state_dir, &index_path)?;
+            return Ok(lines);
+        }
+    }
+
+    eprintln!("rebuilding index of {}", path);
+    let mut offsets: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+    let mut found = Vec::new();
+    for (span, e) in parse_lines(&content) {
+        if let Supported {
+            name: n,
+            meta,
+            raw: Some(line),
+            ..
+        } = e
+        {
+            for n in meta.aliases.iter().chain(std::iter::once(&n)) {
+                offsets.entry(n.clone()).or_default().push(span.start as u64);
+            }
+            if n == name || meta.aliases.iter().any(|a| a == name) {
+                found.push(line.to_string());
+            }
+        }
+    }
+    let index = Index {
+        path: path.to_string(),
+        stamp: Some(stamp),
+        hash,
+        offsets,
+    };
+    index.store(state_dir, &index_path)?;
+    Ok(found)
+}
+
+impl Index {
+    /// Reads the lines of the entry with the given name at its offsets, or returns None if any of them no longer holds
+    /// the entry.
+    fn lines(&self, file: &mut (impl Read + Seek), name: &str) -> Option<Vec<String>> {
+        let offsets = self.offsets.get(name).map(Vec::as_slice).unwrap_or_default();
+        offsets.iter().map(|o| line_at(file, *o, name)).collect()
+    }
+
+    fn store(&self, state_dir: &Path, index_path: &Path) -> Result<(), Error> {
+        create_dir_all(state_dir).context("failed to create state directory")?;
+        let file = File::create(index_path).context("failed to create index file")?;
+        serde_json::to_writer(file, self).context("failed to write index file")
+    }
+}
+
+/// Returns the line at the given offset if it is still a line of the entry with the given name.
+fn line_at(file: &mut (impl Read + Seek), offset: u64, name: &str) -> Option<String> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut line = Vec::new();
+    BufReader::new(file).read_until(b'\n', &mut line).ok()?;
+    let line = line.strip_suffix(b"\n").unwrap_or(&line);
+    let line = std::str::from_utf8(line.strip_suffix(b"\r").unwrap_or(line)).ok()?;
+    parse_content(line.as_bytes())
+        .iter()
+        .any(|e| matches!(e, Supported { .. }) && e.has_name(name))
+        .then(|| line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[test]
+    fn test_lookup_with_index() -> Result<(), Error> {
+        let foo = r##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##;
+        let foo6 = r##"::1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"ip":"::1"}"##;
+        let bar = r##"# 127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##;
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        write!(f, "127.0.0.1   localhost\n{}\n{}\n{}\n", foo, bar, foo6)?;
+        let path = f.path().to_string_lossy().to_string();
+        assert!(lookup(&path, state.path(), "missing.local")?.is_empty());
+        let index_file = index_path(state.path(), &path);
+        let index: Index = serde_json::from_reader(File::open(&index_file)?)?;
+        assert_eq!(
+            index.offsets,
+            BTreeMap::from([("bar.local".to_string(), vec![97]), ("foo.local".to_string(), vec![22, 174])])
+        );
+
+        assert_eq!(lookup(&path, state.path(), "bar.local")?, vec![bar]);
+        assert_eq!(lookup(&path, state.path(), "foo.local")?, vec![foo, foo6]);
+
+        // an edit that keeps the length of the file still invalidates the index
+        let content = read_content(&path)?;
+        std::fs::write(f.path(), String::from_utf8(content)?.replace("foo.local", "baz.local"))?;
+        assert_eq!(lookup(&path, state.path(), "baz.local")?.len(), 2);
+        assert!(lookup(&path, state.path(), "foo.local")?.is_empty());
+
+        // once the metadata has been checked well after the last modification, an unchanged file isn't hashed, so an
+        // edit that keeps the length and modification time goes unnoticed outside the lines of the entry
+        let modified = std::fs::metadata(f.path())?.modified()?;
+        let mut index: Index = serde_json::from_reader(File::open(&index_file)?)?;
+        let stamp = index.stamp.as_mut().expect("the index should have a stamp");
+        stamp.checked_at = Timestamp::try_from(modified)? + SignedDuration::from_secs(3);
+        index.store(state.path(), &index_file)?;
+        let content = read_content(&path)?;
+        std::fs::write(f.path(), String::from_utf8(content)?.replace("localhost", "localhosx"))?;
+        File::options().write(true).open(f.path())?.set_modified(modified)?;
+        assert_eq!(lookup(&path, state.path(), "bar.local")?, vec![bar]);
+        let index: Index = serde_json::from_reader(File::open(&index_file)?)?;
+        assert_eq!(
+            index.stamp.map(|s| s.checked_at),
+            Some(Timestamp::try_from(modified)? + SignedDuration::from_secs(3))
+        );
+
+        // each hosts file has an index of its own
+        let mut other = NamedTempFile::new_in(state.path())?;
+        writeln!(other, "{}", bar)?;
+        let other_path = other.path().to_string_lossy().to_string();
+        assert_eq!(lookup(&other_path, state.path(), "bar.local")?, vec![bar]);
+        assert_ne!(index_path(state.path(), &other_path), index_file);
+        assert!(index_file.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Context, Error};
+use std::path::Path;
+use std::process::Command;
+
+/// The header written to hosts files that don't have one yet, keeping eha's entries together straight after it.
+pub(crate) const STARTER_HEADER: &str = r#"# eha-config {"default_expire_minutes":1440,"managed_block":"after_header"}"#;
+
+const SERVICE_NAME: &str = "eha-cleanup.service";
+const TIMER_NAME: &str = "eha-cleanup.timer";
+const LAUNCHD_LABEL: &str = "com.astromechza.eha-cleanup";
+
+/// An hourly timer running the cleanup service.
+const TIMER_UNIT: &str = "[Unit]
+Description=Remove expired eha hosts entries hourly
+
+[Timer]
+OnCalendar=hourly
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+";
+
+/// Returns where the cleanup timer is installed by default: the launchd daemons on macOS, and the systemd units
+/// elsewhere.
+pub(crate) fn default_unit_dir() -> String {
+    match cfg!(target_os = "macos") {
+        true => "/Library/LaunchDaemons".to_string(),
+        false => "/etc/systemd/system".to_string(),
+    }
+}
+
+/// Returns an error if there is no way to install the cleanup timer on this platform.
+pub(crate) fn check_timer_supported() -> Result<(), Error> {
+    match cfg!(any(target_os = "linux", target_os = "macos")) {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "--timer needs systemd or launchd, schedule `eha remove-expired` hourly with this platform's scheduler instead"
+        )),
+    }
+}
+
+/// Quotes a word for a systemd command line when it needs it, and escapes the specifiers and variables that systemd
+/// would otherwise expand.
+fn systemd_quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty() && !escaped.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c)) {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns a systemd service that removes expired entries from the hosts file.
+fn service_unit(exe: &str, input_file: &str) -> String {
+    let command = [exe, "--input-file", input_file, "remove-expired"].map(systemd_quote).join(" ");
+    format!(
+        "[Unit]\nDescription=Remove expired eha hosts entries\n\n[Service]\nType=oneshot\nExecStart={}\n",
+        command
+    )
+}
+
+/// Returns a launchd daemon that removes expired entries from the hosts file hourly.
+fn launchd_plist(exe: &str, input_file: &str) -> String {
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let arguments = [exe, "--input-file", input_file, "remove-expired"]
+        .map(|a| format!("        <string>{}</string>\n", escape(a)))
+        .concat();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>StartInterval</key>
+    <integer>3600</integer>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        LAUNCHD_LABEL, arguments
+    )
+}
+
+/// Installs and starts a job that removes expired entries hourly: a launchd daemon on macOS, or a systemd timer elsewhere.
+pub(crate) fn install_timer(unit_dir: &Path, input_file: &str) -> Result<(), Error> {
+    check_timer_supported()?;
+    let exe = std::env::current_exe().context("failed to find the eha executable")?;
+    let exe = exe.to_string_lossy();
+    if cfg!(target_os = "macos") {
+        let path = unit_dir.join(format!("{}.plist", LAUNCHD_LABEL));
+        write_file(&path, &launchd_plist(&exe, input_file))?;
+        return run(Command::new("launchctl").arg("load").arg("-w").arg(&path));
+    }
+    let service = service_unit(&exe, input_file);
+    for (name, content) in [(SERVICE_NAME, service.as_str()), (TIMER_NAME, TIMER_UNIT)] {
+        write_file(&unit_dir.join(name), content)?;
+    }
+    run(Command::new("systemctl").args(["enable", "--now", TIMER_NAME]))
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), Error> {
+    std::fs::write(path, content).with_context(|| format!("failed to write {}", path.to_string_lossy()))?;
+    eprintln!("wrote {}", path.to_string_lossy());
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<(), Error> {
+    let description = format!("{:?}", command);
+    let status = command.status().with_context(|| format!("failed to run {}", description))?;
+    if !status.success() {
+        return Err(anyhow!("{} failed with {}", description, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_unit() {
+        let service = service_unit("/usr/local/bin/eha", "/etc/hosts");
+        assert!(service.contains("ExecStart=/usr/local/bin/eha --input-file /etc/hosts remove-expired\n"));
+        let service = service_unit("/opt/my tools/eha", "/srv/my \"hosts\" 100%");
+        assert!(service.contains(r#"ExecStart="/opt/my tools/eha" --input-file "/srv/my \"hosts\" 100%%" remove-expired"#));
+    }
+
+    #[test]
+    fn test_launchd_plist() {
+        let plist = launchd_plist("/usr/local/bin/eha", "/etc/hosts & more");
+        assert!(plist.contains("<string>com.astromechza.eha-cleanup</string>"));
+        assert!(plist.contains(
+            "        <string>/usr/local/bin/eha</string>\n        <string>--input-file</string>\n        <string>/etc/hosts &amp; \
+             more</string>\n        <string>remove-expired</string>\n    </array>"
+        ));
+    }
+}
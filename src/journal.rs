@@ -0,0 +1,193 @@
+use anyhow::{Context, Error};
+use jiff::Timestamp;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// A write to the hosts file that has started but not yet finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Operation {
+    pub(crate) id: String,
+    pub(crate) command: String,
+    pub(crate) target: PathBuf,
+    /// The temp file that will be renamed over the target, if the write goes through one.
+    pub(crate) temp: Option<PathBuf>,
+    /// The content hash of the target before the write, used to tell whether it has changed since. Journals from
+    /// before it was recorded default to 0, so their writes are never finished.
+    #[serde(default)]
+    pub(crate) original_hash: u64,
+    pub(crate) started_at: Timestamp,
+    /// Whether the temp file has been fully written and synced, so that renaming it would finish the write.
+    #[serde(default)]
+    pub(crate) temp_ready: bool,
+}
+
+/// The journal is a json file in the state directory recording writes to one hosts file before they start. A write
+/// is removed from the journal once it finishes, so anything left in it was interrupted. Each target has a journal of
+/// its own, so that the lock on the target also covers its journal, and commands working on different hosts files
+/// never touch each other's writes.
+pub(crate) struct Journal {
+    path: PathBuf,
+    target: PathBuf,
+}
+
+impl Journal {
+    pub(crate) fn new(state_dir: &Path, target: &Path) -> Self {
+        Journal {
+            path: state_dir.join(format!(
+                "journal-{:016x}.json",
+                eha::content_hash(target.as_os_str().as_encoded_bytes())
+            )),
+            target: target.to_path_buf(),
+        }
+    }
+
+    fn load(&self) -> Result<Vec<Operation>, Error> {
+        match File::open(&self.path) {
+            Ok(f) => serde_json::from_reader(f).with_context(|| format!("failed to parse journal {}", self.path.to_string_lossy())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(Error::new(e).context(format!("failed to read journal {}", self.path.to_string_lossy()))),
+        }
+    }
+
+    fn store(&self, operations: &[Operation]) -> Result<(), Error> {
+        if operations.is_empty() {
+            return match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::new(e).context("failed to remove journal")),
+                _ => Ok(()),
+            };
+        }
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).context("failed to create state directory")?;
+        }
+        let mut file = File::create(&self.path).context("failed to create journal")?;
+        file.write_all(&serde_json::to_vec(operations)?)
+            .context("failed to write journal")?;
+        file.sync_all().context("failed to sync journal")
+    }
+
+    /// Records a write to the target that is about to start and returns its id.
+    pub(crate) fn begin(&self, command: String, temp: Option<PathBuf>, original_hash: u64) -> Result<String, Error> {
+        let id = format!("{:016x}", random::<u64>());
+        let mut operations = self.load()?;
+        operations.push(Operation {
+            id: id.clone(),
+            command,
+            target: self.target.clone(),
+            temp,
+            original_hash,
+            started_at: Timestamp::now(),
+            temp_ready: false,
+        });
+        self.store(&operations)?;
+        Ok(id)
+    }
+
+    /// Records that the temp file of a write is complete.
+    pub(crate) fn mark_ready(&self, id: &str) -> Result<(), Error> {
+        let mut operations = self.load()?;
+        operations.iter_mut().filter(|o| o.id == id).for_each(|o| o.temp_ready = true);
+        self.store(&operations)
+    }
+
+    /// Records that a write finished.
+    pub(crate) fn complete(&self, id: &str) -> Result<(), Error> {
+        let mut operations = self.load()?;
+        operations.retain(|o| o.id != id);
+        self.store(&operations)
+    }
+
+    /// Deals with every interrupted write to the target, returning a description of what was done for each. A write
+    /// whose temp file was complete, and whose target hasn't changed since, is finished if the resume callback agrees.
+    /// Otherwise any temp file is removed. The target must be locked, so that none of its writes are still running.
+    pub(crate) fn recover(&self, resume: impl Fn(&Operation) -> Result<bool, Error>) -> Result<Vec<String>, Error> {
+        let (operations, others): (Vec<Operation>, Vec<Operation>) = self.load()?.into_iter().partition(|op| op.target == self.target);
+        let mut messages = Vec::new();
+        for op in &operations {
+            let what = format!("`eha {}` started at {} was interrupted", op.command, op.started_at);
+            let target_unchanged = std::fs::read(&op.target).is_ok_and(|c| eha::content_hash(&c) == op.original_hash);
+            match &op.temp {
+                Some(temp) if temp.exists() => {
+                    if op.temp_ready && target_unchanged && resume(op)? {
+                        crate::move_into_place(temp, &op.target).context("failed to rename temp file to input file")?;
+                        messages.push(format!(
+                            "{}, finished it by moving {} to {}",
+                            what,
+                            temp.to_string_lossy(),
+                            op.target.to_string_lossy()
+                        ));
+                    } else {
+                        std::fs::remove_file(temp).with_context(|| format!("failed to remove temp file {}", temp.to_string_lossy()))?;
+                        messages.push(format!("{}, removed its temp file {}", what, temp.to_string_lossy()));
+                    }
+                }
+                Some(_) => messages.push(format!("{} before changing {}", what, op.target.to_string_lossy())),
+                None => messages.push(format!(
+                    "{} while writing {} in place, check it for a partial write",
+                    what,
+                    op.target.to_string_lossy()
+                )),
+            }
+        }
+        // anything else that ended up in the journal belongs to a different lock, so it is left alone
+        self.store(&others)?;
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[test]
+    fn test_recover_interrupted_writes() -> Result<(), Error> {
+        let state = tempdir()?;
+        let target = NamedTempFile::new_in(state.path())?;
+        let journal = Journal::new(state.path(), target.path());
+        let temp = NamedTempFile::new_in(state.path())?.into_temp_path().keep()?;
+        std::fs::write(&temp, "new content")?;
+
+        // a completed write leaves nothing behind
+        let empty = eha::content_hash(b"");
+        let id = journal.begin("add a.local".to_string(), None, empty)?;
+        journal.complete(&id)?;
+        assert!(!journal.path.exists());
+
+        // the writes of other hosts files are kept apart and aren't recovered
+        let other = Journal::new(state.path(), &state.path().join("other"));
+        other.begin("add z.local".to_string(), Some(temp.clone()), empty)?;
+        assert!(journal.recover(|_| Ok(true))?.is_empty());
+        assert!(temp.exists());
+        assert_eq!(other.load()?.len(), 1);
+        other.store(&[])?;
+
+        // an interrupted write with an unfinished temp file is cleaned up
+        journal.begin("add b.local".to_string(), Some(temp.clone()), empty)?;
+        let messages = journal.recover(|_| Ok(true))?;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("removed its temp file"));
+        assert!(!temp.exists());
+
+        // an interrupted write with a ready temp file can be finished
+        std::fs::write(&temp, "new content")?;
+        let id = journal.begin("add c.local".to_string(), Some(temp.clone()), empty)?;
+        journal.mark_ready(&id)?;
+        let messages = journal.recover(|_| Ok(true))?;
+        assert!(messages[0].contains("finished it"));
+        assert_eq!(std::fs::read_to_string(target.path())?, "new content");
+        assert!(journal.recover(|_| Ok(true))?.is_empty());
+
+        // but not once the target has changed since, even if its length is the same
+        let id = journal.begin("add d.local".to_string(), Some(temp.clone()), eha::content_hash(b"new content"))?;
+        std::fs::write(&temp, "newer content")?;
+        journal.mark_ready(&id)?;
+        std::fs::write(target.path(), "old content")?;
+        let messages = journal.recover(|_| Ok(true))?;
+        assert!(messages[0].contains("removed its temp file"));
+        assert_eq!(std::fs::read_to_string(target.path())?, "old content");
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Error};
+use std::collections::BTreeMap;
+
+/// One requirement of a selector on the labels of an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+}
+
+/// A label selector like `env=staging,owner!=core,team`, matching entries whose labels meet every requirement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Selector(Vec<Requirement>);
+
+impl Selector {
+    pub(crate) fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.0.iter().all(|r| match r {
+            Requirement::Equals(k, v) => labels.get(k) == Some(v),
+            Requirement::NotEquals(k, v) => labels.get(k) != Some(v),
+            Requirement::Exists(k) => labels.contains_key(k),
+        })
+    }
+}
+
+/// Parses a comma separated selector where each part is `key=value`, `key!=value`, or a bare `key` which must exist.
+pub(crate) fn parse_selector(value: &str) -> Result<Selector, Error> {
+    value
+        .split(',')
+        .map(str::trim)
+        .map(|part| {
+            let requirement = if let Some((k, v)) = part.split_once("!=") {
+                Requirement::NotEquals(k.to_string(), v.to_string())
+            } else if let Some((k, v)) = part.split_once('=') {
+                Requirement::Equals(k.to_string(), v.to_string())
+            } else {
+                Requirement::Exists(part.to_string())
+            };
+            let (Requirement::Equals(k, _) | Requirement::NotEquals(k, _) | Requirement::Exists(k)) = &requirement;
+            validate_key(k).map(|_| requirement)
+        })
+        .collect::<Result<_, _>>()
+        .map(Selector)
+}
+
+/// Parses a `key=value` label.
+pub(crate) fn parse_label(value: &str) -> Result<(String, String), Error> {
+    let (k, v) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("label '{}' must be in the form key=value", value))?;
+    validate_key(k)?;
+    if v.contains(',') {
+        return Err(anyhow!("label value '{}' must not contain a comma", v));
+    }
+    Ok((k.to_string(), v.to_string()))
+}
+
+/// Parses a tag, which follows the same rules as a label key.
+pub(crate) fn parse_tag(value: &str) -> Result<String, Error> {
+    validate_key(value).map_err(|_| anyhow!("invalid tag '{}'", value))?;
+    Ok(value.to_string())
+}
+
+fn validate_key(key: &str) -> Result<(), Error> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || "._/-".contains(c)) {
+        return Err(anyhow!("invalid label key '{}'", key));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector_matches() -> Result<(), Error> {
+        let labels = BTreeMap::from([parse_label("env=staging")?, parse_label("owner=payments")?]);
+        assert!(parse_selector("env=staging")?.matches(&labels));
+        assert!(parse_selector("env=staging,owner!=core")?.matches(&labels));
+        assert!(parse_selector("owner")?.matches(&labels));
+        assert!(!parse_selector("env=staging,owner!=payments")?.matches(&labels));
+        assert!(!parse_selector("team")?.matches(&labels));
+        assert!(parse_selector("=staging").is_err());
+        assert!(parse_label("env").is_err());
+        assert_eq!(parse_tag("project-x")?, "project-x");
+        assert!(parse_tag("two words").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1226 @@
+//! The hosts file model behind the eha command line tool, for tools that want to manage temporary localhost names
+//! the same way without shelling out to it.
+//!
+//! ```no_run
+//! use eha::{new_meta, HostsFile};
+//! use jiff::{SignedDuration, Timestamp};
+//!
+//! let mut hosts = HostsFile::load("/etc/hosts")?;
+//! let now = Timestamp::now();
+//! hosts.expire(now)?;
+//! hosts.add("myapp.local", new_meta(Some(now + SignedDuration::from_hours(1)), now, "my-tool"))?;
+//! hosts.save()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::policy::Policy;
+use crate::trash::{Trash, TrashedEntry};
+use crate::Entry::{Other, Supported};
+use anyhow::{anyhow, Context, Error};
+use jiff::Timestamp;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::env::current_dir;
+use std::fmt::{Display, Formatter};
+use std::fs::{remove_file, rename, File};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+pub mod attrs;
+pub mod config;
+pub mod events;
+pub mod explain;
+pub mod header;
+pub mod identity;
+pub mod index;
+pub mod manifest;
+pub mod nonblocking;
+pub mod policy;
+pub mod trash;
+
+/// A hosts file loaded into memory. Changes are made to the loaded content, and only written back to the file by
+/// [`HostsFile::save`], so a failed change leaves both untouched.
+#[derive(Debug, Clone)]
+pub struct HostsFile {
+    path: PathBuf,
+    content: Vec<u8>,
+    /// The content of the file when it was last read or saved, which it must still have when saving, or None when it
+    /// wasn't read from the file.
+    original: Option<Vec<u8>>,
+}
+
+impl HostsFile {
+    /// Reads the hosts file at the given path.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let content = read_content(&path.to_string_lossy())?;
+        Ok(HostsFile {
+            path,
+            original: Some(content.clone()),
+            content,
+        })
+    }
+
+    /// Returns the path the hosts file was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the content of the hosts file, including any changes that haven't been saved.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Returns every line of the hosts file.
+    pub fn entries(&self) -> Result<Vec<Entry<'_>>, Error> {
+        Ok(parse_content(&self.content))
+    }
+
+    /// Returns the metadata of the entry added by eha with the given name or alias, if there is one.
+    pub fn query(&self, name: &str) -> Result<Option<SupportedMeta>, Error> {
+        Ok(self.entries()?.into_iter().find_map(|e| match e {
+            Supported { meta, .. } if e.has_name(name) => Some(*meta),
+            _ => None,
+        }))
+    }
+
+    /// Adds an entry at the end of the file, failing if there already is one with the name. The entry isn't checked,
+    /// see [`validate_entry`] for the checks eha makes.
+    pub fn add(&mut self, name: &str, meta: SupportedMeta) -> Result<(), Error> {
+        self.edit(|entries| {
+            if entries.iter().any(|e| e.has_name(name)) {
+                return Err(anyhow!("an entry with name {} already exists", name));
+            }
+            entries.push(new_entry(name, meta));
+            Ok(())
+        })
+    }
+
+    /// Removes every line of the entry with the given name or alias, failing if eha didn't add one.
+    pub fn remove(&mut self, name: &str) -> Result<(), Error> {
+        self.edit(|entries| {
+            let removed = remove_entries(entries, None, Timestamp::now(), |n, meta| {
+                n == name || meta.aliases.iter().any(|a| a == name)
+            });
+            match removed.is_empty() {
+                true => Err(anyhow!("no entry added by eha with name {}", name)),
+                false => Ok(()),
+            }
+        })
+    }
+
+    /// Sets the expiry of the entry with the given name, recording the renewal.
+    pub fn renew(&mut self, name: &str, expiry: Timestamp, now: Timestamp) -> Result<(), Error> {
+        self.edit(|entries| renew_entry(entries, name, expiry, now))
+    }
+
+    /// Removes the entries that have expired by the given time, returning their names.
+    pub fn expire(&mut self, now: Timestamp) -> Result<Vec<String>, Error> {
+        self.edit(|entries| Ok(remove_entries(entries, None, now, |_, meta| meta.expired(now))))
+    }
+
+    /// Writes the content back to the file with [`replace_file`], holding the same lock as the eha command so that the
+    /// two wait for each other. Fails with [`FileChanged`] if something else changed the file since it was loaded or
+    /// last saved, since writing would undo that change. Backups, the journal and the audit log live in the state
+    /// directory of the eha command, so they are left to it.
+    pub fn save(&mut self) -> Result<(), Error> {
+        let _lock = attrs::lock_for_update(&self.path)?;
+        attrs::check_mutable(&self.path)?;
+        let unchanged = || match &self.original {
+            Some(original) => check_unchanged(&self.path, original),
+            None => Ok(()),
+        };
+        unchanged()?;
+        attrs::with_unprotected(&self.path, || {
+            replace_file(&self.path, &temp_path(&self.path), &self.content, unchanged)
+        })?;
+        self.original = Some(self.content.clone());
+        Ok(())
+    }
+
+    /// Renders every line of the file as json, see the [`Serialize`] implementation for the layout.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).context("failed to serialize hosts file")
+    }
+
+    /// Builds a hosts file from json in the layout written by [`HostsFile::to_json`].
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).context("invalid hosts file json")
+    }
+
+    /// Starts staging several changes to commit together with [`Transaction::commit`].
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction {
+            hosts: self,
+            policy: Policy::default(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Applies a change to the parsed entries, keeping the content as it was if the change fails.
+    fn edit<T>(&mut self, change: impl FnOnce(&mut Vec<Entry>) -> Result<T, Error>) -> Result<T, Error> {
+        let mut entries = self.entries()?;
+        let result = change(&mut entries)?;
+        let content = render(&entries, self.content.len(), LineEndings::detect(&self.content));
+        self.content = content;
+        Ok(result)
+    }
+}
+
+/// One line of a hosts file in its json form, either an entry managed by eha with its metadata or any other line kept
+/// verbatim.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+enum JsonLine {
+    Managed {
+        name: String,
+        #[serde(default)]
+        disabled: bool,
+        meta: Box<SupportedMeta>,
+    },
+    Other {
+        line: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonFile {
+    path: PathBuf,
+    lines: Vec<JsonLine>,
+}
+
+/// Serializes the file as its path and a list of lines in order, like
+/// `{"path":"/etc/hosts","lines":[{"kind":"other","line":"127.0.0.1 localhost"},{"kind":"managed","name":"myapp.local","disabled":false,"meta":{...}}]}`,
+/// so that other tools can read eha's view of the file without parsing the metadata comments themselves. Lines that
+/// aren't valid UTF-8 are written with replacement characters.
+impl Serialize for HostsFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let lines = self
+            .entries()
+            .map_err(serde::ser::Error::custom)?
+            .into_iter()
+            .map(|e| match e {
+                Supported { name, meta, disabled, .. } => JsonLine::Managed { name, disabled, meta },
+                Other(line) => JsonLine::Other {
+                    line: String::from_utf8_lossy(line.raw).to_string(),
+                },
+            })
+            .collect();
+        JsonFile {
+            path: self.path.clone(),
+            lines,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Builds the content from the lines, failing if it wouldn't parse back to the same lines, such as an other line that
+/// looks like a managed entry or contains a line break.
+impl<'de> Deserialize<'de> for HostsFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+        let file = JsonFile::deserialize(deserializer)?;
+        let entries = file
+            .lines
+            .iter()
+            .map(|l| match l {
+                JsonLine::Managed { name, disabled, meta } => Supported {
+                    name: name.clone(),
+                    meta: meta.clone(),
+                    disabled: *disabled,
+                    raw: None,
+                },
+                JsonLine::Other { line } => Other(HostLine::parse(line.as_bytes())),
+            })
+            .collect::<Vec<Entry>>();
+        let content = render(&entries, 0, LineEndings::default());
+        let parsed = parse_content(&content);
+        for (i, line) in file.lines.iter().enumerate() {
+            let same = match (line, parsed.get(i)) {
+                (JsonLine::Managed { name, disabled, .. }, Some(Supported { name: n, disabled: d, .. })) => name == n && disabled == d,
+                (JsonLine::Other { line }, Some(Other(l))) => line.as_bytes() == l.raw,
+                _ => false,
+            };
+            if !same {
+                return Err(D::Error::custom(format!(
+                    "line {} can't be written as a single hosts file line",
+                    i + 1
+                )));
+            }
+        }
+        Ok(HostsFile {
+            path: file.path,
+            content,
+            original: None,
+        })
+    }
+}
+
+/// Changes staged against a [`HostsFile`]. Committing applies them in order and then saves the file once, and if
+/// any of them fails or isn't allowed by the policy, neither the file nor the loaded content change.
+pub struct Transaction<'a> {
+    hosts: &'a mut HostsFile,
+    policy: Policy,
+    changes: Vec<Change>,
+}
+
+enum Change {
+    Add(String, Box<SupportedMeta>),
+    Remove(String),
+    Renew(String, Timestamp, Timestamp),
+}
+
+impl Transaction<'_> {
+    /// Checks added names against the given policy instead of the default one, which allows names ending in .local
+    /// or .localhost.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Stages adding an entry, see [`HostsFile::add`].
+    pub fn add(mut self, name: &str, meta: SupportedMeta) -> Self {
+        self.changes.push(Change::Add(name.to_string(), Box::new(meta)));
+        self
+    }
+
+    /// Stages removing an entry, see [`HostsFile::remove`].
+    pub fn remove(mut self, name: &str) -> Self {
+        self.changes.push(Change::Remove(name.to_string()));
+        self
+    }
+
+    /// Stages renewing an entry, see [`HostsFile::renew`].
+    pub fn renew(mut self, name: &str, expiry: Timestamp, now: Timestamp) -> Self {
+        self.changes.push(Change::Renew(name.to_string(), expiry, now));
+        self
+    }
+
+    /// Applies the staged changes and saves the file in one write.
+    pub fn commit(self) -> Result<(), Error> {
+        let mut staged = self.hosts.clone();
+        for (i, change) in self.changes.into_iter().enumerate() {
+            match change {
+                Change::Add(name, meta) => validate_entry(&name, meta.ip, &self.policy).and_then(|_| staged.add(&name, *meta)),
+                Change::Remove(name) => staged.remove(&name),
+                Change::Renew(name, expiry, now) => staged.renew(&name, expiry, now),
+            }
+            .with_context(|| format!("change {} of the transaction failed, no changes were made", i + 1))?;
+        }
+        staged.save()?;
+        *self.hosts = staged;
+        Ok(())
+    }
+}
+
+/// Returns each name managed by eha once, with the metadata of its first line.
+pub fn managed_names<'a>(entries: &'a [Entry]) -> Vec<(&'a str, &'a SupportedMeta)> {
+    let mut names: Vec<(&str, &SupportedMeta)> = Vec::new();
+    for e in entries {
+        if let Supported { name, meta, .. } = e {
+            if !names.iter().any(|(n, _)| n == name) {
+                names.push((name, meta));
+            }
+        }
+    }
+    names
+}
+
+/// Returns an error if the name is not allowed by the policy, or if the policy doesn't allow pointing it at the address,
+/// where None is the default 127.0.0.1.
+pub fn validate_entry(name: &str, ip: Option<IpAddr>, policy: &Policy) -> Result<(), Error> {
+    validate_name(name, policy)?;
+    policy.check_ip(ip)
+}
+
+/// Returns an error if the name is not a valid DNS name allowed by the policy, which by default only allows names
+/// ending in .local or .localhost.
+pub fn validate_name(name: &str, policy: &Policy) -> Result<(), Error> {
+    policy.check_name(name)?;
+    for (i, x) in name.split('.').enumerate() {
+        let l = x.len();
+        if l == 0 {
+            return Err(anyhow!("invalid DNS name #{}: cannot be empty", i));
+        } else if let Some((j, c, _)) = x.chars().enumerate().map(|(a, b)| (a, b, l)).find(invalid_dns_name_char) {
+            return Err(anyhow!("invalid DNS name char in part #{} @ {}: {}", i, j, c));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if the expiry is outside of what eha or the policy allow.
+pub fn validate_expire_minutes(expire_minutes: usize, policy: &Policy) -> Result<(), Error> {
+    if !(1..525600).contains(&expire_minutes) {
+        return Err(anyhow!("ttl minutes must be between 1m and 365d (inclusive)"));
+    }
+    policy.check_expire_minutes(expire_minutes)
+}
+
+/// Returns the metadata for a new entry with the given expiry, recording where, when and by whom it was added.
+pub fn new_meta(expiry: Option<Timestamp>, now: Timestamp, source: &str) -> SupportedMeta {
+    let (user, uid) = identity::invoking_user(|k| std::env::var(k).ok());
+    SupportedMeta {
+        expiry,
+        comment: Some(format!(
+            "set from {} at {}",
+            current_dir().unwrap_or_default().to_string_lossy(),
+            &now
+        )),
+        created_at: Some(now),
+        source: Some(source.to_string()),
+        user,
+        uid,
+        ..Default::default()
+    }
+}
+
+/// Returns a new entry for the name, which is written out in full the next time the file is rendered.
+pub fn new_entry(name: &str, meta: SupportedMeta) -> Entry<'static> {
+    Supported {
+        name: name.to_string(),
+        meta: Box::new(meta),
+        disabled: false,
+        raw: None,
+    }
+}
+
+/// Removes every entry added by eha that matches the predicate, moving them into the trash if there is one. Returns the
+/// names of the entries removed.
+pub fn remove_entries(
+    entries: &mut Vec<Entry>,
+    mut trash: Option<&mut Trash>,
+    now: Timestamp,
+    predicate: impl Fn(&str, &SupportedMeta) -> bool,
+) -> Vec<String> {
+    let (removed, kept): (Vec<Entry>, Vec<Entry>) = std::mem::take(entries)
+        .into_iter()
+        .partition(|e| matches!(e, Supported { name, meta, .. } if predicate(name, meta)));
+    *entries = kept;
+    let mut names: Vec<String> = Vec::new();
+    for e in &removed {
+        // the lines of a dual stack name are one entry as far as the user is concerned
+        if let Supported { name, .. } = e {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    if let Some(trash) = trash.as_mut() {
+        for e in removed {
+            if let Supported { name, meta, disabled, .. } = e {
+                trash.push(TrashedEntry {
+                    name,
+                    removed_at: now,
+                    disabled,
+                    meta: *meta,
+                });
+            }
+        }
+    }
+    names
+}
+
+/// Sets the expiry of every entry with the given name, failing if eha didn't add it or it has already expired.
+pub fn renew_entry(entries: &mut [Entry], target: &str, expiry: Timestamp, now: Timestamp) -> Result<(), Error> {
+    let mut found = false;
+    for e in entries.iter_mut() {
+        let matched = e.has_name(target);
+        if let Supported { meta, raw, .. } = e {
+            if matched && !meta.expired(now) {
+                meta.renew(Some(expiry), now);
+                *raw = None;
+                found = true;
+            }
+        }
+    }
+    if !found {
+        return Err(anyhow!("no unexpired entry added by eha with name {}", target));
+    }
+    Ok(())
+}
+
+/// Pushes the expiry of every entry with the given name out to the given time, keeping any later expiry, and failing if
+/// eha didn't add it or it has already expired.
+pub fn extend_entry(entries: &mut [Entry], target: &str, expiry: Timestamp, now: Timestamp) -> Result<(), Error> {
+    let mut found = false;
+    for e in entries.iter_mut() {
+        let matched = e.has_name(target);
+        if let Supported { meta, raw, .. } = e {
+            if matched && !meta.expired(now) {
+                if meta.extend(expiry, now) {
+                    *raw = None;
+                }
+                found = true;
+            }
+        }
+    }
+    if !found {
+        return Err(anyhow!("no unexpired entry added by eha with name {}", target));
+    }
+    Ok(())
+}
+
+/// Marks every entry with the given name as disabled or enabled. Returns an error if eha does not manage an entry with
+/// that name.
+pub fn set_disabled(entries: &mut [Entry], target: &str, value: bool) -> Result<(), Error> {
+    let mut found = false;
+    for e in entries.iter_mut() {
+        let matched = e.has_name(target);
+        if let Supported { disabled, raw, .. } = e {
+            if matched {
+                *disabled = value;
+                *raw = None;
+                found = true;
+            }
+        }
+    }
+    if !found {
+        return Err(anyhow!("no entry added by eha with name {}", target));
+    }
+    Ok(())
+}
+
+/// Returns whether the given character is invalid in a DNS name. This designed to be used as a
+/// chained filter.
+fn invalid_dns_name_char(bits: &(usize, char, usize)) -> bool {
+    let (index, c, part_len) = *bits;
+
+    // cannot be longer than 63
+    if part_len > 63 {
+        return true;
+    }
+    // cannot start or end with -
+    if c == '-' && (index == 0 || index == part_len - 1) {
+        return true;
+    }
+    // must be valid char
+    !c.is_ascii_alphanumeric() && c != '-'
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SupportedMeta {
+    /// When the entry may be removed, or None if it never expires.
+    pub expiry: Option<Timestamp>,
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Timestamp>,
+    /// When the expiry was extended, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub renewed_at: Vec<Timestamp>,
+    /// The address the name resolves to, when it isn't 127.0.0.1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    /// Other names written on the same line after the name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// What added the entry, such as cli or apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The git repository the entry was added from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Who added the entry, looking through sudo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// The machine the entry was added on, since hosts files get copied between machines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// The arguments of the eha command that added the entry.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+}
+
+impl SupportedMeta {
+    pub fn expired(&self, now: Timestamp) -> bool {
+        self.expiry.is_some_and(|e| e <= now)
+    }
+
+    /// Sets a new expiry, recording when it was renewed.
+    pub fn renew(&mut self, expiry: Option<Timestamp>, now: Timestamp) {
+        self.expiry = expiry;
+        self.renewed_at.push(now);
+    }
+
+    /// Renews with the given expiry unless the entry already lasts longer, returning whether it was renewed.
+    pub fn extend(&mut self, expiry: Timestamp, now: Timestamp) -> bool {
+        if self.expiry.is_none_or(|e| e >= expiry) {
+            return false;
+        }
+        self.renew(Some(expiry), now);
+        true
+    }
+
+    /// Returns the expiry for display, or never if there isn't one.
+    pub fn expiry_text(&self) -> String {
+        self.expiry.map_or("never".to_string(), |e| e.to_string())
+    }
+}
+
+/// A single line of the hosts file. Entries borrow from the content that was read so that the common case of a large
+/// file with only a few managed lines doesn't allocate or re-serialize every line.
+pub enum Entry<'a> {
+    /// An entry managed by eha. Disabled entries are written as a commented out line so that they do not resolve, but
+    /// keep their metadata so that they can be enabled again. The raw line is written back verbatim and must be cleared
+    /// whenever the entry is modified.
+    Supported {
+        name: String,
+        meta: Box<SupportedMeta>,
+        disabled: bool,
+        raw: Option<&'a str>,
+    },
+    /// Any other line, which is written back verbatim.
+    Other(HostLine<'a>),
+}
+
+/// A line of the hosts file that eha doesn't manage, split into its parts for reading while keeping the original bytes
+/// to write back. Blank lines and comments have no address or names, and lines that don't start with a valid address
+/// are kept without being split. Unmanaged lines may be in any encoding, and the parts that aren't valid UTF-8 are left
+/// out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostLine<'a> {
+    /// The line exactly as it was read.
+    pub raw: &'a [u8],
+    pub ip: Option<IpAddr>,
+    /// The first name after the address.
+    pub name: Option<&'a str>,
+    /// Any other names after it.
+    pub aliases: Vec<&'a str>,
+    /// The text after the first #, without the #.
+    pub comment: Option<&'a str>,
+}
+
+impl<'a> HostLine<'a> {
+    pub fn parse(raw: &'a [u8]) -> Self {
+        let (body, comment) = match raw.iter().position(|b| *b == b'#') {
+            Some(i) => (&raw[..i], std::str::from_utf8(&raw[i + 1..]).ok()),
+            None => (raw, None),
+        };
+        let mut words = body
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|w| !w.is_empty())
+            .map(|w| std::str::from_utf8(w).ok());
+        let ip = words.next().flatten().and_then(|w| w.parse::<IpAddr>().ok());
+        let (name, aliases) = match ip {
+            Some(_) => (words.next().flatten(), words.flatten().collect()),
+            None => (None, Vec::new()),
+        };
+        HostLine {
+            raw,
+            ip,
+            name,
+            aliases,
+            comment,
+        }
+    }
+
+    /// Returns the line as text, or None if it isn't valid UTF-8.
+    pub fn text(&self) -> Option<&'a str> {
+        std::str::from_utf8(self.raw).ok()
+    }
+
+    /// Returns true if the line resolves the given name, as its name or an alias.
+    pub fn has_name(&self, target: &str) -> bool {
+        self.name == Some(target) || self.aliases.contains(&target)
+    }
+}
+
+impl Entry<'_> {
+    /// Returns true if the entry was added by eha for the given name, or has it as an alias.
+    pub fn has_name(&self, target: &str) -> bool {
+        matches!(self, Supported { name, meta, .. } if name == target || meta.aliases.iter().any(|a| a == target))
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Entry<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        if let Some((a, b)) = value.split_once(std::str::from_utf8(MANAGED_MARKER).expect("the marker is ascii")) {
+            let (disabled, a) = match a.trim_start().strip_prefix('#') {
+                Some(rest) => (true, rest),
+                None => (false, a),
+            };
+            // the name follows the address, and any aliases after it are also recorded in the metadata
+            let mut words = a.split_whitespace();
+            if let Some(name) = words.nth(1).or_else(|| a.split_whitespace().last()) {
+                return Ok(Supported {
+                    name: name.to_string(),
+                    meta: serde_json::from_str(b).context("invalid eha metadata")?,
+                    disabled,
+                    raw: Some(value),
+                });
+            }
+        }
+        Ok(Other(HostLine::parse(value.as_bytes())))
+    }
+}
+
+/// The error returned when a file changed between being read and being written.
+#[derive(Debug)]
+pub struct FileChanged;
+
+impl Display for FileChanged {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "input file was modified while eha was running")
+    }
+}
+
+impl std::error::Error for FileChanged {}
+
+/// Returns a [`FileChanged`] error if the file no longer has the content that was read from it, which means another
+/// program changed it in the meantime and writing now would undo that change.
+pub fn check_unchanged(path: &Path, content: &[u8]) -> Result<(), Error> {
+    if read_content(&path.to_string_lossy())? != content {
+        return Err(FileChanged.into());
+    }
+    Ok(())
+}
+
+/// Returns a new temp file path next to the target, since renaming only works within one filesystem or volume.
+pub fn temp_path(target: &Path) -> PathBuf {
+    let mut temp = target.as_os_str().to_owned();
+    temp.push(format!(".eha-{:08x}.tmp", rand::random::<u32>()));
+    PathBuf::from(temp)
+}
+
+/// Replaces the target with the output by writing and syncing the temp file, copying the owner, permissions and
+/// extended attributes of the target to it, and moving it into place, so that readers never see a partial write. The
+/// ready callback runs once the temp file is complete and can stop the move by failing, and the temp file is removed
+/// on failure. A temp file that can't carry the security label of the target is copied over it instead.
+pub fn replace_file(target: &Path, temp: &Path, output: &[u8], ready: impl FnOnce() -> Result<(), Error>) -> Result<(), Error> {
+    eprintln!("writing to {} and moving to {}", temp.to_string_lossy(), target.to_string_lossy());
+    let result = File::create(temp)
+        .context("failed to create temp file")
+        .and_then(|mut file| {
+            file.write_all(output).context("failed to write content")?;
+            file.sync_all().context("failed to sync temp file")
+        })
+        .and_then(|_| copy_ownership(target, temp).context("failed to copy the owner and permissions of the input file"))
+        .and_then(|_| copy_xattrs(target, temp))
+        .and_then(|labelled| ready().map(|_| labelled))
+        .and_then(|labelled| match labelled {
+            true => attrs::retry_while_locked(|| move_into_place(temp, target)).context("failed to rename temp file to input file"),
+            false => {
+                eprintln!(
+                    "the security label of {} can't be copied, copying the content over it instead",
+                    target.to_string_lossy()
+                );
+                copy_over(temp, target).context("failed to copy temp file over input file")
+            }
+        });
+    if result.is_err() {
+        let _ = remove_file(temp);
+    }
+    result
+}
+
+/// Renames a complete temp file over the target, which replaces it atomically as long as the temp file is in the same
+/// directory. Renames can't replace a file that is a mount point, such as a hosts file bind mounted into a container,
+/// or cross filesystems, so then the content is copied over the target and synced instead, keeping its permissions.
+/// The temp file must already be synced, and the directory is synced after the rename so that it survives a crash.
+pub fn move_into_place(temp: &Path, target: &Path) -> std::io::Result<()> {
+    match rename(temp, target) {
+        Ok(()) => sync_parent_dir(target),
+        Err(e) if needs_copy(&e) => copy_over(temp, target),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copies the content of a complete temp file over the target and syncs it, then removes the temp file. The target
+/// keeps its inode and so its permissions and labels, but unlike [`move_into_place`] a crash part way through can
+/// leave it partially written.
+pub fn copy_over(temp: &Path, target: &Path) -> std::io::Result<()> {
+    let mut file = File::options().write(true).truncate(true).open(target)?;
+    std::io::copy(&mut File::open(temp)?, &mut file)?;
+    file.sync_all()?;
+    remove_file(temp)
+}
+
+/// Syncs the directory holding the path, which makes a rename within it durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => File::open(".")?.sync_all(),
+    }
+}
+
+/// Windows has no way to sync a directory, and its renames are journaled by NTFS.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Gives the temp file the permissions, owner, and group of the file it will replace, since a new file gets those of
+/// the user running eha instead and some services refuse to read a hosts file that isn't root's. Nothing is copied if
+/// the target doesn't exist yet.
+#[cfg(unix)]
+pub fn copy_ownership(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let original = match std::fs::metadata(from) {
+        Ok(m) => m,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let current = std::fs::metadata(to)?;
+    // only root can give a file away, so leave it alone when it already matches
+    if (current.uid(), current.gid()) != (original.uid(), original.gid()) {
+        std::os::unix::fs::chown(to, Some(original.uid()), Some(original.gid()))?;
+    }
+    std::fs::set_permissions(to, original.permissions())
+}
+
+/// Windows files get their permissions from the directory, which the temp file shares with the hosts file.
+#[cfg(not(unix))]
+pub fn copy_ownership(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Copies every extended attribute, including security.* and user.* attributes, from the file being replaced to the
+/// temp file, so that tools which tag it don't see the tags disappear. Returns false if a security label, such as the
+/// `etc_t` SELinux context or a SMACK label, couldn't be set on the temp file. Confined daemons may not be able to read
+/// a hosts file without its label, so the temp file should then be copied over the original with [`copy_over`].
+#[cfg(unix)]
+pub fn copy_xattrs(from: &Path, to: &Path) -> Result<bool, Error> {
+    let names = match xattr::list(from) {
+        Ok(names) => names,
+        Err(e) if matches!(e.kind(), ErrorKind::Unsupported | ErrorKind::NotFound) => return Ok(true),
+        Err(e) => return Err(Error::new(e).context("failed to list extended attributes")),
+    };
+    for name in names {
+        let n = name.to_string_lossy();
+        if let Some(value) = xattr::get(from, &name).with_context(|| format!("failed to read extended attribute {}", n))? {
+            match xattr::set(to, &name, &value) {
+                Ok(()) => {}
+                Err(_) if n.starts_with("security.") => return Ok(false),
+                Err(e) => return Err(Error::new(e).context(format!("failed to copy extended attribute {}", n))),
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn copy_xattrs(_from: &Path, _to: &Path) -> Result<bool, Error> {
+    Ok(true)
+}
+
+/// Returns true if a rename failed because the target can't be replaced, rather than for a reason copying won't fix.
+fn needs_copy(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::CrossesDevices | ErrorKind::ResourceBusy)
+}
+
+/// Reads the content of the given hosts file. The content is kept as bytes so that unmanaged lines in other encodings
+/// are written back untouched.
+pub fn read_content(path: &str) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).with_context(|| format!("failed to read input file {}", path))
+}
+
+/// Returns a 64 bit FNV-1a hash of the content, which stays the same across builds and platforms so that it can be
+/// recorded in the state directory to tell whether a file changed since.
+pub fn content_hash(content: &[u8]) -> u64 {
+    content
+        .iter()
+        .fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Parses each line of the content into an entry. Lines are split like [`str::lines`], and a line is only managed when
+/// its eha metadata is valid UTF-8 and parses, so that one damaged line doesn't stop eha from working on the rest of the
+/// file. Any other line is kept as it is, and [`invalid_lines`] describes the ones that looked managed.
+pub fn parse_content(content: &[u8]) -> Vec<Entry<'_>> {
+    parse_lines(content).into_iter().map(|(_, e)| e).collect()
+}
+
+/// Parses the content like [`parse_content`], along with the byte range of each line within it, not including the line
+/// ending.
+pub fn parse_lines(content: &[u8]) -> Vec<(Range<usize>, Entry<'_>)> {
+    let mut start = 0;
+    content
+        .split_inclusive(|b| *b == b'\n')
+        .map(|line| {
+            let span = start..start + line.len();
+            start = span.end;
+            let line = match line.strip_suffix(b"\n") {
+                Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+                None => line,
+            };
+            let entry = match std::str::from_utf8(line).map(Entry::try_from) {
+                Ok(Ok(entry)) => entry,
+                _ => Other(HostLine::parse(line)),
+            };
+            (span.start..span.start + line.len(), entry)
+        })
+        .collect()
+}
+
+/// Returns a warning with the line number, content and problem of each line that has eha metadata which doesn't parse,
+/// and which [`parse_content`] therefore left unmanaged.
+pub fn invalid_lines(path: &str, entries: &[Entry]) -> Vec<String> {
+    entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| match e {
+            Other(line) if line.raw.windows(MANAGED_MARKER.len()).any(|w| w == MANAGED_MARKER) => {
+                let problem = match std::str::from_utf8(line.raw) {
+                    Ok(raw) => Entry::try_from(raw).err()?,
+                    Err(_) => anyhow!("eha metadata must be valid UTF-8"),
+                };
+                Some(format!(
+                    "ignoring invalid eha metadata on line {} of {}, which is left as it is: {}: {:#}",
+                    i + 1,
+                    path,
+                    truncate_line(&String::from_utf8_lossy(line.raw)),
+                    problem
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The comment that starts the metadata of a managed line.
+const MANAGED_MARKER: &[u8] = b"# eha ";
+
+/// Shortens a line for inclusion in an error message.
+fn truncate_line(line: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    match line.char_indices().nth(MAX_CHARS) {
+        Some((i, _)) => format!("{}...", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+impl Display for Entry<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Supported { raw: Some(raw), .. } => f.write_str(raw),
+            Other(line) => f.write_str(&String::from_utf8_lossy(line.raw)),
+            Supported { name, meta, disabled, .. } => write!(
+                f,
+                "{}{}\t{}{}\t# eha {}",
+                if *disabled { "# " } else { "" },
+                meta.ip.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                name,
+                meta.aliases.iter().map(|a| format!(" {}", a)).collect::<String>(),
+                serde_json::to_string(meta).unwrap_or_else(|e| e.to_string())
+            ),
+        }
+    }
+}
+
+/// How the lines of a hosts file end, so that writing it back keeps them the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEndings {
+    /// \r\n for a file that came from Windows, and otherwise \n.
+    pub newline: &'static [u8],
+    /// Whether the last line ends with a newline too.
+    pub trailing: bool,
+}
+
+impl Default for LineEndings {
+    fn default() -> Self {
+        LineEndings {
+            newline: b"\n",
+            trailing: false,
+        }
+    }
+}
+
+impl LineEndings {
+    /// Detects the line endings of the content, using \r\n if the first line ends with one.
+    pub fn detect(content: &[u8]) -> Self {
+        let newline: &'static [u8] = match content.iter().position(|b| *b == b'\n') {
+            Some(i) if i > 0 && content[i - 1] == b'\r' => b"\r\n",
+            _ => b"\n",
+        };
+        LineEndings {
+            newline,
+            trailing: content.ends_with(b"\n"),
+        }
+    }
+}
+
+/// Renders the entries back into the content of a hosts file, with unmanaged lines written back byte for byte and
+/// each line ended as given.
+pub fn render(entries: &[Entry], capacity: usize, endings: LineEndings) -> Vec<u8> {
+    let mut out = Vec::with_capacity(capacity);
+    for (i, e) in entries.iter().enumerate() {
+        if i > 0 {
+            out.extend_from_slice(endings.newline);
+        }
+        match e {
+            Other(line) => out.extend_from_slice(line.raw),
+            e => out.extend_from_slice(e.to_string().as_bytes()),
+        }
+    }
+    if endings.trailing && !entries.is_empty() {
+        out.extend_from_slice(endings.newline);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::SignedDuration;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_hosts_file() -> Result<(), Error> {
+        // the lock file goes next to the hosts file, so both go in a directory that is removed afterwards
+        let dir = tempfile::tempdir()?;
+        let mut f = NamedTempFile::new_in(dir.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	old.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let now: Timestamp = "2030-01-01T00:00:00Z".parse()?;
+        let mut hosts = HostsFile::load(f.path())?;
+        assert_eq!(hosts.expire(now)?, vec!["old.local".to_string()]);
+        hosts.add("foo.local", new_meta(Some(now + SignedDuration::from_hours(1)), now, "test"))?;
+        assert!(hosts.add("foo.local", SupportedMeta::default()).is_err());
+        hosts.renew("foo.local", now + SignedDuration::from_hours(2), now)?;
+        assert_eq!(
+            hosts.query("foo.local")?.and_then(|m| m.expiry),
+            Some(now + SignedDuration::from_hours(2))
+        );
+        // an entry that has already expired is gone as far as renewing goes
+        let later = now + SignedDuration::from_hours(3);
+        assert!(hosts.renew("foo.local", later + SignedDuration::from_hours(1), later).is_err());
+
+        // failed changes leave the content alone
+        let before = hosts.content().to_vec();
+        assert!(hosts.remove("bar.local").is_err());
+        assert_eq!(hosts.content(), before);
+
+        hosts.save()?;
+        let mut saved = HostsFile::load(f.path())?;
+        assert_eq!(saved.content(), before);
+        assert_eq!(saved.entries()?.len(), 2);
+
+        saved.remove("foo.local")?;
+        assert_eq!(saved.content(), b"127.0.0.1   localhost");
+
+        // saving doesn't undo a change made to the file since it was loaded
+        std::fs::write(f.path(), b"127.0.0.1   localhost\n10.0.0.1 db\n")?;
+        let e = saved.save().unwrap_err();
+        assert!(e.is::<FileChanged>());
+        assert_eq!(std::fs::read(f.path())?, b"127.0.0.1   localhost\n10.0.0.1 db\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_line() {
+        let line = HostLine::parse(b"::1\tlocalhost ip6-localhost  ip6-loopback # loopback");
+        assert_eq!(line.ip, Some("::1".parse().unwrap()));
+        assert_eq!(line.name, Some("localhost"));
+        assert_eq!(line.aliases, vec!["ip6-localhost", "ip6-loopback"]);
+        assert_eq!(line.comment, Some(" loopback"));
+        assert!(line.has_name("ip6-loopback"));
+
+        for raw in ["", "   ", "# just a comment", "not-an-ip name"] {
+            let line = HostLine::parse(raw.as_bytes());
+            assert_eq!((line.ip, line.name), (None, None), "{}", raw);
+        }
+        let content = b"10.0.0.1 db  # primary\r\n\n  # caf\xe9\n10.0.0.2 caf\xe9.lan cache\n";
+        let entries = parse_content(content);
+        assert!(matches!(&entries[3], Other(l) if l.ip.is_some() && l.name.is_none() && l.aliases == ["cache"]));
+        let spans = parse_lines(content)
+            .into_iter()
+            .map(|(span, _)| span)
+            .collect::<Vec<Range<usize>>>();
+        assert_eq!(spans, vec![0..22, 24..24, 25..33, 34..57]);
+        assert_eq!(
+            render(&entries, 0, LineEndings::default()),
+            b"10.0.0.1 db  # primary\n\n  # caf\xe9\n10.0.0.2 caf\xe9.lan cache"
+        );
+
+        // lines with metadata that doesn't parse are left unmanaged, with a warning about each of them
+        let content = b"127.0.0.1\tcaf\xe9.local\t# eha {}\n127.0.0.1\tfoo.local\t# eha {\"expiry\":\"bad\"}\n# eha notes";
+        let entries = parse_content(content);
+        assert!(entries.iter().all(|e| matches!(e, Other(_))));
+        assert_eq!(render(&entries, 0, LineEndings::default()), content);
+        let warnings = invalid_lines("hosts", &entries);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("ignoring invalid eha metadata on line 1 of hosts, which is left as it is: "));
+        assert!(warnings[0].ends_with(": eha metadata must be valid UTF-8"));
+        assert!(warnings[1].contains("line 2 of hosts") && warnings[1].contains("invalid eha metadata: "));
+    }
+
+    #[test]
+    fn test_line_endings() -> Result<(), Error> {
+        let crlf = |trailing| LineEndings {
+            newline: b"\r\n",
+            trailing,
+        };
+        assert_eq!(LineEndings::detect(b"a\r\nb\nc"), crlf(false));
+        assert_eq!(LineEndings::detect(b"\r\n"), crlf(true));
+        assert_eq!(LineEndings::detect(b"a\nb\r\n").newline, b"\n");
+        assert_eq!(LineEndings::detect(b""), LineEndings::default());
+
+        let dir = tempfile::tempdir()?;
+        let mut f = NamedTempFile::new_in(dir.path())?;
+        f.write_all(b"127.0.0.1   localhost\r\n127.0.0.1\told.local\t# eha {\"expiry\":\"2029-01-01T00:00:00Z\",\"comment\":null}\r\n::1 localhost\r\n")?;
+        let mut hosts = HostsFile::load(f.path())?;
+        hosts.expire("2030-01-01T00:00:00Z".parse()?)?;
+        assert_eq!(hosts.content(), b"127.0.0.1   localhost\r\n::1 localhost\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let mut f = NamedTempFile::new_in(dir.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+# 127.0.0.1	old.local	# eha {"expiry":null,"comment":"kept"}"##,
+        )?;
+        let hosts = HostsFile::load(f.path())?;
+        let json = hosts.to_json()?;
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(
+            value["lines"][0],
+            serde_json::json!({"kind": "other", "line": "127.0.0.1   localhost"})
+        );
+        assert_eq!(
+            value["lines"][1],
+            serde_json::json!({"kind": "managed", "name": "old.local", "disabled": true, "meta": {"expiry": null, "comment": "kept"}})
+        );
+
+        let back = HostsFile::from_json(&json)?;
+        assert_eq!(back.path(), f.path());
+        assert_eq!(back.query("old.local")?.and_then(|m| m.comment).as_deref(), Some("kept"));
+        assert_eq!(back.entries()?.len(), 2);
+
+        let bad = r##"{"path":"/etc/hosts","lines":[{"kind":"other","line":"a\nb"}]}"##;
+        assert!(HostsFile::from_json(bad).is_err());
+        let bad = r##"{"path":"/etc/hosts","lines":[{"kind":"other","line":"127.0.0.1 x # eha {}"}]}"##;
+        assert!(HostsFile::from_json(bad).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_into_place() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let (temp, target) = (dir.path().join("hosts.tmp"), dir.path().join("hosts"));
+        std::fs::write(&temp, "new")?;
+        std::fs::write(&target, "old")?;
+        move_into_place(&temp, &target)?;
+        assert_eq!(std::fs::read_to_string(&target)?, "new");
+        assert!(!temp.exists());
+
+        assert!(needs_copy(&std::io::Error::from(ErrorKind::CrossesDevices)));
+        assert!(needs_copy(&std::io::Error::from(ErrorKind::ResourceBusy)));
+        assert!(!needs_copy(&std::io::Error::from(ErrorKind::PermissionDenied)));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sync_parent_dir() -> Result<(), Error> {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir()?;
+        sync_parent_dir(&dir.path().join("hosts"))?;
+        // a bare file name is in the current directory
+        sync_parent_dir(Path::new("hosts"))?;
+        assert!(sync_parent_dir(&dir.path().join("missing/hosts")).is_err());
+
+        // copying over the target keeps its inode, and syncs it before removing the temp file
+        let (temp, target) = (dir.path().join("hosts.tmp"), dir.path().join("hosts"));
+        std::fs::write(&temp, "new")?;
+        std::fs::write(&target, "older")?;
+        let inode = std::fs::metadata(&target)?.ino();
+        copy_over(&temp, &target)?;
+        assert_eq!(std::fs::read_to_string(&target)?, "new");
+        assert_eq!(std::fs::metadata(&target)?.ino(), inode);
+        assert!(!temp.exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_ownership() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let (original, temp) = (dir.path().join("hosts"), dir.path().join("hosts.tmp"));
+        std::fs::write(&original, "old")?;
+        std::fs::write(&temp, "new")?;
+        std::fs::set_permissions(&original, std::fs::Permissions::from_mode(0o640))?;
+        copy_ownership(&original, &temp)?;
+        assert_eq!(std::fs::metadata(&temp)?.permissions().mode() & 0o777, 0o640);
+        copy_ownership(&dir.path().join("missing"), &temp)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_xattrs() -> Result<(), Error> {
+        let from = NamedTempFile::new()?;
+        let to = NamedTempFile::new()?;
+        if xattr::set(from.path(), "user.eha.test", b"tagged").is_err() {
+            // the filesystem doesn't support user attributes
+            return Ok(());
+        }
+        assert!(copy_xattrs(from.path(), to.path())?);
+        assert_eq!(xattr::get(to.path(), "user.eha.test")?, Some(b"tagged".to_vec()));
+        assert!(copy_xattrs(Path::new("/nonexistent/hosts"), to.path())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_over() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let (temp, target) = (dir.path().join("hosts.tmp"), dir.path().join("hosts"));
+        std::fs::write(&temp, "new")?;
+        std::fs::write(&target, "old content")?;
+        copy_over(&temp, &target)?;
+        assert_eq!(std::fs::read_to_string(&target)?, "new");
+        assert!(!temp.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let mut f = NamedTempFile::new_in(dir.path())?;
+        f.write_all(b"127.0.0.1   localhost")?;
+        let now: Timestamp = "2030-01-01T00:00:00Z".parse()?;
+        let meta = new_meta(Some(now + SignedDuration::from_hours(1)), now, "test");
+        let mut hosts = HostsFile::load(f.path())?;
+        hosts
+            .transaction()
+            .add("api.local", meta.clone())
+            .add("web.local", meta.clone())
+            .renew("api.local", now + SignedDuration::from_hours(2), now)
+            .commit()?;
+        assert_eq!(HostsFile::load(f.path())?.entries()?.len(), 3);
+
+        // a failing change rolls back the ones before it
+        let before = hosts.content().to_vec();
+        let err = hosts
+            .transaction()
+            .remove("api.local")
+            .add("db.example.com", meta)
+            .commit()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "change 2 of the transaction failed, no changes were made");
+        assert_eq!(hosts.content(), before);
+        assert_eq!(HostsFile::load(f.path())?.content(), before);
+        Ok(())
+    }
+}
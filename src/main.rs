@@ -1,27 +1,83 @@
-use crate::Entry::{Other, Supported};
+use crate::journal::{Journal, Operation};
+use crate::labels::{parse_label, parse_selector, parse_tag, Selector};
+use crate::undo::{Change, History};
 use anyhow::{anyhow, Context, Error};
-use clap::Parser;
-use jiff::{SignedDuration, Timestamp};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use eha::attrs;
+use eha::config::{self, Config};
+use eha::header::{FileConfig, ManagedBlock};
+use eha::manifest::Manifest;
+use eha::policy::{glob_match, Policy};
+use eha::trash::Trash;
+use eha::Entry::{Other, Supported};
+use eha::{
+    check_unchanged, content_hash, events, explain, extend_entry, identity, index, invalid_lines, managed_names, move_into_place,
+    new_entry, new_meta, parse_content, parse_lines, read_content, remove_entries, render, renew_entry, replace_file, set_disabled,
+    temp_path, validate_entry, validate_expire_minutes, validate_name, Entry, FileChanged, HostLine, LineEndings, SupportedMeta,
+};
+use jiff::{SignedDuration, Span, Timestamp};
 use rand::random;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::env::current_dir;
-use std::fs::{rename, File};
-use std::io::{BufRead, BufReader, Write};
-use std::ops::Add;
+use std::fmt::{Display, Formatter};
+use std::fs::{canonicalize, create_dir_all, symlink_metadata, File, OpenOptions};
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::{Add, Range};
+use std::path::{Path, PathBuf};
+
+mod audit;
+mod backup;
+mod diff;
+mod dns;
+mod fleet;
+mod init;
+mod journal;
+mod labels;
+mod managers;
+mod project;
+mod undo;
+#[cfg(any(windows, test))]
+mod windows;
 
 fn main() {
-    if let Err(e) = main_err() {
+    let mut check = false;
+    if let Err(e) = main_err(&mut check) {
+        // clap prints its own help, version and usage errors, and knows the status for each
+        if let Some(e) = e.downcast_ref::<clap::Error>() {
+            e.exit();
+        }
         for ee in e.chain() {
             eprintln!("{}", ee);
         }
+        std::process::exit(exit_code(&e, check));
     }
 }
 
-fn main_err() -> Result<(), Error> {
-    let args = Args::try_parse()?;
+fn main_err(check: &mut bool) -> Result<(), Error> {
+    let matches = Args::command().try_get_matches()?;
+    let mut args = Args::from_arg_matches(&matches)?;
+    *check = args.check;
+    args.apply_config(Config::load()?, &matches);
+    args.terminal = std::io::stdout().is_terminal();
+    // a diff is only a different way of showing what --test would write
+    args.test |= args.diff || args.check;
+    args.expand_shorthand()?;
+    args.read_names_file()?;
     args.validate()?;
-    if let Some(contents) = args.run()? {
-        println!("{}", contents);
+    #[cfg(windows)]
+    if args.elevate && !args.test && !args.read_only() && !windows::is_elevated() {
+        std::process::exit(windows::relaunch_elevated(&std::env::args().skip(1).collect::<Vec<String>>())?);
+    }
+    if let Some(contents) = args.run_with_retries()? {
+        // --test prints the new content as it would be written, which may already end with a newline
+        match contents.ends_with('\n') {
+            true => print!("{}", contents),
+            false => println!("{}", contents),
+        }
     }
     Ok(())
 }
@@ -35,282 +91,4513 @@ struct Args {
     #[command(subcommand)]
     subcommand: Subcommand,
 
-    #[clap(long, help = "Operate on the given hosts file.", default_value = "/etc/hosts")]
+    #[clap(long, env = "EHA_INPUT_FILE", help = "Operate on the given hosts file.", default_value_t = default_input_file())]
     input_file: String,
 
-    #[arg(long, help = "Print the new content to stdout instead of attempting to write the file.")]
+    #[arg(
+        long,
+        env = "EHA_TEST",
+        help = "Print the new content to stdout instead of attempting to write the file."
+    )]
     test: bool,
+
+    #[arg(
+        long,
+        env = "EHA_STATE_DIR",
+        help = "Directory for eha's own state such as the trash. Defaults to $XDG_STATE_HOME/eha or ~/.local/state/eha."
+    )]
+    state_dir: Option<String>,
+
+    #[arg(
+        long,
+        env = "EHA_TRASH_DAYS",
+        help = "Number of days to keep removed entries in the trash, 0 disables the trash.",
+        default_value = "7"
+    )]
+    trash_days: usize,
+
+    #[arg(long, env = "EHA_NOW", help = "Treat the given RFC3339 timestamp as the current time.")]
+    now: Option<Timestamp>,
+
+    #[arg(short, long, env = "EHA_YES", help = "Do not ask for confirmation before destructive operations.")]
+    yes: bool,
+
+    #[arg(
+        long,
+        env = "EHA_NO_FOLLOW_SYMLINKS",
+        help = "Replace a symlinked hosts file with a regular file instead of writing to the target of the link."
+    )]
+    no_follow_symlinks: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        env = "EHA_WRITE_STRATEGY",
+        help = "How to write changes to the hosts file.",
+        default_value = "auto"
+    )]
+    write_strategy: WriteStrategy,
+
+    #[arg(
+        long,
+        value_enum,
+        env = "EHA_OUTPUT",
+        help = "How to print results. Json covers list, show, status, and the commands that change the hosts file.",
+        default_value = "text"
+    )]
+    output: Output,
+
+    #[arg(
+        long,
+        env = "EHA_ENSURE_TRAILING_NEWLINE",
+        help = "End the hosts file with a newline when writing it, even if it didn't have one."
+    )]
+    ensure_trailing_newline: bool,
+
+    #[arg(
+        long,
+        env = "EHA_POLICY_FILE",
+        help = "Use this policy file instead of /etc/eha/policy.toml, unless that policy is locked."
+    )]
+    policy_file: Option<String>,
+
+    #[arg(
+        long,
+        env = "EHA_INDEX",
+        help = "Keep an index of entry offsets in the state directory to speed up lookups in very large hosts files."
+    )]
+    index: bool,
+
+    #[arg(
+        long,
+        env = "EHA_SANDBOX",
+        help = "Run against a temporary copy of the hosts file and state directory, then print the path and a diff of the copy."
+    )]
+    sandbox: bool,
+
+    #[arg(
+        long,
+        env = "EHA_KEEP_SANDBOX",
+        requires = "sandbox",
+        help = "Keep the sandbox directory after running with --sandbox, to look at its files, instead of removing it."
+    )]
+    keep_sandbox: bool,
+
+    #[arg(
+        long,
+        env = "EHA_DEFAULT_EXPIRE",
+        value_parser = parse_expire_minutes,
+        help = "Expiry of added entries in minutes or as a duration like 12h when --expire isn't given, instead of the \
+                default from the hosts file header or config files."
+    )]
+    default_expire: Option<usize>,
+
+    #[arg(
+        long = "suffix",
+        env = "EHA_SUFFIX",
+        value_delimiter = ',',
+        value_parser = parse_suffix,
+        help = "Allow names ending in this suffix, like .test, instead of the hosts file header or config files. Can be \
+                repeated, and is ignored when the policy sets its own suffixes."
+    )]
+    suffixes: Vec<String>,
+
+    #[arg(
+        long,
+        env = "EHA_OVERRIDE_LOCK",
+        help = "Change the hosts file even though an administrator locked it with an eha-locked comment."
+    )]
+    override_lock: bool,
+
+    #[arg(
+        long,
+        env = "EHA_ELEVATE",
+        help = "On Windows, relaunch eha with Administrator rights after a UAC prompt when changing the hosts file needs them."
+    )]
+    elevate: bool,
+
+    #[arg(
+        long,
+        env = "EHA_FLUSH_CACHE",
+        help = "Flush the DNS cache after changing the hosts file, so that running apps see the change. Only does anything on macOS, Windows, or with systemd-resolved."
+    )]
+    flush_cache: bool,
+
+    #[arg(
+        long,
+        env = "EHA_DIFF",
+        help = "Print a unified diff of the changes to the hosts file instead of writing it, like --test but without \
+                printing the whole file."
+    )]
+    diff: bool,
+
+    #[arg(
+        long,
+        env = "EHA_CHECK",
+        help = "Make no changes, but exit with status 1 if the command would change the hosts file, such as when entries \
+                have expired, 0 if it is already as it should be, and 2 if the command fails."
+    )]
+    check: bool,
+
+    #[arg(
+        long,
+        env = "EHA_RETRIES",
+        default_value_t = 3,
+        help = "Start again this many times when another program changes the hosts file while eha is changing it."
+    )]
+    retries: usize,
+
+    #[arg(
+        long,
+        env = "EHA_BACKUP",
+        help = "Copy the hosts file to <file>.eha-bak.<timestamp> next to it before changing it."
+    )]
+    backup: bool,
+
+    #[arg(
+        long,
+        env = "EHA_BACKUP_COUNT",
+        help = "Keep this many backups of the hosts file with --backup, removing older ones. Defaults to 5."
+    )]
+    backup_count: Option<usize>,
+
+    #[arg(
+        long,
+        env = "EHA_AUDIT_LOG",
+        help = "Record every change to the hosts file in this file instead of audit.jsonl in the state directory. Config \
+                files can set audit_log too."
+    )]
+    audit_log: Option<String>,
+
+    /// Defaults from the config files.
+    #[arg(skip)]
+    config: Config,
+
+    /// Where --sandbox creates its directory, the system temp directory unless tests give their own.
+    #[arg(skip)]
+    sandbox_base: Option<PathBuf>,
+
+    /// The query of tf-plan and tf-apply, read from stdin unless tests give their own.
+    #[arg(skip)]
+    terraform_query: Option<BTreeMap<String, String>>,
+
+    /// Whether stdout is a terminal, which gets tables rather than tab separated lines.
+    #[arg(skip)]
+    terminal: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+enum ConfigAction {
+    /// Print the effective configuration, after merging the config files, environment variables, and flags.
+    Show,
+    /// Print the effective value of one setting.
+    Get {
+        #[arg(help = "The setting to print, such as default_expire_minutes.")]
+        key: String,
+    },
+    /// Change a setting in the user's config file, or the system one with --system.
+    Set {
+        #[arg(help = "The setting to change, such as default_expire_minutes.")]
+        key: String,
+
+        #[arg(help = "The new value, with lists like allowed_suffixes given comma separated.")]
+        value: String,
+
+        #[arg(long, help = "Change the system config file /etc/eha.toml instead of the user's.")]
+        system: bool,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+enum AuditLogAction {
+    /// Print the most recent changes, oldest first.
+    Tail {
+        #[arg(short = 'n', long, default_value_t = 10, help = "The number of changes to print.")]
+        lines: usize,
+
+        #[arg(long, help = "Print the records as json lines.")]
+        json: bool,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+enum CiAction {
+    /// Print a new scope token to pass to `eha add --scope` for the duration of a job.
+    Begin,
+    /// Remove every entry added under a scope token.
+    End {
+        #[arg(
+            help = "The scope token from `eha ci begin`.",
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        token: Option<String>,
+
+        #[arg(long, help = "Remove the entries of every scope, for example to clean up after crashed jobs.")]
+        all: bool,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+enum SessionAction {
+    /// Print a new session id. Entries added while it is exported as EHA_SESSION are recorded under the session.
+    Start,
+    /// Remove every entry added in a session.
+    End {
+        #[arg(
+            help = "The session id from `eha session start`.",
+            env = "EHA_SESSION",
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        id: Option<String>,
+
+        #[arg(long, help = "Remove the entries of every session and CI scope.")]
+        all: bool,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
+enum FleetAction {
+    /// Run `eha apply` with the manifest on every host and report whether each one changed.
+    Apply {
+        #[arg(help = "Path to a json or yaml manifest of the entries that should exist.")]
+        manifest: String,
+
+        #[arg(long, help = "Path to a file listing one ssh destination per line.")]
+        hosts: String,
+
+        #[arg(long, help = "Only report drift on each host, without making changes.")]
+        check: bool,
+
+        #[arg(long, help = "Remove entries added by eha that are not in the manifest.")]
+        prune: bool,
+
+        #[arg(
+            long,
+            help = "The ssh command used to connect to each host.",
+            default_value = "ssh -o BatchMode=yes"
+        )]
+        ssh_command: String,
+
+        #[arg(long, help = "The command to run on each host.", default_value = "eha apply")]
+        remote_command: String,
+    },
+}
+
+/// The ways eha can write the hosts file. Renaming a temp file over the original is atomic but creates a new inode,
+/// which breaks bind mounts and hard links. Writing in place keeps the inode but a crash part way through can leave a
+/// partially written file.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteStrategy {
+    /// Append or rewrite only the managed region in place when possible, otherwise rename a temp file over the original.
+    Auto,
+    /// Always write a temp file and rename it over the original.
+    Rename,
+    /// Always write in place: append or rewrite the managed region when possible, otherwise truncate and rewrite the
+    /// whole file, then fsync.
+    InPlace,
+}
+
+/// How eha prints its results.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Text meant for reading in a terminal.
+    Text,
+    /// Json for scripts and editors to consume.
+    Json,
 }
 
 #[derive(Parser, Debug, Clone)]
 enum Subcommand {
-    /// Add a new DNS name for 127.0.0.1.
+    /// Add new DNS names for 127.0.0.1 or another address.
     Add {
-        #[arg(help = "The DNS name ending in .local or .localhost to add.")]
+        #[arg(
+            required_unless_present = "from_file",
+            help = "The DNS names ending in .local or .localhost to add, all written at once."
+        )]
+        names: Vec<String>,
+
+        #[arg(
+            long,
+            help = "Also add the names in this file, one per line, or - to read them from stdin. Blank lines and lines \
+                    starting with # are ignored."
+        )]
+        from_file: Option<String>,
+
+        #[arg(
+            short,
+            long = "expire",
+            visible_alias = "expire-minutes",
+            value_parser = parse_expire_minutes,
+            help = "Expiry for the entry in minutes or as a duration like 12h or 7d, the entry is subject to removal after \
+                    this time. Defaults to the default_expire_minutes of the eha-config header in the hosts file, or 1440."
+        )]
+        expire_minutes: Option<usize>,
+
+        #[arg(
+            long,
+            conflicts_with = "expire_minutes",
+            help = "Expire the entry at an exact RFC3339 timestamp, like 2030-01-01T17:00:00Z, instead of after a duration."
+        )]
+        expire_at: Option<Timestamp>,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["expire_minutes", "expire_at"],
+            help = "Never expire the entry, so that only remove takes it out of the file."
+        )]
+        no_expire: bool,
+
+        #[arg(
+            long,
+            help = "The address the name resolves to, such as a docker bridge or a machine on the LAN. Defaults to 127.0.0.1."
+        )]
+        ip: Option<IpAddr>,
+
+        #[arg(
+            long,
+            conflicts_with = "ip",
+            help = "Also add a ::1 line for the name, for stacks that look up IPv6 addresses first."
+        )]
+        ipv6: bool,
+
+        #[arg(
+            long = "alias",
+            help = "Another DNS name for the same line, can be repeated. Removing any of the names removes the line."
+        )]
+        aliases: Vec<String>,
+
+        #[arg(
+            long,
+            env = "EHA_SESSION",
+            help = "Record the entry under a session id from `eha session start` or a CI scope token from `eha ci begin`."
+        )]
+        scope: Option<String>,
+
+        #[arg(long, help = "Record what added the entry, for use by integrations.", default_value = "cli")]
+        source: String,
+
+        #[arg(long = "label", value_parser = parse_label, help = "Attach a key=value label to the entry, can be repeated.")]
+        labels: Vec<(String, String)>,
+
+        #[arg(long = "tag", value_parser = parse_tag, help = "Tag the entry to group it with others, can be repeated.")]
+        tags: Vec<String>,
+
+        #[arg(long, help = "Fail if a name was already added, instead of refreshing it with the given options.")]
+        no_clobber: bool,
+    },
+    /// Extend the expiry of an entry added by eha, keeping its comment and other metadata, and any later expiry.
+    Renew {
+        #[arg(
+            help = "The DNS name ending in .local or .localhost to renew.",
+            required_unless_present = "selector",
+            conflicts_with = "selector"
+        )]
+        name: Option<String>,
+
+        #[arg(
+            short,
+            long = "expire",
+            visible_alias = "expire-minutes",
+            value_parser = parse_expire_minutes,
+            help = "New expiry from now for the entry in minutes or as a duration like 12h or 7d. Defaults to the \
+                    default_expire_minutes of the eha-config header in the hosts file, or 1440."
+        )]
+        expire_minutes: Option<usize>,
+
+        #[arg(
+            long,
+            value_parser = parse_selector,
+            help = "Renew every entry with an expiry whose labels match a selector like env=staging,owner!=core."
+        )]
+        selector: Option<Selector>,
+    },
+    /// Set the expiry of an entry added by eha to an exact time or a duration from now, keeping its comment.
+    SetExpiry {
+        #[arg(help = "The DNS name ending in .local or .localhost to change.")]
         name: String,
 
+        #[arg(value_parser = parse_expiry, help = "The new expiry as an RFC3339 timestamp, or a duration from now like 2h.")]
+        expiry: When,
+    },
+    /// Extend the expiry of every unexpired and enabled entry added by eha, keeping any later expiry.
+    RenewAll {
         #[arg(
             short,
+            long = "expire",
+            visible_alias = "expire-minutes",
+            value_parser = parse_expire_minutes,
+            help = "New expiry from now for the entries in minutes or as a duration like 12h or 7d. Defaults to the \
+                    default_expire_minutes of the eha-config header in the hosts file, or 1440."
+        )]
+        expire_minutes: Option<usize>,
+
+        #[arg(
             long,
-            help = "Expiry in minutes for the entry, the entry is subject to removal after this time.",
-            default_value = "1440"
+            value_parser = parse_selector,
+            help = "Only renew entries whose labels match a selector like env=staging,owner!=core."
         )]
-        expire_minutes: usize,
+        selector: Option<Selector>,
     },
     /// Remove a DNS name added by eha.
+    #[command(visible_alias = "rm")]
     Remove {
-        #[arg(help = "The DNS name ending in .local or .localhost to remove.")]
+        #[arg(
+            help = "The DNS name ending in .local or .localhost to remove, or a glob like 'myapp-*.local' to remove every \
+                    matching name. Choose interactively if omitted in a terminal.",
+            conflicts_with = "all"
+        )]
+        name: Option<String>,
+
+        #[arg(long, help = "Remove every entry added by eha.")]
+        all: bool,
+
+        #[arg(
+            long,
+            help = "Remove every entry added by the given source, such as cli or apply.",
+            conflicts_with_all = ["name", "all"]
+        )]
+        source: Option<String>,
+
+        #[arg(
+            long,
+            value_parser = parse_selector,
+            help = "Remove every entry whose labels match a selector like env=staging,owner!=core.",
+            conflicts_with_all = ["name", "all"]
+        )]
+        selector: Option<Selector>,
+
+        #[arg(
+            long,
+            value_parser = Regex::new,
+            help = "Remove every entry whose name matches a regular expression like '^pr-[0-9]+\\.ci\\.local$'.",
+            conflicts_with_all = ["name", "all"]
+        )]
+        regex: Option<Regex>,
+
+        #[arg(
+            long,
+            value_parser = parse_tag,
+            help = "Remove every entry with the given tag.",
+            conflicts_with_all = ["name", "all"]
+        )]
+        tag: Option<String>,
+    },
+    /// List the entries added by eha with their expiry and comment, including expired ones.
+    #[command(visible_alias = "ls")]
+    List {
+        #[arg(long, value_parser = parse_tag, help = "Only list entries with the given tag.")]
+        tag: Option<String>,
+
+        #[arg(
+            long,
+            value_parser = parse_selector,
+            help = "Only list entries whose labels match a selector like env=staging,owner!=core."
+        )]
+        selector: Option<Selector>,
+    },
+    /// Print the address, expiry, comment, and line of one entry added by eha, failing if there isn't one.
+    Show {
+        #[arg(help = "The DNS name, or one of its aliases, to show.")]
         name: String,
     },
+    /// Print how many entries eha manages, how many have expired, the next expiry, and whether the file is writable.
+    Status,
+    /// Remove every entry added by eha, leaving the rest of the file untouched.
+    Clear,
     /// Remove any expired entries added by eha.
-    RemoveExpired,
-}
+    #[command(visible_alias = "gc")]
+    RemoveExpired {
+        #[arg(long, help = "Print the entries that would be removed instead of removing them.")]
+        dry_run: bool,
 
-impl Args {
-    fn validate(&self) -> Result<(), Error> {
-        match &self.subcommand {
-            Subcommand::Add { name, expire_minutes } => {
-                if !name.ends_with(".local") && !name.ends_with(".localhost") {
-                    Err(anyhow!("name must end in .local or .localhost"))
-                } else if !(1..525600).contains(expire_minutes) {
-                    Err(anyhow!("ttl minutes must be between 1m and 365d (inclusive)"))
-                } else {
-                    for (i, x) in name.split('.').enumerate() {
-                        let l = x.len();
-                        if l == 0 {
-                            return Err(anyhow!("invalid DNS name #{}: cannot be empty", i));
-                        } else if let Some((j, c, _)) = x.chars().enumerate().map(|(a, b)| (a, b, l)).find(invalid_dns_name_char) {
-                            return Err(anyhow!("invalid DNS name char in part #{} @ {}: {}", i, j, c));
-                        }
-                    }
-                    Ok(())
-                }
-            }
-            Subcommand::Remove { .. } => Ok(()),
-            Subcommand::RemoveExpired => Ok(()),
-        }
-    }
+        #[arg(
+            long,
+            requires = "dry_run",
+            value_parser = parse_when,
+            help = "Check expiry at the given RFC3339 timestamp or offset from now like +3d instead of the current time."
+        )]
+        at: Option<When>,
 
-    fn run(&self) -> Result<Option<String>, Error> {
-        let mut entries: Vec<Entry> = Vec::new();
-        {
-            let file = File::open(&self.input_file).context("failed to read input file")?;
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
-                let line = line.context("failed to read line")?;
-                entries.push(Entry::from(line.as_str()));
-            }
-        }
-        eprintln!("read {} entries from existing file {}", entries.len(), &self.input_file);
+        #[arg(long, value_parser = parse_tag, help = "Only remove expired entries with the given tag.")]
+        tag: Option<String>,
+    },
+    /// Remove entries added by eha that were created a long time ago, regardless of their expiry.
+    Prune {
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            help = "Remove entries created longer ago than this duration, for example 30d."
+        )]
+        older_than: SignedDuration,
+    },
+    /// Restore a recently removed DNS name from the trash with its original metadata, or the whole hosts file from a
+    /// backup made with --backup.
+    Restore {
+        #[arg(
+            required_unless_present_any = ["list", "backup"],
+            conflicts_with_all = ["list", "backup"],
+            help = "The DNS name ending in .local or .localhost to restore."
+        )]
+        name: Option<String>,
 
-        let now = Timestamp::now();
-        entries.retain_mut(|e| match e {
-            Supported { meta, .. } => meta.expiry > now,
-            Other(_) => true,
-        });
+        #[arg(
+            short,
+            long = "expire",
+            visible_alias = "expire-minutes",
+            value_parser = parse_expire_minutes,
+            help = "Expiry for the restored entry in minutes or as a duration like 12h or 7d, required if the original \
+                    expiry has already passed."
+        )]
+        expire_minutes: Option<usize>,
 
-        match &self.subcommand {
-            Subcommand::Add { name, expire_minutes } => {
-                entries.push(Supported {
-                    name: name.to_string(),
-                    meta: SupportedMeta {
-                        expiry: now.add(SignedDuration::from_mins(*expire_minutes as i64)),
-                        comment: Some(format!("set from {} at {}", current_dir().unwrap_or_default().to_string_lossy(), &now,).to_string()),
-                    },
-                });
-            }
-            Subcommand::Remove { name } => {
-                let n = name;
-                entries.retain_mut(|e| match e {
-                    Supported { name, .. } => name.ne(&n),
-                    Other(_) => true,
-                })
-            }
-            Subcommand::RemoveExpired => {}
-        }
+        #[arg(long, conflicts_with = "backup", help = "List the backups of the hosts file, oldest first.")]
+        list: bool,
 
-        if self.test {
-            return Ok(Some(entries.iter().map(String::from).collect::<Vec<String>>().join("\n")));
-        }
+        #[arg(
+            long,
+            conflicts_with = "expire_minutes",
+            help = "Replace the hosts file with the backup with this id, as shown by --list."
+        )]
+        backup: Option<String>,
+    },
+    /// Make the entries added by eha match a json or yaml manifest, printing changed=true or changed=false.
+    Apply {
+        #[arg(help = "Path to a json or yaml manifest of the entries that should exist, or - to read it from stdin.")]
+        manifest: String,
 
-        let mut temp_file_path = std::env::temp_dir();
-        temp_file_path.push(format!("hosts{}", random::<u32>()));
-        eprintln!(
-            "writing to {} and moving to {}",
-            &temp_file_path.to_string_lossy(),
-            &self.input_file
-        );
-        let mut file = File::create(&temp_file_path).context("failed to create temp file")?;
-        file.write_all(entries.iter().map(String::from).collect::<Vec<String>>().join("\n").as_bytes())
-            .context("failed to write content")?;
-        rename(&temp_file_path, &self.input_file).context("failed to rename temp file to input file")?;
-        Ok(None)
-    }
-}
+        #[arg(long, help = "Only report whether changes would be made, without making them.")]
+        check: bool,
 
-/// Returns whether the given character is invalid in a DNS name. This designed to be used as a
-/// chained filter.
-fn invalid_dns_name_char(bits: &(usize, char, usize)) -> bool {
-    let (index, c, part_len) = *bits;
+        #[arg(long, help = "Remove entries added by eha that are not in the manifest.")]
+        prune: bool,
+    },
+    /// Terraform external data source: print the changes a json query on stdin would make, without making them.
+    TfPlan,
+    /// Terraform external data source: apply a json query on stdin and print the results.
+    TfApply,
+    /// Apply a manifest to many remote machines over ssh.
+    Fleet {
+        #[command(subcommand)]
+        action: FleetAction,
+    },
+    /// Print or change the defaults from the config files.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Show the log of changes made to the hosts file.
+    AuditLog {
+        #[command(subcommand)]
+        action: AuditLogAction,
+    },
+    /// Manage job scoped entries on CI runners.
+    Ci {
+        #[command(subcommand)]
+        action: CiAction,
+    },
+    /// Group the entries added in a shell or script so they can be removed together.
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+    /// Prepare a machine for eha by adding an eha-config header to the hosts file and creating the state directory.
+    Init {
+        #[arg(
+            long,
+            help = "Also install and enable a job that removes expired entries hourly, a systemd timer or a launchd daemon \
+                    on macOS."
+        )]
+        timer: bool,
 
-    // cannot be longer than 63
-    if part_len > 63 {
-        return true;
-    }
-    // cannot start or end with -
-    if c == '-' && (index == 0 || index == part_len - 1) {
-        return true;
-    }
-    // must be valid char
-    !c.is_ascii_alphanumeric() && c != '-'
-}
+        #[arg(
+            long,
+            help = "Where to install the systemd units, or the launchd daemon on macOS.",
+            default_value_t = init::default_unit_dir()
+        )]
+        unit_dir: String,
+    },
+    /// Describe how eha parses a line of the hosts file and what it would do with it.
+    Explain {
+        #[arg(
+            long,
+            help = "The line number to explain, starting from 1.",
+            required_unless_present = "name",
+            conflicts_with = "name"
+        )]
+        line: Option<usize>,
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct SupportedMeta {
-    expiry: Timestamp,
-    comment: Option<String>,
-}
+        #[arg(long, help = "Explain the first line mentioning this name.")]
+        name: Option<String>,
+    },
+    /// Exit successfully if an enabled, unexpired entry with the given name exists, or with an error otherwise.
+    Exists {
+        #[arg(help = "The DNS name to look for.")]
+        name: String,
+    },
+    /// Print a json line for each entry in the hosts file and, when following, for each change made to it.
+    Events {
+        #[arg(long, help = "Keep watching the hosts file and print events as it changes.")]
+        follow: bool,
 
-enum Entry {
-    Supported { name: String, meta: SupportedMeta },
-    Other(String),
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            help = "How often to check the hosts file for changes when following.",
+            default_value = "1s"
+        )]
+        interval: SignedDuration,
+    },
+    /// Check for common reasons that entries added by eha don't resolve, like the resolver configuration ignoring the
+    /// hosts file.
+    Doctor,
+    /// Print a one line summary of entries expiring soon, or nothing, for use in shell prompts and login messages.
+    Motd {
+        #[arg(
+            long,
+            value_parser = parse_duration,
+            help = "Mention entries expiring within this duration, for example 30m.",
+            default_value = "1h"
+        )]
+        within: SignedDuration,
+    },
+    /// Disable a DNS name added by eha by commenting it out, preserving its metadata.
+    Disable {
+        #[arg(help = "The DNS name ending in .local or .localhost to disable.")]
+        name: String,
+    },
+    /// Enable a DNS name that was previously disabled.
+    Enable {
+        #[arg(help = "The DNS name ending in .local or .localhost to enable.")]
+        name: String,
+    },
+    /// Reverse the last change eha made to the hosts file, putting back the entries it removed and removing the ones it
+    /// added. Running it again redoes the change.
+    Undo,
+    /// `eha foo.local` is shorthand for `eha add foo.local`.
+    #[command(external_subcommand)]
+    Shorthand(Vec<String>),
 }
 
-impl From<&str> for Entry {
-    fn from(value: &str) -> Self {
-        if let Some((a, b)) = value.split_once("# eha ") {
-            if let Some(name) = a.split_whitespace().last() {
-                return Supported {
-                    name: name.to_string(),
-                    meta: serde_json::from_str(b).unwrap_or_default(),
-                };
+impl Args {
+    /// Replaces an unknown subcommand that looks like a DNS name with an add of that name, so that `eha foo.local -e 60`
+    /// means `eha add foo.local -e 60`.
+    fn expand_shorthand(&mut self) -> Result<(), Error> {
+        if let Subcommand::Shorthand(rest) = &self.subcommand {
+            match rest.first() {
+                Some(name) if name.contains('.') => {
+                    self.subcommand = Subcommand::try_parse_from(["eha", "add"].into_iter().chain(rest.iter().map(String::as_str)))?;
+                }
+                other => return Err(anyhow!("unrecognized subcommand '{}'", other.map_or("", String::as_str))),
             }
         }
-        Other(value.to_string())
+        Ok(())
     }
-}
 
-impl From<&Entry> for String {
-    fn from(value: &Entry) -> Self {
-        match value {
-            Supported { name, meta } => format!(
-                "127.0.0.1\t{}\t# eha {}",
-                name,
-                serde_json::to_string(meta).unwrap_or_else(|e| e.to_string())
-            ),
-            Other(raw) => raw.to_string(),
+    /// Reads the names given with --from-file into the names to add, so that they are validated and written with the
+    /// rest.
+    fn read_names_file(&mut self) -> Result<(), Error> {
+        if let Subcommand::Add { names, from_file, .. } = &mut self.subcommand {
+            if let Some(path) = from_file.take() {
+                let mut raw = String::new();
+                if path == "-" {
+                    std::io::stdin()
+                        .read_to_string(&mut raw)
+                        .context("failed to read names from stdin")?;
+                } else {
+                    File::open(&path)
+                        .and_then(|mut f| f.read_to_string(&mut raw))
+                        .with_context(|| format!("failed to read names from {}", path))?;
+                }
+                names.extend(
+                    raw.lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                        .map(str::to_string),
+                );
+            }
         }
+        Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Read;
-    use tempfile::NamedTempFile;
-
-    #[test]
-    fn test_no_op() -> Result<(), Error> {
-        let mut f = NamedTempFile::new()?;
-        let input = r##"# some leading comments followed by whitespace
 
-127.0.0.1   localhost
+    fn validate(&self) -> Result<(), Error> {
+        if self.no_follow_symlinks && self.write_strategy == WriteStrategy::InPlace {
+            return Err(anyhow!(
+                "writing in place always follows symlinks, so it can't be used with --no-follow-symlinks"
+            ));
+        }
+        if self.elevate && !cfg!(windows) {
+            return Err(anyhow!("--elevate is only supported on Windows, run eha with sudo instead"));
+        }
+        if self.backup_count() == 0 {
+            return Err(anyhow!("backup count must be at least 1"));
+        }
+        match &self.subcommand {
+            Subcommand::Add {
+                names,
+                expire_minutes,
+                expire_at,
+                no_expire,
+                ip,
+                aliases,
+                ..
+            } => {
+                let policy = self.policy()?;
+                if names.len() > 1 && !aliases.is_empty() {
+                    return Err(anyhow!("aliases can only be given when adding a single name"));
+                }
+                for name in names {
+                    validate_entry(name, ip.or(self.config.default_ip), &policy)?;
+                }
+                for alias in aliases {
+                    validate_name(alias, &policy)?;
+                }
+                match expire_at {
+                    _ if *no_expire => policy.check_no_expire(),
+                    Some(at) => {
+                        let remaining = at.duration_since(self.now.unwrap_or_else(Timestamp::now));
+                        if !remaining.is_positive() {
+                            return Err(anyhow!("expire-at {} is not in the future", at));
+                        }
+                        validate_expire_minutes((remaining.as_secs() as u64).div_ceil(60) as usize, &policy)
+                    }
+                    None => validate_expire_minutes(self.add_expire_minutes(*expire_minutes)?, &policy),
+                }
+            }
+            Subcommand::Renew { expire_minutes, .. } | Subcommand::RenewAll { expire_minutes, .. } => {
+                validate_expire_minutes(self.add_expire_minutes(*expire_minutes)?, &self.policy()?)
+            }
+            Subcommand::SetExpiry { expiry, .. } => {
+                let now = self.now.unwrap_or_else(Timestamp::now);
+                let remaining = expiry.resolve(now).duration_since(now);
+                if !remaining.is_positive() {
+                    return Err(anyhow!("the new expiry is not in the future, use remove to remove the entry"));
+                }
+                validate_expire_minutes((remaining.as_secs() as u64).div_ceil(60) as usize, &self.policy()?)
+            }
+            Subcommand::Remove { .. } => Ok(()),
+            Subcommand::Clear => Ok(()),
+            Subcommand::RemoveExpired { .. } => Ok(()),
+            Subcommand::Prune { older_than } => {
+                if older_than.is_positive() {
+                    Ok(())
+                } else {
+                    Err(anyhow!("older-than duration must be positive"))
+                }
+            }
+            Subcommand::Restore { expire_minutes, .. } => match expire_minutes {
+                Some(m) => validate_expire_minutes(*m, &self.policy()?),
+                None => Ok(()),
+            },
+            Subcommand::Apply { .. } => Ok(()),
+            Subcommand::TfPlan | Subcommand::TfApply => Ok(()),
+            Subcommand::Fleet { .. } => Ok(()),
+            Subcommand::Config { .. } | Subcommand::AuditLog { .. } => Ok(()),
+            Subcommand::Ci { .. } | Subcommand::Session { .. } => Ok(()),
+            Subcommand::Init { timer: true, .. } => init::check_timer_supported(),
+            Subcommand::Init { .. } => Ok(()),
+            Subcommand::Explain { .. } | Subcommand::Exists { .. } => Ok(()),
+            Subcommand::Events { interval, .. } => {
+                if interval.is_positive() {
+                    Ok(())
+                } else {
+                    Err(anyhow!("interval must be positive"))
+                }
+            }
+            Subcommand::Motd { within } => {
+                if within.is_positive() {
+                    Ok(())
+                } else {
+                    Err(anyhow!("within duration must be positive"))
+                }
+            }
+            Subcommand::List { .. } | Subcommand::Show { .. } | Subcommand::Status | Subcommand::Doctor => Ok(()),
+            Subcommand::Disable { .. } => Ok(()),
+            Subcommand::Enable { .. } => Ok(()),
+            Subcommand::Undo => Ok(()),
+            Subcommand::Shorthand(_) => unreachable!("shorthand is expanded before validating"),
+        }
+    }
+
+    fn run(&self) -> Result<Option<String>, Error> {
+        if self.sandbox {
+            return self.run_sandboxed();
+        }
+        match &self.subcommand {
+            Subcommand::Ci { action: CiAction::Begin } => return Ok(Some(format!("ci-{:016x}", random::<u64>()))),
+            Subcommand::Session {
+                action: SessionAction::Start,
+            } => return Ok(Some(format!("session-{:016x}", random::<u64>()))),
+            Subcommand::Fleet {
+                action:
+                    FleetAction::Apply {
+                        manifest,
+                        hosts,
+                        check,
+                        prune,
+                        ssh_command,
+                        remote_command,
+                    },
+            } => return fleet::apply(manifest, hosts, ssh_command, remote_command, *check, *prune).map(Some),
+            // only the lines of the entry are read from the index, without parsing the rest of the file
+            Subcommand::Exists { name } | Subcommand::Show { name } if self.index => {
+                let lines = index::lookup(&self.input_file, &self.state_dir()?, name)?;
+                let entries = lines
+                    .iter()
+                    .map(|l| Entry::try_from(l.as_str()))
+                    .collect::<Result<Vec<Entry>, Error>>()?;
+                return self.query(&entries, self.now.unwrap_or_else(Timestamp::now));
+            }
+            // removing a name that isn't in the file has nothing to write, which the index tells without reading all of it
+            Subcommand::Remove {
+                name: Some(name),
+                all: false,
+                source: None,
+                selector: None,
+                regex: None,
+                tag: None,
+            } if self.index
+                && !self.test
+                && self.output == Output::Text
+                && !name.contains(['*', '?'])
+                && index::lookup(&self.input_file, &self.state_dir()?, name)?.is_empty() =>
+            {
+                eprintln!("no entry with name {} in {}", name, self.input_file);
+                return Ok(None);
+            }
+            Subcommand::Events { follow, interval } => {
+                return events::watch(&self.input_file, *follow, *interval, &mut std::io::stdout().lock()).map(|_| None)
+            }
+            Subcommand::Config { action } => return self.run_config(action),
+            Subcommand::AuditLog {
+                action: AuditLogAction::Tail { lines, json },
+            } => {
+                let records = audit::tail(&self.audit_log()?, *lines)?;
+                let lines = records
+                    .iter()
+                    .map(|r| match json {
+                        true => serde_json::to_string(r).map_err(Error::new),
+                        false => Ok(audit::render(r)),
+                    })
+                    .collect::<Result<Vec<String>, Error>>()?;
+                return Ok(Some(lines.join("\n")).filter(|s| !s.is_empty()));
+            }
+            Subcommand::Restore { list: true, .. } => {
+                let backups = backup::list(&self.write_path()?)?;
+                let lines = backups.iter().map(|(id, path)| format!("{}\t{}", id, path.to_string_lossy()));
+                return Ok(Some(lines.collect::<Vec<String>>().join("\n")).filter(|s| !s.is_empty()));
+            }
+            _ => {}
+        }
+        // held until the end so that nothing can change the file between reading and writing it
+        let _lock = if !self.test && !self.read_only() {
+            Some(attrs::lock_for_update(&self.write_path()?).map_err(advise_elevation)?)
+        } else {
+            None
+        };
+        if !self.test && !self.read_only() {
+            attrs::check_mutable(&self.write_path()?)?;
+            for m in Journal::new(&self.state_dir()?, &self.write_path()?).recover(|op| self.confirm_resume(op))? {
+                eprintln!("{}", m);
+            }
+        }
+        let content = read_content(&self.input_file)?;
+        if !self.read_only() && !self.override_lock {
+            if let Some((line, reason)) = FileConfig::find_lock(&String::from_utf8_lossy(&content)) {
+                return Err(anyhow!(
+                    "{} is locked against changes by eha on line {}{}, pass --override-lock to change it anyway",
+                    self.input_file,
+                    line,
+                    if reason.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", reason)
+                    }
+                ));
+            }
+        }
+        let now = self.now.unwrap_or_else(Timestamp::now);
+        if let Subcommand::Explain { line, name } = &self.subcommand {
+            return explain::explain(&String::from_utf8_lossy(&content), *line, name.as_deref(), now, &self.policy()?).map(Some);
+        }
+        // restoring a backup starts from its entries instead, which then go through the same checks and write
+        let from_backup = match &self.subcommand {
+            Subcommand::Restore { backup: Some(id), .. } => {
+                let path = backup::find(&self.write_path()?, id)?;
+                let backup = read_content(&path.to_string_lossy())?;
+                Some((path.to_string_lossy().to_string(), backup))
+            }
+            _ => None,
+        };
+        // the byte range of each line that was read, since rewriting only part of the file starts at the end of one
+        let (spans, mut entries): (Vec<Range<usize>>, Vec<Entry>) =
+            parse_lines(from_backup.as_ref().map_or(&content, |(_, backup)| backup))
+                .into_iter()
+                .unzip();
+        let endings = self.line_endings(from_backup.as_ref().map_or(&content, |(_, backup)| backup));
+        // shell prompts call motd all the time, so it stays quiet on stderr
+        if !matches!(self.subcommand, Subcommand::Motd { .. }) {
+            let path = from_backup.as_ref().map_or(&self.input_file, |(path, _)| path);
+            for w in invalid_lines(path, &entries) {
+                eprintln!("{}", w);
+            }
+        }
+        match &self.subcommand {
+            Subcommand::Motd { within } => return Ok(motd(&entries, now, *within)),
+            Subcommand::List { tag, selector } => {
+                let listed = |meta: &SupportedMeta| has_tag(meta, tag.as_deref()) && selects(selector.as_ref(), meta);
+                return match self.output {
+                    Output::Json => {
+                        let listed = list_json(&entries, now, |e| matches!(e, Supported { meta, .. } if listed(meta)));
+                        Ok(Some(serde_json::to_string_pretty(&listed)?))
+                    }
+                    // a terminal gets an aligned table, while scripts keep reading tab separated lines
+                    Output::Text if self.terminal => Ok(list_table(&entries, now, listed, self.colour())),
+                    Output::Text => Ok(list(&entries, now, listed)),
+                };
+            }
+            Subcommand::Show { .. } | Subcommand::Exists { .. } => return self.query(&entries, now),
+            Subcommand::Doctor => return Ok(Some(doctor(&entries))),
+            Subcommand::Status => {
+                let writable = OpenOptions::new()
+                    .write(true)
+                    .open(self.write_path()?)
+                    .map_err(Error::new)
+                    .and_then(|_| attrs::check_mutable(&self.write_path()?));
+                let status = status(&entries, now, writable);
+                return Ok(Some(match self.output {
+                    Output::Text => status.text(now),
+                    Output::Json => serde_json::to_string_pretty(&status)?,
+                }));
+            }
+            _ => {}
+        }
+        eprintln!("read {} entries from existing file {}", entries.len(), &self.input_file);
+        for w in managers::clobber_warnings(&sections(&entries)) {
+            eprintln!("{}", w);
+        }
+
+        if let Subcommand::RemoveExpired { dry_run: true, at, tag } = &self.subcommand {
+            let at = at.map_or(now, |w| w.resolve(now));
+            let expired = entries
+                .iter()
+                .filter_map(|e| match e {
+                    Supported { name, meta, .. } if has_tag(meta, tag.as_deref()) => meta.expiry.filter(|e| *e <= at).map(|e| (name, e)),
+                    _ => None,
+                })
+                .collect::<Vec<(&String, Timestamp)>>();
+            eprintln!("{} entries would be removed by {}", expired.len(), at);
+            if self.check && !expired.is_empty() {
+                let described = expired.iter().map(|(name, e)| format!("expired {} at {}", name, e));
+                return Err(self.would_change(&described.collect::<Vec<String>>().join(", ")));
+            }
+            let lines = expired.iter().map(|(name, e)| format!("{}\t{}", name, e));
+            return Ok((!expired.is_empty()).then(|| lines.collect::<Vec<String>>().join("\n")));
+        }
+
+        // Unmanaged lines before the first managed entry are never modified, so we only need to rewrite from there on.
+        let mut region_start = entries
+            .iter()
+            .position(|e| matches!(e, Supported { .. }))
+            .filter(|_| from_backup.is_none());
+        let read_count = entries.len();
+        // remove-expired with a tag leaves the expired entries of other tags for later
+        let expire_tag = match &self.subcommand {
+            Subcommand::RemoveExpired { tag, .. } => tag.as_deref(),
+            _ => None,
+        };
+        entries.retain_mut(|e| match e {
+            Supported { meta, .. } => !meta.expired(now) || !has_tag(meta, expire_tag),
+            Other(_) => true,
+        });
+        let expired_count = read_count - entries.len();
+        let managed_before = managed_names(&entries).len();
+
+        let mut trash = match &self.subcommand {
+            Subcommand::Remove { .. } | Subcommand::Clear | Subcommand::Prune { .. } | Subcommand::Restore { name: Some(_), .. }
+                if self.trash_days > 0 =>
+            {
+                Some(Trash::load(&self.state_dir()?, now, self.trash_days)?)
+            }
+            _ => None,
+        };
+
+        // an optional message to print once the changes have been written
+        let mut message = None;
+        // the number of new entries at the end of the file, when they are the only change
+        let mut appended = 0;
+        match &self.subcommand {
+            Subcommand::Add {
+                names,
+                expire_minutes,
+                expire_at,
+                no_expire,
+                ip,
+                ipv6,
+                aliases,
+                scope,
+                source,
+                labels,
+                tags,
+                no_clobber,
+                from_file: _,
+            } => {
+                let expiry = match expire_at {
+                    _ if *no_expire => None,
+                    Some(at) => Some(*at),
+                    None => Some(now.add(SignedDuration::from_mins(self.add_expire_minutes(*expire_minutes)? as i64))),
+                };
+                let meta = SupportedMeta {
+                    ip: ip.or(self.config.default_ip),
+                    aliases: aliases.clone(),
+                    scope: scope.clone(),
+                    labels: labels.iter().cloned().collect(),
+                    tags: tags.clone(),
+                    project: current_dir().ok().and_then(|d| project::detect(&d)),
+                    host: identity::hostname(),
+                    command: std::env::args().collect(),
+                    ..new_meta(expiry, now, source)
+                };
+                let mut added = Vec::new();
+                let mut refreshed = false;
+                for name in names {
+                    // adding a name again refreshes the existing lines rather than duplicating them
+                    if entries.iter().any(|e| e.has_name(name)) {
+                        if *no_clobber {
+                            return Err(anyhow!("an entry with name {} already exists", name));
+                        }
+                        if let Some(alias) = aliases.iter().find(|a| entries.iter().any(|e| e.has_name(a) && !e.has_name(name))) {
+                            return Err(anyhow!(
+                                "{} is already another entry, so it can't become an alias of {}",
+                                alias,
+                                name
+                            ));
+                        }
+                        let localhost6 = Some(IpAddr::V6(Ipv6Addr::LOCALHOST));
+                        // a new address replaces the addresses of the name, since a name with its own address isn't dual stack
+                        let other_address = |e: &Entry| matches!(e, Supported { meta, .. } if meta.ip != localhost6 && e.has_name(name));
+                        if ip.is_some() && entries.iter().any(other_address) {
+                            entries.retain(|e| !matches!(e, Supported { meta, .. } if meta.ip == localhost6 && e.has_name(name)));
+                        }
+                        let mut last = 0;
+                        let mut dual_stack = false;
+                        for (i, e) in entries.iter_mut().enumerate().filter(|(_, e)| e.has_name(name)) {
+                            if let Supported { meta: existing, raw, .. } = e {
+                                dual_stack |= existing.ip == localhost6;
+                                refresh(existing, &meta, *ip, now);
+                                *raw = None;
+                                last = i;
+                            }
+                        }
+                        if *ipv6 && !dual_stack {
+                            let refreshed_meta = entries.iter().find_map(|e| match e {
+                                Supported { meta, .. } if e.has_name(name) => Some(SupportedMeta {
+                                    ip: localhost6,
+                                    ..*meta.clone()
+                                }),
+                                _ => None,
+                            });
+                            if let Some(v6) = refreshed_meta {
+                                entries.insert(last + 1, new_entry(name, v6));
+                                region_start = region_start.map(|k| k.min(last + 1));
+                            }
+                        }
+                        refreshed = true;
+                        continue;
+                    }
+                    added.push(new_entry(name, meta.clone()));
+                    // a dual stack name is a pair of lines for the same name, which every subcommand handles together
+                    if *ipv6 {
+                        added.push(new_entry(
+                            name,
+                            SupportedMeta {
+                                ip: Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+                                ..meta.clone()
+                            },
+                        ));
+                    }
+                }
+                let position = self.insertion_point(&entries)?;
+                if position == entries.len() && !refreshed {
+                    appended = added.len();
+                }
+                entries.splice(position..position, added);
+                // the region to rewrite must include the new entries
+                region_start = region_start.map(|k| k.min(position));
+            }
+            Subcommand::Renew {
+                name: Some(name),
+                expire_minutes,
+                ..
+            } => {
+                let expiry = now.add(SignedDuration::from_mins(self.add_expire_minutes(*expire_minutes)? as i64));
+                extend_entry(&mut entries, name, expiry, now)?;
+            }
+            Subcommand::SetExpiry { name, expiry } => renew_entry(&mut entries, name, expiry.resolve(now), now)?,
+            Subcommand::Renew {
+                name: None,
+                expire_minutes,
+                selector,
+            }
+            | Subcommand::RenewAll { expire_minutes, selector } => {
+                let expiry = now.add(SignedDuration::from_mins(self.add_expire_minutes(*expire_minutes)? as i64));
+                let mut renewed = 0;
+                for e in entries.iter_mut() {
+                    // entries without an expiry or with a later one stay that way
+                    if let Supported { meta, raw, disabled, .. } = e {
+                        if *disabled || !selects(selector.as_ref(), meta) {
+                            continue;
+                        }
+                        if meta.extend(expiry, now) {
+                            *raw = None;
+                            renewed += 1;
+                        }
+                    }
+                }
+                eprintln!("renewed {} entries until {}", renewed, expiry);
+            }
+            Subcommand::Remove {
+                name,
+                all,
+                source,
+                selector,
+                regex,
+                tag,
+            } => {
+                let filtered = source.is_some() || selector.is_some() || regex.is_some() || tag.is_some();
+                let glob = name.as_ref().filter(|n| n.contains(['*', '?']));
+                let bulk = *all || filtered || glob.is_some();
+                let selected = match name {
+                    Some(_) if glob.is_some() => Vec::new(),
+                    Some(n) => vec![n.clone()],
+                    None if !bulk => pick_entries(&entries, "Select entries to remove:")?,
+                    None => Vec::new(),
+                };
+                let removed = remove_entries(&mut entries, trash.as_mut(), now, |n, meta| {
+                    if filtered {
+                        source.as_ref().is_none_or(|s| meta.source.as_ref() == Some(s))
+                            && selects(selector.as_ref(), meta)
+                            && has_tag(meta, tag.as_deref())
+                            && regex
+                                .as_ref()
+                                .is_none_or(|r| r.is_match(n) || meta.aliases.iter().any(|a| r.is_match(a)))
+                    } else if let Some(g) = glob {
+                        glob_match(g, n) || meta.aliases.iter().any(|a| glob_match(g, a))
+                    } else {
+                        *all || selected.iter().any(|s| s == n || meta.aliases.contains(s))
+                    }
+                });
+                if bulk {
+                    self.confirm(&removed)?;
+                }
+                if let Some(g) = glob {
+                    eprintln!("removed {} entries matching {}", removed.len(), g);
+                }
+            }
+            Subcommand::Clear => {
+                let removed = remove_entries(&mut entries, trash.as_mut(), now, |_, _| true);
+                self.confirm(&removed)?;
+                eprintln!("cleared {} entries", removed.len());
+            }
+            Subcommand::RemoveExpired { .. } => {}
+            Subcommand::Prune { older_than } => {
+                let cutoff = now - *older_than;
+                let removed = remove_entries(&mut entries, trash.as_mut(), now, |_, meta| {
+                    meta.created_at.is_some_and(|c| c < cutoff)
+                });
+                self.confirm(&removed)?;
+                eprintln!("pruned {} entries created before {}", removed.len(), cutoff);
+            }
+            Subcommand::Restore { backup: Some(id), .. } => {
+                message = Some(format!("restored {} from backup {}", self.input_file, id));
+            }
+            Subcommand::Restore { name: None, .. } => unreachable!("listing backups returns early"),
+            Subcommand::Restore {
+                name: Some(name),
+                expire_minutes,
+                ..
+            } => {
+                if entries.iter().any(|e| e.has_name(name)) {
+                    return Err(anyhow!("an entry with name {} already exists", name));
+                }
+                let restored = trash.as_mut().map(|t| t.take(name)).unwrap_or_default();
+                if restored.is_empty() {
+                    return Err(anyhow!("no removed entry with name {} in the trash", name));
+                }
+                for r in restored {
+                    let mut meta = r.meta;
+                    match (expire_minutes, meta.expiry) {
+                        (Some(m), _) => meta.expiry = Some(now.add(SignedDuration::from_mins(*m as i64))),
+                        (None, Some(e)) if e <= now => {
+                            return Err(anyhow!(
+                                "entry {} expired at {}, use --expire to restore it with a new expiry",
+                                name,
+                                e
+                            ))
+                        }
+                        // the original expiry may predate the policy, which must still hold for the restored entry
+                        (None, expiry) => {
+                            let policy = self.policy()?;
+                            match expiry {
+                                Some(e) => policy.check_expire_minutes((e.duration_since(now).as_secs() as u64).div_ceil(60) as usize),
+                                None => policy.check_no_expire(),
+                            }
+                            .with_context(|| format!("entry {} can't be restored with its original expiry", name))?;
+                        }
+                    }
+                    self.policy()?
+                        .check_ip(meta.ip)
+                        .with_context(|| format!("entry {} can't be restored", name))?;
+                    let position = self.insertion_point(&entries)?;
+                    entries.insert(
+                        position,
+                        Supported {
+                            name: r.name,
+                            meta: Box::new(meta),
+                            disabled: r.disabled,
+                            raw: None,
+                        },
+                    );
+                    region_start = region_start.map(|k| k.min(position));
+                }
+            }
+            Subcommand::Apply { manifest, check, prune } => {
+                let position = self.insertion_point(&entries)?;
+                let changes = Manifest::load(manifest, &self.policy()?)?.apply(&mut entries, position, now, *prune, "apply");
+                region_start = region_start.map(|k| k.min(position));
+                for c in &changes {
+                    eprintln!("{}", c);
+                }
+                let changed = expired_count > 0 || !changes.is_empty();
+                if *check || !changed {
+                    return Ok(Some(format!("changed={}", changed)));
+                }
+                message = Some("changed=true".to_string());
+            }
+            Subcommand::TfPlan | Subcommand::TfApply => {
+                let query: BTreeMap<String, String> = match &self.terraform_query {
+                    Some(q) => q.clone(),
+                    None => serde_json::from_reader(std::io::stdin()).context("failed to read terraform query from stdin")?,
+                };
+                let (manifest, prune) = Manifest::from_terraform_query(&query, &self.policy()?)?;
+                let position = self.insertion_point(&entries)?;
+                let changes = manifest.apply(&mut entries, position, now, prune, "terraform");
+                region_start = region_start.map(|k| k.min(position));
+                let changed = expired_count > 0 || !changes.is_empty();
+                let mut result = BTreeMap::from([
+                    ("changed".to_string(), changed.to_string()),
+                    ("changes".to_string(), changes.join(",")),
+                ]);
+                for e in &entries {
+                    if let Supported { name, meta, .. } = e {
+                        if manifest.entries.iter().any(|me| me.name == *name) {
+                            result.insert(name.clone(), meta.expiry_text());
+                        }
+                    }
+                }
+                let result = serde_json::to_string(&result).context("failed to serialize terraform result")?;
+                if self.check && changed {
+                    return Err(self.would_change(&changes.join(", ")));
+                }
+                if matches!(self.subcommand, Subcommand::TfPlan) || !changed {
+                    return Ok(Some(result));
+                }
+                message = Some(result);
+            }
+            Subcommand::Fleet { .. } | Subcommand::Config { .. } | Subcommand::AuditLog { .. } => {}
+            // sessions and CI scopes are both recorded as the scope of an entry
+            Subcommand::Ci {
+                action: CiAction::End { token, all },
+            }
+            | Subcommand::Session {
+                action: SessionAction::End { id: token, all },
+            } => {
+                let removed = remove_entries(&mut entries, None, now, |_, meta| {
+                    meta.scope.is_some() && (*all || meta.scope == *token)
+                });
+                eprintln!("removed {} scoped entries", removed.len());
+            }
+            Subcommand::Ci { .. } | Subcommand::Session { .. } => {}
+            Subcommand::Init { timer, unit_dir } => {
+                if !entries
+                    .iter()
+                    .any(|e| matches!(e, Other(line) if line.text().is_some_and(FileConfig::is_header)))
+                {
+                    entries.insert(0, Other(HostLine::parse(init::STARTER_HEADER.as_bytes())));
+                    // everything moved down a line, so the whole file needs writing
+                    region_start = None;
+                }
+                if !self.test {
+                    let state_dir = self.state_dir()?;
+                    create_dir_all(&state_dir).context("failed to create state directory")?;
+                    if *timer {
+                        init::install_timer(Path::new(unit_dir), &self.input_file)?;
+                    }
+                }
+                message = Some(format!("initialised {}", self.input_file));
+            }
+            Subcommand::Explain { .. }
+            | Subcommand::Exists { .. }
+            | Subcommand::Events { .. }
+            | Subcommand::Motd { .. }
+            | Subcommand::List { .. }
+            | Subcommand::Show { .. }
+            | Subcommand::Status
+            | Subcommand::Doctor => {}
+            Subcommand::Disable { name } => set_disabled(&mut entries, name, true)?,
+            Subcommand::Enable { name } => set_disabled(&mut entries, name, false)?,
+            Subcommand::Undo => {
+                let change = History::new(&self.state_dir()?)
+                    .last()?
+                    .ok_or_else(|| anyhow!("there is no change to undo"))?;
+                let target = self.write_path()?;
+                if change.target != target {
+                    return Err(anyhow!(
+                        "the last change was to {}, not {}",
+                        change.target.to_string_lossy(),
+                        target.to_string_lossy()
+                    ));
+                }
+                for line in &change.added {
+                    match entries.iter().position(|e| matches!(e, Supported { .. }) && e.to_string() == *line) {
+                        Some(i) => {
+                            entries.remove(i);
+                        }
+                        // entries that have expired since were removed above
+                        None if parse_undo_line(line)?.1.expired(now) => {}
+                        None => {
+                            return Err(anyhow!(
+                                "the entries added by `eha {}` have changed since, so it can't be undone",
+                                change.command
+                            ))
+                        }
+                    }
+                }
+                for line in &change.removed {
+                    let (name, meta, disabled) = parse_undo_line(line)?;
+                    if entries.iter().any(|e| e.has_name(&name)) {
+                        return Err(anyhow!("an entry with name {} already exists", name));
+                    }
+                    let position = self.insertion_point(&entries)?;
+                    entries.insert(
+                        position,
+                        Supported {
+                            name,
+                            meta,
+                            disabled,
+                            raw: None,
+                        },
+                    );
+                    region_start = region_start.map(|k| k.min(position));
+                }
+                message = Some(format!("undid `eha {}` from {}", change.command, change.at));
+            }
+            Subcommand::Shorthand(_) => unreachable!("shorthand is expanded before running"),
+        }
+        // checking the result covers every way of adding entries, while still allowing removals from an oversized file
+        let mut managed = managed_names(&entries);
+        if managed.len() > managed_before {
+            managed.sort_by_key(|(_, meta)| meta.created_at);
+            let oldest = managed
+                .iter()
+                .take(managed_before.min(5))
+                .map(|(name, _)| *name)
+                .collect::<Vec<&str>>();
+            self.policy()?.check_entry_count(managed.len(), &oldest)?;
+        }
+
+        // The most common operation is adding a single name to a file with nothing expired, in which case we can append
+        // the new line rather than rewriting the whole file.
+        // Writing in place always follows symlinks, so when those shouldn't be followed the link must be replaced.
+        let in_place = match self.write_strategy {
+            WriteStrategy::Auto => !self.no_follow_symlinks || !is_symlink(&self.input_file),
+            WriteStrategy::Rename => false,
+            WriteStrategy::InPlace => true,
+        };
+        if !self.test && in_place && expired_count == 0 && appended > 0 {
+            let lines = render(&entries[entries.len() - appended..], 0, endings);
+            let target = self.write_path()?;
+            self.before_write(&target, now)?;
+            let journal = Journal::new(&self.state_dir()?, &target);
+            let op = journal.begin(command_line(), None, content_hash(&content))?;
+            let written = attrs::with_unprotected(&target, || self.append_lines(&content, &lines)).map_err(advise_elevation);
+            if written.as_ref().is_err_and(file_changed) {
+                // nothing was written, so there is nothing for the next run to recover
+                journal.complete(&op)?;
+            }
+            written?;
+            journal.complete(&op)?;
+            self.record_change(&content, &entries, now)?;
+            self.after_write();
+            return self.report(message, &content, &entries, now, None);
+        }
+
+        let output = render(&entries, content.len(), endings);
+        if self.test {
+            return self.report(message, &content, &entries, now, Some(&output));
+        }
+
+        // rewriting an identical file would only disturb anything watching it or backing it up
+        if output == content {
+            eprintln!("no changes to {}", self.input_file);
+            if let Some(trash) = trash {
+                trash.save()?;
+            }
+            return self.report(message, &content, &entries, now, None);
+        }
+
+        // every write is journaled first, so that a run interrupted part way through can be cleaned up by the next one
+        let target = self.write_path()?;
+        let rewrite_region = region_start.filter(|k| *k > 0 && in_place);
+        let temp = (rewrite_region.is_none() && self.write_strategy != WriteStrategy::InPlace).then(|| temp_path(&target));
+        self.before_write(&target, now)?;
+        let journal = Journal::new(&self.state_dir()?, &target);
+        let op = journal.begin(command_line(), temp.clone(), content_hash(&content))?;
+        let written = attrs::with_unprotected(&target, || match (&temp, rewrite_region) {
+            // the temp file is only moved into place while the original still has the content that was read from it
+            (Some(temp), _) => replace_file(&target, temp, &output, || {
+                self.check_unchanged(&content)?;
+                journal.mark_ready(&op)
+            }),
+            // the entries before the managed region are still the lines that were read
+            (None, Some(k)) => self.rewrite_region(&content, spans[k - 1].end, &entries[k..]),
+            (None, None) => self.write_in_place(&content, &output),
+        })
+        .map_err(advise_elevation);
+        if written.as_ref().is_err_and(file_changed) {
+            journal.complete(&op)?;
+        }
+        written?;
+        journal.complete(&op)?;
+        self.record_change(&content, &entries, now)?;
+        self.after_write();
+
+        if let Some(trash) = trash {
+            trash.save()?;
+        }
+        self.report(message, &content, &entries, now, None)
+    }
+
+    /// Returns the output of a command that changed the hosts file: the message, or with --test the new content. With
+    /// --output json this is what happened to each name instead, along with the message and any new content.
+    fn report(
+        &self,
+        message: Option<String>,
+        content: &[u8],
+        entries: &[Entry],
+        now: Timestamp,
+        test_output: Option<&[u8]>,
+    ) -> Result<Option<String>, Error> {
+        if self.check && test_output.is_some_and(|o| o != content) {
+            let changes = audit::changes(&parse_content(content), entries, now);
+            return Err(self.would_change(&audit::describe(&changes)));
+        }
+        if self.check {
+            return Ok(None);
+        }
+        let test_output = test_output.map(|o| String::from_utf8_lossy(o).to_string());
+        if self.output == Output::Text {
+            return match test_output {
+                Some(new) if self.diff => {
+                    let diff = diff::unified(&self.input_file, &self.input_file, &String::from_utf8_lossy(content), &new, 3);
+                    let diff = if self.colour() { diff::colourize(&diff) } else { diff };
+                    Ok(Some(diff).filter(|d| !d.is_empty()))
+                }
+                test_output => Ok(test_output.or(message)),
+            };
+        }
+        let changes = audit::changes(&parse_content(content), entries, now);
+        let report = serde_json::json!({
+            "changed": !changes.is_empty(),
+            "changes": changes,
+            "message": message,
+            "content": test_output,
+        });
+        Ok(Some(serde_json::to_string_pretty(&report)?))
+    }
+
+    /// Returns the error that --check fails with when the hosts file would change in the described way.
+    fn would_change(&self, description: &str) -> Error {
+        WouldChange(match description.is_empty() {
+            true => format!("{} would change", self.input_file),
+            false => format!("{} would change: {}", self.input_file, description),
+        })
+        .into()
+    }
+
+    /// Runs show or exists against the entries, which are either all of them or the ones with the name from the index.
+    fn query(&self, entries: &[Entry], now: Timestamp) -> Result<Option<String>, Error> {
+        match &self.subcommand {
+            Subcommand::Show { name } if self.output == Output::Json => {
+                let shown = list_json(entries, now, |e| e.has_name(name));
+                if shown.is_empty() {
+                    return Err(anyhow!("no entry added by eha with name {}", name));
+                }
+                Ok(Some(serde_json::to_string_pretty(&shown)?))
+            }
+            Subcommand::Show { name } => show(entries, name, now).map(Some),
+            Subcommand::Exists { name } => check_exists(entries, name, now),
+            _ => unreachable!("only show and exists are queries"),
+        }
+    }
+
+    /// Runs the subcommand against a copy of the hosts file in a new temp directory, which also holds the state
+    /// directory, and appends a diff of the copy to the output. The directory is removed afterwards unless
+    /// --keep-sandbox was given.
+    fn run_sandboxed(&self) -> Result<Option<String>, Error> {
+        let base = self.sandbox_base.clone().unwrap_or_else(std::env::temp_dir);
+        let dir = base.join(format!("eha-sandbox-{:08x}", random::<u32>()));
+        create_dir_all(&dir).context("failed to create sandbox directory")?;
+        let path = dir.join("hosts");
+        let sandboxed = Args {
+            input_file: path.to_string_lossy().to_string(),
+            state_dir: Some(dir.join("state").to_string_lossy().to_string()),
+            sandbox: false,
+            ..self.clone()
+        };
+        let result = (|| {
+            std::fs::copy(&self.input_file, &path).context("failed to copy input file into the sandbox")?;
+            let before = read_content(&self.input_file)?;
+            let output = sandboxed.run();
+            let diff = diff::unified(
+                &self.input_file,
+                &sandboxed.input_file,
+                &String::from_utf8_lossy(&before),
+                &String::from_utf8_lossy(&read_content(&sandboxed.input_file)?),
+                3,
+            );
+            let output = [output?.unwrap_or_default(), diff]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<String>>()
+                .join("\n");
+            Ok((!output.is_empty()).then_some(output.trim_end().to_string()))
+        })();
+        if self.keep_sandbox {
+            eprintln!("sandbox hosts file is {}", &sandboxed.input_file);
+        } else {
+            std::fs::remove_dir_all(&dir).with_context(|| format!("failed to remove sandbox directory {}", dir.to_string_lossy()))?;
+        }
+        result
+    }
+
+    /// Returns true if the subcommand only reads the hosts file.
+    fn read_only(&self) -> bool {
+        matches!(
+            self.subcommand,
+            Subcommand::RemoveExpired { dry_run: true, .. }
+                | Subcommand::Explain { .. }
+                | Subcommand::Exists { .. }
+                | Subcommand::Motd { .. }
+                | Subcommand::List { .. }
+                | Subcommand::Show { .. }
+                | Subcommand::Status
+                | Subcommand::Doctor
+                | Subcommand::Restore { list: true, .. }
+        )
+    }
+
+    /// Truncates and rewrites the whole input file in place, preserving its inode, and then syncs it to disk.
+    fn write_in_place(&self, content: &[u8], output: &[u8]) -> Result<(), Error> {
+        let mut file = self.open_unchanged(content, OpenOptions::new().write(true))?;
+        eprintln!("rewriting {} in place", &self.input_file);
+        file.set_len(0).context("failed to truncate input file")?;
+        file.write_all(output).context("failed to write content")?;
+        file.sync_all().context("failed to sync input file")
+    }
+
+    /// Returns the path that should be replaced when writing the input file. If the input file is a symlink, this is
+    /// the final target of the link so that the link itself is preserved, unless --no-follow-symlinks was given.
+    fn write_path(&self) -> Result<PathBuf, Error> {
+        if !self.no_follow_symlinks && is_symlink(&self.input_file) {
+            return canonicalize(&self.input_file).context("failed to resolve symlinked input file");
+        }
+        Ok(PathBuf::from(&self.input_file))
+    }
+
+    /// Rewrites the input file from the given offset, which is the end of the last unmanaged line before the managed
+    /// region, leaving everything before it untouched on disk. Large files with a small managed section don't need to be
+    /// rewritten in full.
+    fn rewrite_region(&self, content: &[u8], offset: usize, region: &[Entry]) -> Result<(), Error> {
+        let endings = self.line_endings(content);
+        let mut buf = Vec::new();
+        if !region.is_empty() {
+            buf.extend_from_slice(endings.newline);
+            buf.extend(render(region, content.len() - offset, endings));
+        } else if endings.trailing {
+            buf.extend_from_slice(endings.newline);
+        }
+        let mut file = self.open_unchanged(content, OpenOptions::new().write(true))?;
+        eprintln!("rewriting {} from byte {}", &self.input_file, offset);
+        file.seek(SeekFrom::Start(offset as u64)).context("failed to seek input file")?;
+        file.write_all(&buf).context("failed to write content")?;
+        file.set_len((offset + buf.len()) as u64).context("failed to truncate input file")?;
+        file.sync_all().context("failed to sync input file")
+    }
+
+    /// Appends lines to the input file. The file is locked while appending and must not have changed since the
+    /// given content was read from it.
+    fn append_lines(&self, content: &[u8], lines: &[u8]) -> Result<(), Error> {
+        let mut file = self.open_unchanged(content, OpenOptions::new().append(true))?;
+        let mut buf = Vec::with_capacity(lines.len() + 1);
+        if !content.is_empty() && !content.ends_with(b"\n") {
+            buf.extend_from_slice(self.line_endings(content).newline);
+        }
+        buf.extend_from_slice(lines);
+        eprintln!("appending to {}", &self.input_file);
+        file.write_all(&buf).context("failed to append content")?;
+        file.sync_all().context("failed to sync input file")
+    }
+
+    /// Runs whatever should come before a write of the hosts file, which is backing it up with --backup.
+    fn before_write(&self, target: &Path, now: Timestamp) -> Result<(), Error> {
+        if self.backup || self.config.backup.unwrap_or(false) {
+            let backup = backup::create(target, now, self.backup_count())?;
+            eprintln!("backed up {} to {}", self.input_file, backup.to_string_lossy());
+        }
+        Ok(())
+    }
+
+    /// Returns the number of backups to keep, from --backup-count or the config files.
+    fn backup_count(&self) -> usize {
+        self.backup_count
+            .or(self.config.backup_count)
+            .unwrap_or(backup::DEFAULT_BACKUP_COUNT)
+    }
+
+    /// Records the managed lines that a write added and removed, so that `eha undo` can reverse it, and what happened to
+    /// each name in the audit log.
+    fn record_change(&self, content: &[u8], entries: &[Entry], now: Timestamp) -> Result<(), Error> {
+        let before = parse_content(content);
+        let target = self.write_path()?;
+        let Some(change) = Change::between(command_line(), &target, now, &managed_lines(&before), &managed_lines(entries)) else {
+            return Ok(());
+        };
+        History::new(&self.state_dir()?).record(&change)?;
+        let (user, uid) = identity::invoking_user(|k| std::env::var(k).ok());
+        let record = audit::Record {
+            at: now,
+            user,
+            uid,
+            host: identity::hostname(),
+            command: change.command,
+            target,
+            changes: audit::changes(&before, entries, now),
+        };
+        audit::append(&self.audit_log()?, &record)
+    }
+
+    /// Returns whether to colour output, which is only done in a terminal and unless NO_COLOR is set.
+    fn colour(&self) -> bool {
+        self.terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+    }
+
+    /// Returns the path of the audit log, from --audit-log, the config files, or the state directory.
+    fn audit_log(&self) -> Result<PathBuf, Error> {
+        match self.audit_log.as_ref().or(self.config.audit_log.as_ref()) {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(self.state_dir()?.join("audit.jsonl")),
+        }
+    }
+
+    /// Runs whatever should follow a successful write of the hosts file.
+    fn after_write(&self) {
+        if self.flush_cache || self.config.flush_cache.unwrap_or(false) {
+            dns::flush_cache();
+        }
+    }
+
+    /// Returns the line endings to write the content back with, adding a trailing newline if --ensure-trailing-newline
+    /// was given.
+    fn line_endings(&self, content: &[u8]) -> LineEndings {
+        let endings = LineEndings::detect(content);
+        LineEndings {
+            trailing: endings.trailing || self.ensure_trailing_newline,
+            ..endings
+        }
+    }
+
+    /// Asks for confirmation before removing the given entries when attached to a terminal, unless --yes or --test was
+    /// given. Returns an error if the user declines.
+    fn confirm(&self, removed: &[String]) -> Result<(), Error> {
+        if removed.is_empty() || self.yes || self.test || !std::io::stdin().is_terminal() {
+            return Ok(());
+        }
+        eprint!(
+            "this will remove {} entries: {}\nproceed? [y/N] ",
+            removed.len(),
+            removed.join(", ")
+        );
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).context("failed to read confirmation")?;
+        match answer.trim() {
+            "y" | "Y" | "yes" => Ok(()),
+            _ => Err(anyhow!("aborted, no changes were made")),
+        }
+    }
+
+    /// Asks whether to finish a write that was interrupted after its temp file was complete, when attached to a
+    /// terminal. Otherwise the temp file is left to be cleaned up.
+    fn confirm_resume(&self, op: &Operation) -> Result<bool, Error> {
+        if !std::io::stdin().is_terminal() {
+            return Ok(false);
+        }
+        eprint!(
+            "`eha {}` started at {} was interrupted before moving its finished temp file into place\nfinish it? [y/N] ",
+            op.command, op.started_at
+        );
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).context("failed to read confirmation")?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    /// Opens and locks the input file for modification in place, checking that it still has the content that was read
+    /// from it. The lock is released when the file is closed.
+    fn open_unchanged(&self, content: &[u8], options: &OpenOptions) -> Result<File, Error> {
+        let file = attrs::retry_while_locked(|| options.open(&self.input_file)).context("failed to open input file for writing")?;
+        file.lock().context("failed to lock input file")?;
+        self.check_unchanged(content)?;
+        Ok(file)
+    }
+
+    /// Returns a [`FileChanged`] error if the input file no longer has the content that was read from it, which means
+    /// another program changed it while eha was running and writing now would undo that change.
+    fn check_unchanged(&self, content: &[u8]) -> Result<(), Error> {
+        check_unchanged(Path::new(&self.input_file), content)
+    }
+
+    /// Runs the command, starting again from reading the hosts file up to --retries times if another program changed
+    /// it before eha could write it.
+    fn run_with_retries(&self) -> Result<Option<String>, Error> {
+        let mut attempt = 0;
+        loop {
+            match self.run() {
+                Err(e) if attempt < self.retries && file_changed(&e) => {
+                    attempt += 1;
+                    eprintln!("{}, trying again", e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns where new entries go: at the end of the managed block when the header asks for one after it, otherwise
+    /// at the end of the file, and never inside a section that another tool manages.
+    fn insertion_point(&self, entries: &[Entry]) -> Result<usize, Error> {
+        let position = match FileConfig::read(&self.input_file)?.managed_block {
+            ManagedBlock::AfterHeader => entries
+                .iter()
+                .rposition(|e| matches!(e, Supported { .. }))
+                .or_else(|| {
+                    entries
+                        .iter()
+                        .position(|e| matches!(e, Other(line) if line.text().is_some_and(FileConfig::is_header)))
+                })
+                .map_or(entries.len(), |i| i + 1),
+            ManagedBlock::End => entries.len(),
+        };
+        Ok(managers::outside_sections(&sections(entries), position))
+    }
+
+    fn policy(&self) -> Result<Policy, Error> {
+        Ok(Policy::load(self.policy_file.as_deref())?
+            .with_flag_suffixes(&self.suffixes)
+            .with_file_defaults(&FileConfig::read(&self.input_file)?)
+            .with_config_defaults(&self.config))
+    }
+
+    /// Returns the expiry in minutes for an added entry, falling back to --default-expire, the default from the hosts
+    /// file header, and then the config files.
+    fn add_expire_minutes(&self, given: Option<usize>) -> Result<usize, Error> {
+        match given.or(self.default_expire) {
+            Some(m) => Ok(m),
+            None => Ok(FileConfig::read(&self.input_file)?
+                .default_expire_minutes
+                .or(self.config.default_expire_minutes)
+                .unwrap_or(1440)),
+        }
+    }
+
+    /// Returns the configuration in effect, after merging the config files, environment variables, and flags.
+    fn effective_config(&self) -> Result<Config, Error> {
+        Ok(Config {
+            input_file: Some(self.input_file.clone()),
+            default_expire_minutes: Some(self.add_expire_minutes(None)?),
+            default_ip: Some(self.config.default_ip.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))),
+            allowed_suffixes: Some(self.policy()?.allowed_suffixes().into_iter().map(str::to_string).collect()),
+            flush_cache: Some(self.flush_cache || self.config.flush_cache.unwrap_or(false)),
+            backup: Some(self.backup || self.config.backup.unwrap_or(false)),
+            backup_count: Some(self.backup_count()),
+            audit_log: Some(self.audit_log()?.to_string_lossy().to_string()),
+        })
+    }
+
+    fn run_config(&self, action: &ConfigAction) -> Result<Option<String>, Error> {
+        match action {
+            ConfigAction::Show => Ok(Some(self.effective_config()?.to_toml()?.trim_end().to_string())),
+            ConfigAction::Get { key } => Ok(Some(self.effective_config()?.get(key)?)),
+            ConfigAction::Set { key, value, system } => {
+                let path = match system {
+                    true => PathBuf::from(config::SYSTEM_CONFIG_FILE),
+                    false => config::user_config_file()
+                        .ok_or_else(|| anyhow!("could not determine the user config file, please set $XDG_CONFIG_HOME"))?,
+                };
+                config::set_in_file(&path, key, value)?;
+                eprintln!("set {} in {}", key, path.to_string_lossy());
+                Ok(None)
+            }
+        }
+    }
+
+    /// Fills in the flags that weren't given on the command line from the config files.
+    fn apply_config(&mut self, config: Config, matches: &clap::ArgMatches) {
+        if let Some(input_file) = &config.input_file {
+            if matches.value_source("input_file") == Some(ValueSource::DefaultValue) {
+                self.input_file = input_file.clone();
+            }
+        }
+        self.config = config;
+    }
+
+    /// Returns the directory eha keeps its own state in.
+    fn state_dir(&self) -> Result<PathBuf, Error> {
+        if let Some(d) = &self.state_dir {
+            return Ok(PathBuf::from(d));
+        }
+        if let Some(d) = std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+            return Ok(PathBuf::from(d).join("eha"));
+        }
+        std::env::var_os("HOME")
+            .filter(|d| !d.is_empty())
+            .map(|d| PathBuf::from(d).join(".local/state/eha"))
+            .ok_or_else(|| anyhow!("could not determine the state directory, please set --state-dir"))
+    }
+}
+
+/// A point in time given on the command line, either absolute or relative to the current time.
+#[derive(Debug, Clone, Copy)]
+enum When {
+    At(Timestamp),
+    After(SignedDuration),
+}
+
+impl When {
+    fn resolve(&self, now: Timestamp) -> Timestamp {
+        match self {
+            When::At(t) => *t,
+            When::After(d) => now.add(*d),
+        }
+    }
+}
+
+/// Parses an expiry given as an RFC3339 timestamp or a duration from now such as 2h or +2h.
+fn parse_expiry(value: &str) -> Result<When, Error> {
+    parse_when(value)
+        .or_else(|_| parse_duration(value).map(When::After))
+        .map_err(|_| anyhow!("expected an RFC3339 timestamp or a duration like 2h, got '{}'", value))
+}
+
+/// Parses either an RFC3339 timestamp or an offset from now such as +3d or -12h.
+fn parse_when(value: &str) -> Result<When, Error> {
+    if let Some(d) = value.strip_prefix('+') {
+        Ok(When::After(parse_duration(d)?))
+    } else if let Some(d) = value.strip_prefix('-') {
+        Ok(When::After(-parse_duration(d)?))
+    } else {
+        Ok(When::At(
+            value.parse().context("expected an RFC3339 timestamp or an offset like +3d")?,
+        ))
+    }
+}
+
+/// Returns true if the error comes from the hosts file changing while eha was running.
+fn file_changed(e: &Error) -> bool {
+    e.chain().any(|c| c.is::<FileChanged>())
+}
+
+/// The error returned by --check when the command would change the hosts file, describing how.
+#[derive(Debug)]
+struct WouldChange(String);
+
+impl Display for WouldChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for WouldChange {}
+
+/// Returns the status to exit with after the error. With --check, status 1 only ever means that the hosts file would
+/// change, so other errors exit with 2. Asking for help or the version isn't a failure at all.
+fn exit_code(e: &Error, check: bool) -> i32 {
+    if let Some(e) = e.downcast_ref::<clap::Error>() {
+        return e.exit_code();
+    }
+    match check && !e.chain().any(|c| c.is::<WouldChange>()) {
+        true => 2,
+        false => 1,
+    }
+}
+
+/// Adds advice to a failure to write the hosts file when it was denied on Windows without Administrator rights.
+fn advise_elevation(e: Error) -> Error {
+    #[cfg(windows)]
+    if !windows::is_elevated()
+        && e.chain().any(|c| {
+            c.downcast_ref::<std::io::Error>()
+                .is_some_and(|io| io.kind() == std::io::ErrorKind::PermissionDenied)
+        })
+    {
+        return e.context("changing the hosts file needs Administrator rights, run eha from an elevated prompt or pass --elevate");
+    }
+    e
+}
+
+/// Returns the usual location of the hosts file on this platform.
+fn default_input_file() -> String {
+    #[cfg(windows)]
+    return windows::hosts_path();
+    #[cfg(not(windows))]
+    return "/etc/hosts".to_string();
+}
+
+/// Parses a human friendly duration like 90m, 12h, or 3d. Days are always treated as 24 hours.
+fn parse_duration(value: &str) -> Result<SignedDuration, Error> {
+    let span: Span = value.parse().with_context(|| format!("invalid duration '{}'", value))?;
+    // spans relative to a civil date are never affected by time zone transitions
+    span.to_jiff_duration(jiff::civil::date(2000, 1, 1))
+        .with_context(|| format!("invalid duration '{}'", value))
+}
+
+/// Parses an expiry given either as a number of minutes or as a duration, which is rounded up to whole minutes.
+fn parse_expire_minutes(value: &str) -> Result<usize, Error> {
+    if let Ok(minutes) = value.parse() {
+        return Ok(minutes);
+    }
+    let duration = parse_duration(value)?;
+    if !duration.is_positive() {
+        return Err(anyhow!("expiry must be positive"));
+    }
+    Ok((duration.as_secs() as u64).div_ceil(60) as usize)
+}
+
+/// Parses a suffix that names are allowed to end in, which must start with a dot so that it can't match part of a label.
+fn parse_suffix(value: &str) -> Result<String, Error> {
+    match value.strip_prefix('.') {
+        Some(rest) if !rest.is_empty() && !rest.starts_with('.') => Ok(value.to_string()),
+        _ => Err(anyhow!("suffix '{}' must be a dot followed by a domain, like .test", value)),
+    }
+}
+
+/// Returns an error unless there is an entry with the name that is enabled and has not expired, since otherwise the name
+/// doesn't resolve.
+fn check_exists(entries: &[Entry], name: &str, now: Timestamp) -> Result<Option<String>, Error> {
+    match entries
+        .iter()
+        .any(|e| matches!(e, Supported { meta, disabled: false, .. } if e.has_name(name) && !meta.expired(now)))
+    {
+        true => Ok(None),
+        false => Err(anyhow!("no entry with name {}", name)),
+    }
+}
+
+/// Summarises the enabled entries expiring within the given duration on one line, soonest first, or returns None if
+/// there are none.
+fn motd(entries: &[Entry], now: Timestamp, within: SignedDuration) -> Option<String> {
+    let mut expiring = entries
+        .iter()
+        .filter_map(|e| match e {
+            Supported {
+                name,
+                meta,
+                disabled: false,
+                ..
+            } => meta.expiry.filter(|e| *e > now && *e <= now + within).map(|e| (e, name)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if expiring.is_empty() {
+        return None;
+    }
+    expiring.sort();
+    expiring.dedup();
+    let names = expiring
+        .iter()
+        .map(|(expiry, name)| format!("{} in {}", name, humanize(expiry.duration_since(now))))
+        .collect::<Vec<String>>();
+    Some(format!("eha: {} expiring soon: {}", expiring.len(), names.join(", ")))
+}
+
+/// Refreshes an entry that is added again: the expiry and comment are renewed, any given address, scope and source
+/// replace the old ones, and the given aliases, labels and tags are added to those it has.
+fn refresh(existing: &mut SupportedMeta, given: &SupportedMeta, ip: Option<IpAddr>, now: Timestamp) {
+    existing.renew(given.expiry, now);
+    existing.comment = given.comment.clone();
+    if ip.is_some() {
+        existing.ip = ip;
+    }
+    if given.scope.is_some() {
+        existing.scope = given.scope.clone();
+    }
+    existing.source = given.source.clone();
+    for alias in &given.aliases {
+        if !existing.aliases.contains(alias) {
+            existing.aliases.push(alias.clone());
+        }
+    }
+    existing.labels.extend(given.labels.clone());
+    for tag in &given.tags {
+        if !existing.tags.contains(tag) {
+            existing.tags.push(tag.clone());
+        }
+    }
+}
+
+/// Whether an entry has the given tag, or any entry when there is no tag to filter by.
+fn has_tag(meta: &SupportedMeta, tag: Option<&str>) -> bool {
+    tag.is_none_or(|t| meta.tags.iter().any(|m| m == t))
+}
+
+/// Whether the labels of an entry match the given selector, or any entry when there is no selector to filter by.
+fn selects(selector: Option<&Selector>, meta: &SupportedMeta) -> bool {
+    selector.is_none_or(|s| s.matches(&meta.labels))
+}
+
+/// Formats a tab separated line for each name added by eha that passes the filter with its expiry, how long it has
+/// left, and comment, or returns None if there are none.
+fn list(entries: &[Entry], now: Timestamp, filter: impl Fn(&SupportedMeta) -> bool) -> Option<String> {
+    let mut seen = Vec::new();
+    let lines = entries
+        .iter()
+        .filter_map(|e| match e {
+            Supported { name, meta, .. } if seen.contains(&name) || !filter(meta) => None,
+            Supported { name, meta, disabled, .. } => {
+                seen.push(name);
+                let mut status = match meta.expiry {
+                    Some(e) if e > now => format!("in {}", humanize(e.duration_since(now))),
+                    Some(_) => "expired".to_string(),
+                    None => "never expires".to_string(),
+                };
+                if *disabled {
+                    status.push_str(", disabled");
+                }
+                let age = meta.created_at.map(|c| humanize(now.duration_since(c))).unwrap_or_default();
+                let line = format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    name,
+                    meta.expiry_text(),
+                    status,
+                    age,
+                    meta.project.as_deref().unwrap_or_default(),
+                    meta.comment.as_deref().unwrap_or_default()
+                );
+                Some(line.trim_end().to_string())
+            }
+            Other(_) => None,
+        })
+        .collect::<Vec<String>>();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// A summary of the entries added by eha and whether the hosts file can be written.
+#[derive(Debug, Serialize)]
+struct Status {
+    entries: usize,
+    expired: usize,
+    next_expiry: Option<NextExpiry>,
+    writable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    not_writable_because: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct NextExpiry {
+    name: String,
+    at: Timestamp,
+}
+
+impl Status {
+    fn text(&self, now: Timestamp) -> String {
+        [
+            format!("entries: {}", self.entries),
+            format!("expired: {}", self.expired),
+            match &self.next_expiry {
+                Some(n) => format!("next expiry: {} at {} (in {})", n.name, n.at, humanize(n.at.duration_since(now))),
+                None => "next expiry: none".to_string(),
+            },
+            match &self.not_writable_because {
+                None => "writable: yes".to_string(),
+                Some(e) => format!("writable: no, {}", e),
+            },
+        ]
+        .join("\n")
+    }
+}
+
+/// An entry added by eha as printed with --output json.
+#[derive(Debug, Serialize)]
+struct JsonEntry<'a> {
+    name: &'a str,
+    disabled: bool,
+    expired: bool,
+    line: String,
+    #[serde(flatten)]
+    meta: &'a SupportedMeta,
+}
+
+/// Returns the entries added by eha that match the filter, once per name.
+fn list_json<'a>(entries: &'a [Entry], now: Timestamp, filter: impl Fn(&Entry) -> bool) -> Vec<JsonEntry<'a>> {
+    let mut listed: Vec<JsonEntry> = Vec::new();
+    for e in entries.iter().filter(|e| filter(e)) {
+        if let Supported { name, meta, disabled, .. } = e {
+            if !listed.iter().any(|l| l.name == name) {
+                listed.push(JsonEntry {
+                    name,
+                    disabled: *disabled,
+                    expired: meta.expired(now),
+                    line: e.to_string(),
+                    meta,
+                });
+            }
+        }
+    }
+    listed
+}
+
+/// How soon an entry must expire to be shown as expiring soon in the table.
+const EXPIRING_SOON: SignedDuration = SignedDuration::from_hours(1);
+
+/// Renders the entries added by eha that pass the filter as a table with aligned columns, how long each has left, and
+/// its state, which is coloured with ANSI escapes if asked for. Returns None if there are no entries.
+fn list_table(entries: &[Entry], now: Timestamp, filter: impl Fn(&SupportedMeta) -> bool, colour: bool) -> Option<String> {
+    let mut seen = Vec::new();
+    let mut rows = Vec::new();
+    for e in entries {
+        let Supported { name, meta, disabled, .. } = e else {
+            continue;
+        };
+        if seen.contains(&name) || !filter(meta) {
+            continue;
+        }
+        seen.push(name);
+        let (expires, state, code) = match meta.expiry {
+            _ if *disabled => (String::new(), "disabled", "2"),
+            Some(x) if x <= now => (format!("expired {} ago", humanize(now.duration_since(x))), "expired", "31"),
+            Some(x) if x.duration_since(now) <= EXPIRING_SOON => {
+                (format!("expires in {}", humanize(x.duration_since(now))), "expiring soon", "33")
+            }
+            Some(x) => (format!("expires in {}", humanize(x.duration_since(now))), "active", "32"),
+            None => ("never expires".to_string(), "active", "32"),
+        };
+        rows.push((
+            [
+                name.to_string(),
+                state.to_string(),
+                expires,
+                meta.project.clone().unwrap_or_default(),
+                meta.comment.clone().unwrap_or_default(),
+            ],
+            code,
+        ));
+    }
+    if rows.is_empty() {
+        return None;
+    }
+    let header = ["NAME", "STATE", "EXPIRES", "PROJECT", "COMMENT"].map(str::to_string);
+    let mut widths = header.clone().map(|h| h.len());
+    for (row, _) in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let lines = std::iter::once((header, None)).chain(rows.into_iter().map(|(row, code)| (row, Some(code))));
+    let rendered = lines.map(|(row, code)| {
+        let cells = row.iter().zip(widths).enumerate().map(|(i, (cell, width))| {
+            // the padding goes outside the escapes so that they don't count towards the width
+            let padded = format!("{:width$}", cell, width = width);
+            match code {
+                Some(code) if colour && i == 1 => {
+                    let padding = &padded[cell.len()..];
+                    format!("\x1b[{}m{}\x1b[0m{}", code, cell, padding)
+                }
+                _ => padded,
+            }
+        });
+        cells.collect::<Vec<String>>().join("  ").trim_end().to_string()
+    });
+    Some(rendered.collect::<Vec<String>>().join("\n"))
+}
+
+/// Formats a duration in its two largest units, like 2d 4h, 3h 12m or 45m. It rounds up to the minute, so that an
+/// entry with seconds left never shows as expiring in 0m. Every command shows durations this way.
+fn humanize(d: SignedDuration) -> String {
+    let minutes = (d.as_secs().max(0) + 59) / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    match (days, hours, minutes) {
+        (0, 0, m) => format!("{}m", m),
+        (0, h, 0) => format!("{}h", h),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, 0, _) => format!("{}d", d),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
+/// Summarises the entries added by eha and whether the hosts file can be written, given the result of trying.
+fn status(entries: &[Entry], now: Timestamp, writable: Result<(), Error>) -> Status {
+    let mut names: BTreeMap<&str, &SupportedMeta> = BTreeMap::new();
+    for e in entries {
+        if let Supported { name, meta, .. } = e {
+            names.entry(name).or_insert(meta);
+        }
+    }
+    let expired = names.values().filter(|m| m.expired(now)).count();
+    let next = names
+        .iter()
+        .filter_map(|(name, m)| m.expiry.filter(|e| *e > now).map(|e| (e, *name)))
+        .min();
+    Status {
+        entries: names.len(),
+        expired,
+        next_expiry: next.map(|(at, name)| NextExpiry {
+            name: name.to_string(),
+            at,
+        }),
+        writable: writable.is_ok(),
+        not_writable_because: writable.err().map(|e| format!("{:#}", e)),
+    }
+}
+
+/// Finds the sections of the hosts file that other tools manage.
+fn sections(entries: &[Entry]) -> Vec<managers::Section> {
+    managers::find_sections(entries.iter().map(|e| match e {
+        Other(line) => line.text().unwrap_or_default(),
+        Supported { .. } => "",
+    }))
+}
+
+/// Lists the problems that would stop entries in the hosts file from resolving, or says that there are none.
+fn doctor(entries: &[Entry]) -> String {
+    let mut warnings = managers::clobber_warnings(&sections(entries));
+    if cfg!(target_os = "linux") {
+        warnings.extend(dns::resolver_warnings(Path::new("/")));
+    }
+    match warnings.is_empty() {
+        true => "no problems found".to_string(),
+        false => warnings.join("\n"),
+    }
+}
+
+/// Describes every line of the entry with the given name, which is more than one for a dual stack name.
+fn show(entries: &[Entry], target: &str, now: Timestamp) -> Result<String, Error> {
+    let mut out = Vec::new();
+    for e in entries.iter().filter(|e| e.has_name(target)) {
+        if let Supported { name, meta, disabled, .. } = e {
+            if !out.is_empty() {
+                out.push(String::new());
+            }
+            out.push(format!("name: {}{}", name, if *disabled { " (disabled)" } else { "" }));
+            if !meta.aliases.is_empty() {
+                out.push(format!("aliases: {}", meta.aliases.join(" ")));
+            }
+            out.push(format!("ip: {}", meta.ip.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))));
+            out.push(match meta.expiry {
+                Some(x) if x > now => format!("expiry: {} (in {})", x, humanize(x.duration_since(now))),
+                Some(x) => format!("expiry: {} (expired)", x),
+                None => "expiry: never".to_string(),
+            });
+            out.push(format!("comment: {}", meta.comment.as_deref().unwrap_or_default()));
+            match (&meta.user, meta.uid) {
+                (Some(user), Some(uid)) => out.push(format!("user: {} (uid {})", user, uid)),
+                (Some(user), None) => out.push(format!("user: {}", user)),
+                (None, Some(uid)) => out.push(format!("user: uid {}", uid)),
+                (None, None) => {}
+            }
+            if let Some(host) = &meta.host {
+                out.push(format!("host: {}", host));
+            }
+            if !meta.command.is_empty() {
+                out.push(format!("command: {}", quote_args(&meta.command)));
+            }
+            out.push(format!("line: {}", e));
+        }
+    }
+    if out.is_empty() {
+        return Err(anyhow!("no entry added by eha with name {}", target));
+    }
+    Ok(out.join("\n"))
+}
+
+/// Joins arguments into a command that could be pasted into a shell, quoting the ones that need it.
+fn quote_args(args: &[String]) -> String {
+    args.iter()
+        .map(|a| {
+            if !a.is_empty() && a.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+".contains(c)) {
+                a.clone()
+            } else {
+                format!("'{}'", a.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Returns each managed entry as the line it is written as.
+fn managed_lines(entries: &[Entry]) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|e| matches!(e, Supported { .. }))
+        .map(|e| e.to_string())
+        .collect()
+}
+
+/// Parses a managed line recorded in the undo history back into the name, metadata, and disabled state of its entry.
+fn parse_undo_line(line: &str) -> Result<(String, Box<SupportedMeta>, bool), Error> {
+    match parse_content(line.as_bytes()).pop() {
+        Some(Supported { name, meta, disabled, .. }) => Ok((name, meta, disabled)),
+        _ => Err(anyhow!("invalid line in undo history: {}", line)),
+    }
+}
+
+/// Returns the arguments eha was run with, to describe an operation in the journal.
+fn command_line() -> String {
+    std::env::args().skip(1).collect::<Vec<String>>().join(" ")
+}
+
+fn is_symlink(path: &str) -> bool {
+    symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// Presents a fuzzy searchable multi-select of the entries managed by eha and returns the chosen names. This requires
+/// an interactive terminal.
+fn pick_entries(entries: &[Entry], message: &str) -> Result<Vec<String>, Error> {
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("a name is required when not running in a terminal"));
+    }
+    let (names, labels): (Vec<&str>, Vec<String>) = entries
+        .iter()
+        .filter_map(|e| match e {
+            Supported { name, meta, disabled, .. } => Some((
+                name.as_str(),
+                format!(
+                    "{} (expires {}{})",
+                    name,
+                    meta.expiry_text(),
+                    if *disabled { ", disabled" } else { "" }
+                ),
+            )),
+            Other(_) => None,
+        })
+        .unzip();
+    if names.is_empty() {
+        return Err(anyhow!("there are no entries added by eha to choose from"));
+    }
+    let chosen = inquire::MultiSelect::new(message, labels)
+        .raw_prompt()
+        .context("failed to choose entries")?;
+    Ok(chosen.into_iter().map(|o| names[o.index].to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, NamedTempFile, TempDir};
+
+    /// Returns the args for running against the given hosts file, with the state directory in the given temp dir.
+    fn test_args(f: &NamedTempFile, state: &TempDir) -> Args {
+        Args {
+            subcommand: Subcommand::RemoveExpired {
+                dry_run: false,
+                at: None,
+                tag: None,
+            },
+            input_file: f.path().to_string_lossy().to_string(),
+            test: true,
+            state_dir: Some(state.path().to_string_lossy().to_string()),
+            trash_days: 7,
+            now: None,
+            yes: false,
+            no_follow_symlinks: false,
+            ensure_trailing_newline: false,
+            write_strategy: WriteStrategy::Auto,
+            output: Output::Text,
+            policy_file: None,
+            index: false,
+            sandbox: false,
+            keep_sandbox: false,
+            sandbox_base: Some(state.path().to_path_buf()),
+            terraform_query: None,
+            default_expire: None,
+            suffixes: Vec::new(),
+            override_lock: false,
+            elevate: false,
+            flush_cache: false,
+            diff: false,
+            check: false,
+            retries: 0,
+            backup: false,
+            backup_count: None,
+            audit_log: None,
+            config: Config::default(),
+            terminal: false,
+        }
+    }
+
+    #[test]
+    fn test_no_op() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        let input = r##"# some leading comments followed by whitespace
+
+127.0.0.1   localhost
+10.0.0.9    other.name
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##;
+        f.write_all(input.as_bytes())?;
+        let args = Args {
+            subcommand: Subcommand::RemoveExpired {
+                dry_run: false,
+                at: None,
+                tag: None,
+            },
+            test: true,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        let content = args.run()?.unwrap_or_default();
+        println!("{}", content);
+        assert_eq!(content, input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_expired_while_adding() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"# some leading comments followed by whitespace
+
+127.0.0.1   localhost
+10.0.0.9    other.name
+127.0.0.1	foo.local	# eha {"expiry":"2001-01-01T00:00:00Z","comment":"hello world"}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "thing.local", "--expire", "1"])?,
+            test: true,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        let content = args.run()?.unwrap_or_default();
+        println!("{}", content);
+        assert!(content.contains("127.0.0.1\tthing.local\t# eha {"));
+        assert!(!content.contains("127.0.0.1\tfoo.local\t# eha {"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_entry() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"# some leading comments followed by whitespace
+
+127.0.0.1   localhost
+10.0.0.9    other.name
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "foo.local"])?,
+            test: true,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        let content = args.run()?.unwrap_or_default();
+        println!("{}", content);
+        assert_eq!(
+            content,
+            r##"# some leading comments followed by whitespace
+
+127.0.0.1   localhost
+10.0.0.9    other.name"##
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_overwrite_file() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"# some leading comments followed by whitespace
+
+127.0.0.1   localhost
+10.0.0.9    other.name"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local", "--expire", "1"])?,
+            test: false,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        assert!(args.run()?.is_none());
+
+        let mut f2 = File::open(f.path())?;
+        let mut content = String::new();
+        f2.read_to_string(&mut content)?;
+        assert!(content.contains("127.0.0.1\tfoo.local\t# eha {"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disable_and_enable_entry() -> Result<(), Error> {
+        let input = r##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##;
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(input.as_bytes())?;
+        let mut args = Args {
+            subcommand: Subcommand::Disable {
+                name: "foo.local".to_string(),
+            },
+            test: false,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        assert!(args.run()?.is_none());
+
+        let mut content = String::new();
+        File::open(f.path())?.read_to_string(&mut content)?;
+        assert_eq!(
+            content,
+            r##"127.0.0.1   localhost
+# 127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##
+        );
+
+        args.subcommand = Subcommand::Enable {
+            name: "foo.local".to_string(),
+        };
+        args.test = true;
+        assert_eq!(args.run()?.unwrap_or_default(), input);
+
+        args.subcommand = Subcommand::Enable {
+            name: "bar.local".to_string(),
+        };
+        assert!(args.run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_and_restore_entry() -> Result<(), Error> {
+        let input = r##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##;
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(input.as_bytes())?;
+        let state_dir = tempdir()?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "foo.local"])?,
+            test: false,
+            state_dir: Some(state_dir.path().to_string_lossy().to_string()),
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.is_none());
+        assert!(state_dir.path().join("trash.json").exists());
+
+        args.subcommand = Subcommand::Restore {
+            name: Some("foo.local".to_string()),
+            expire_minutes: None,
+            list: false,
+            backup: None,
+        };
+        args.validate()?;
+        assert!(args.run()?.is_none());
+        let mut content = String::new();
+        File::open(f.path())?.read_to_string(&mut content)?;
+        assert_eq!(content, input);
+
+        // the entry has been taken out of the trash, so it can't be restored twice
+        let mut trash = String::new();
+        File::open(state_dir.path().join("trash.json"))?.read_to_string(&mut trash)?;
+        assert_eq!(trash, "[]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_expired_dry_run_at() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-05T00:00:00Z","comment":null}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::RemoveExpired {
+                dry_run: true,
+                at: Some(parse_when("+3d")?),
+                tag: None,
+            },
+            test: false,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?.unwrap_or_default(), "foo.local\t2030-01-02T00:00:00Z");
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("hosts");
+        std::fs::write(&path, "127.0.0.1   localhost\n")?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local"])?,
+            input_file: path.to_string_lossy().to_string(),
+            state_dir: Some(dir.path().join("state").to_string_lossy().to_string()),
+            test: false,
+            backup: true,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&NamedTempFile::new()?, &dir)
+        };
+        args.run()?;
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("hosts.eha-bak.20300101T000000Z"))?,
+            "127.0.0.1   localhost\n"
+        );
+        assert!(std::fs::read_to_string(&path)?.contains("foo.local"));
+
+        args.backup = false;
+        args.subcommand = Subcommand::try_parse_from(["eha", "restore", "--list"])?;
+        assert_eq!(
+            args.run()?,
+            Some(format!("20300101T000000Z\t{}.eha-bak.20300101T000000Z", path.to_string_lossy()))
+        );
+        args.subcommand = Subcommand::try_parse_from(["eha", "restore", "--backup", "20300102T000000Z"])?;
+        assert!(args.run().is_err());
+        args.subcommand = Subcommand::try_parse_from(["eha", "restore", "--backup", "20300101T000000Z"])?;
+        args.run()?;
+        assert_eq!(std::fs::read_to_string(&path)?, "127.0.0.1   localhost\n");
+
+        assert!(Subcommand::try_parse_from(["eha", "restore"]).is_err());
+        assert!(Subcommand::try_parse_from(["eha", "restore", "foo.local", "--backup", "x"]).is_err());
+
+        args.backup_count = Some(0);
+        assert!(args.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let mut args = Args {
+            subcommand: Subcommand::Undo,
+            test: false,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run().unwrap_err().to_string(), "there is no change to undo");
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "foo.local", "bar.local"])?;
+        args.run()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "foo.local"])?;
+        args.run()?;
+        assert!(!std::fs::read_to_string(f.path())?.contains("foo.local"));
+
+        args.subcommand = Subcommand::Undo;
+        assert!(args.run()?.unwrap_or_default().starts_with("undid `eha "));
+        let restored = std::fs::read_to_string(f.path())?;
+        assert!(restored.contains("\tfoo.local\t") && restored.contains("\tbar.local\t"));
+        // undoing the undo removes it again
+        args.run()?;
+        assert!(!std::fs::read_to_string(f.path())?.contains("foo.local"));
+        args.run()?;
+
+        // the added entries must still be there to undo adding them
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "baz.local"])?;
+        args.run()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "baz.local"])?;
+        args.run()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "baz.local", "--expire", "30"])?;
+        args.run()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "audit-log", "tail", "-n", "1"])?;
+        let tail = args.run()?.unwrap_or_default();
+        assert!(tail.contains("\tadded baz.local expiring 2030-01-01T00:30:00Z"), "{}", tail);
+        let history = History::new(&args.state_dir()?);
+        let mut change = history.last()?.expect("a change was recorded");
+        change.added = vec!["127.0.0.1\tbaz.local\t# eha {}".to_string()];
+        history.record(&change)?;
+        args.subcommand = Subcommand::Undo;
+        assert!(args.run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_expired_without_changes() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}
+"##,
+        )?;
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        f.as_file().set_modified(modified)?;
+        let args = Args {
+            subcommand: Subcommand::RemoveExpired {
+                dry_run: false,
+                at: None,
+                tag: None,
+            },
+            test: false,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?, None);
+        assert_eq!(std::fs::metadata(f.path())?.modified()?, modified);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_when() -> Result<(), Error> {
+        let now: Timestamp = "2030-01-01T00:00:00Z".parse()?;
+        assert_eq!(parse_when("+3d")?.resolve(now).to_string(), "2030-01-04T00:00:00Z");
+        assert_eq!(parse_when("-90m")?.resolve(now).to_string(), "2029-12-31T22:30:00Z");
+        assert_eq!(parse_when("2031-01-01T00:00:00Z")?.resolve(now).to_string(), "2031-01-01T00:00:00Z");
+        assert!(parse_when("tomorrow").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_old_entries() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	old.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null,"created_at":"2029-01-01T00:00:00Z"}
+127.0.0.1	new.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null,"created_at":"2029-12-25T00:00:00Z"}
+127.0.0.1	unknown.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::Prune {
+                older_than: parse_duration("30d")?,
+            },
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?.unwrap_or_default(),
+            r##"127.0.0.1   localhost
+127.0.0.1	new.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null,"created_at":"2029-12-25T00:00:00Z"}
+127.0.0.1	unknown.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null}"##
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unmodified_entries_are_written_verbatim() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        let input = r##"127.0.0.1   localhost
+127.0.0.1 foo.local  # eha {"comment":"hello world","expiry":"2030-01-01T00:00:00Z"}
+127.0.0.1 bar.local  # eha {"comment":"hello world","expiry":"2030-01-01T00:00:00Z"}"##;
+        f.write_all(input.as_bytes())?;
+        let args = Args {
+            subcommand: Subcommand::Disable {
+                name: "bar.local".to_string(),
+            },
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?.unwrap_or_default(),
+            r##"127.0.0.1   localhost
+127.0.0.1 foo.local  # eha {"comment":"hello world","expiry":"2030-01-01T00:00:00Z"}
+# 127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_appends_to_file() -> Result<(), Error> {
+        for (input, prefix, ensure_trailing_newline, suffix) in [
+            ("", "", false, ""),
+            ("127.0.0.1   localhost", "127.0.0.1   localhost\n", false, ""),
+            ("127.0.0.1   localhost", "127.0.0.1   localhost\n", true, "\n"),
+            ("127.0.0.1   localhost\n", "127.0.0.1   localhost\n", false, "\n"),
+            (
+                "127.0.0.1   localhost\r\n::1 localhost",
+                "127.0.0.1   localhost\r\n::1 localhost\r\n",
+                false,
+                "",
+            ),
+        ] {
+            let state = tempdir()?;
+            let mut f = NamedTempFile::new_in(state.path())?;
+            f.write_all(input.as_bytes())?;
+            let args = Args {
+                subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local", "--expire", "1"])?,
+                test: false,
+                ensure_trailing_newline,
+                ..test_args(&f, &state)
+            };
+            assert!(args.run()?.is_none());
+
+            let mut content = String::new();
+            File::open(f.path())?.read_to_string(&mut content)?;
+            let line = content.strip_prefix(prefix).unwrap_or_default();
+            assert!(line.starts_with("127.0.0.1\tfoo.local\t# eha {"), "{:?}", content);
+            let line = line.strip_suffix(suffix).unwrap_or_default();
+            assert!(line.ends_with('}') && !line.contains('\n'), "{:?}", content);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_managed_region() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+10.0.0.9    other.name
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+"##,
+        )?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "bar.local"])?,
+            test: false,
+            trash_days: 0,
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.is_none());
+        let mut content = String::new();
+        File::open(f.path())?.read_to_string(&mut content)?;
+        assert_eq!(
+            content,
+            r##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+10.0.0.9    other.name
+"##
+        );
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "foo.local"])?;
+        assert!(args.run()?.is_none());
+        let mut content = String::new();
+        File::open(f.path())?.read_to_string(&mut content)?;
+        assert_eq!(content, "127.0.0.1   localhost\n10.0.0.9    other.name\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_all_entries() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+10.0.0.9    other.name
+# 127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "--all"])?,
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?.unwrap_or_default(), "127.0.0.1   localhost\n10.0.0.9    other.name");
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_by_source() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"source":"docker"}
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"source":"cli"}
+127.0.0.1	baz.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "--source", "docker"])?,
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?.unwrap_or_default(),
+            r##"127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"source":"cli"}
+127.0.0.1	baz.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_metadata_is_left_unmanaged() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        let content = b"127.0.0.1   localhost\n127.0.0.1\tfoo.local\t# eha {\"expiry\":\"bad\"}\n127.0.0.1\tcaf\xe9.local\t# eha {}\n";
+        f.write_all(content)?;
+        // every other line still works, and the invalid ones are written back as they were
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "bar.local", "--expire-at", "2030-01-01T00:00:00Z"])?,
+            now: Some("2029-12-31T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.expect("the new content should be returned");
+        assert!(output.starts_with(&*String::from_utf8_lossy(content)));
+        assert!(
+            output.contains("\n127.0.0.1\tbar.local\t# eha {\"expiry\":\"2030-01-01T00:00:00Z\""),
+            "{}",
+            output
+        );
+
+        // they aren't entries added by eha, so removing the name leaves them in place
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "foo.local"])?,
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?, Some(String::from_utf8_lossy(content).to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_preserve_non_utf8_lines() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            b"127.0.0.1   caf\xe9.name # r\xe9seau\n127.0.0.1\tfoo.local\t# eha {\"expiry\":\"2000-01-01T00:00:00Z\",\"comment\":null}\n",
+        )?;
+        let args = Args {
+            test: false,
+            trash_days: 0,
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.is_none());
+        assert_eq!(std::fs::read(f.path())?, b"127.0.0.1   caf\xe9.name # r\xe9seau\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_through_symlink() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let target = dir.path().join("hosts");
+        let link = dir.path().join("link");
+        let input = r##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1   localhost"##;
+        std::fs::write(&target, input)?;
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        let f = NamedTempFile::new()?;
+        let state = tempdir()?;
+        let mut args = Args {
+            subcommand: Subcommand::Disable {
+                name: "foo.local".to_string(),
+            },
+            input_file: link.to_string_lossy().to_string(),
+            test: false,
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.is_none());
+        assert!(is_symlink(&args.input_file));
+        assert!(std::fs::read_to_string(&target)?.starts_with("# 127.0.0.1\tfoo.local"));
+
+        args.subcommand = Subcommand::Enable {
+            name: "foo.local".to_string(),
+        };
+        args.no_follow_symlinks = true;
+        assert!(args.run()?.is_none());
+        assert!(!is_symlink(&args.input_file));
+        assert_eq!(std::fs::read_to_string(&link)?, input);
+        assert!(std::fs::read_to_string(&target)?.starts_with("# 127.0.0.1\tfoo.local"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_strategies() -> Result<(), Error> {
+        use std::os::unix::fs::MetadataExt;
+        for (strategy, same_inode) in [(WriteStrategy::Rename, false), (WriteStrategy::InPlace, true)] {
+            let state = tempdir()?;
+            let mut f = NamedTempFile::new_in(state.path())?;
+            f.write_all(br##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##)?;
+            let inode = f.as_file().metadata()?.ino();
+            let args = Args {
+                subcommand: Subcommand::try_parse_from(["eha", "remove", "foo.local"])?,
+                test: false,
+                trash_days: 0,
+                write_strategy: strategy,
+                ..test_args(&f, &state)
+            };
+            args.validate()?;
+            assert!(args.run()?.is_none());
+            assert_eq!(std::fs::read_to_string(f.path())?, "");
+            assert_eq!(std::fs::metadata(f.path())?.ino() == inode, same_inode, "{:?}", strategy);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_check() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(br##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##)?;
+        let mut manifest = NamedTempFile::new()?;
+        manifest.write_all(br#"{"entries":[{"name":"foo.local"}]}"#)?;
+        let mut args = Args {
+            subcommand: Subcommand::Apply {
+                manifest: manifest.path().to_string_lossy().to_string(),
+                check: true,
+                prune: false,
+            },
+            test: false,
+            now: Some("2029-12-31T12:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?.unwrap_or_default(), "changed=false");
+
+        // an entry about to expire needs renewing
+        args.now = Some("2029-12-31T23:00:00Z".parse()?);
+        assert_eq!(args.run()?.unwrap_or_default(), "changed=true");
+
+        args.now = Some("2031-01-01T00:00:00Z".parse()?);
+        assert_eq!(args.run()?.unwrap_or_default(), "changed=true");
+        assert!(std::fs::read_to_string(f.path())?.contains("2030-01-01T00:00:00Z"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ci_scope() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1	a.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"scope":"ci-1"}
+127.0.0.1	b.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"scope":"ci-2"}
+127.0.0.1	c.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let mut args = Args {
+            subcommand: Subcommand::Ci { action: CiAction::Begin },
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.unwrap_or_default().starts_with("ci-"));
+
+        args.subcommand = Subcommand::Ci {
+            action: CiAction::End {
+                token: Some("ci-1".to_string()),
+                all: false,
+            },
+        };
+        let content = args.run()?.unwrap_or_default();
+        assert!(!content.contains("a.local"));
+        assert!(content.contains("b.local"));
+
+        args.subcommand = Subcommand::Ci {
+            action: CiAction::End { token: None, all: true },
+        };
+        let content = args.run()?.unwrap_or_default();
+        assert!(!content.contains("b.local"));
+        assert!(content.contains("c.local"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_session() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1	a.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"scope":"session-1"}
+127.0.0.1	b.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let mut args = Args {
+            subcommand: Subcommand::Session {
+                action: SessionAction::Start,
+            },
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.unwrap_or_default().starts_with("session-"));
+
+        args.subcommand = Args::try_parse_from(["eha", "session", "end", "session-1"])?.subcommand;
+        let content = args.run()?.unwrap_or_default();
+        assert!(!content.contains("a.local"));
+        assert!(content.contains("b.local"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy_file() -> Result<(), Error> {
+        let f = NamedTempFile::new()?;
+        let state = tempdir()?;
+        let mut policy = NamedTempFile::new()?;
+        policy.write_all(b"allowed_suffixes = [\".test\"]\nmax_expire_minutes = 60\n")?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.test", "--expire", "60"])?,
+            policy_file: Some(policy.path().to_string_lossy().to_string()),
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "foo.local", "--expire", "60"])?;
+        assert_eq!(args.validate().unwrap_err().to_string(), "name must end in .test");
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "foo.test", "--expire", "61"])?;
+        assert!(args.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_motd() -> Result<(), Error> {
+        let content = r##"127.0.0.1	a.local	# eha {"expiry":"2029-01-01T01:30:00Z","comment":null}
+127.0.0.1	b.local	# eha {"expiry":"2029-01-01T00:12:00Z","comment":null}
+# 127.0.0.1	c.local	# eha {"expiry":"2029-01-01T00:05:00Z","comment":null}
+127.0.0.1	d.local	# eha {"expiry":"2029-01-02T00:00:00Z","comment":null}"##;
+        let entries = parse_content(content.as_bytes());
+        let now = "2029-01-01T00:00:00Z".parse()?;
+        assert_eq!(
+            motd(&entries, now, SignedDuration::from_hours(2)),
+            Some("eha: 2 expiring soon: b.local in 12m, a.local in 1h 30m".to_string())
+        );
+        assert_eq!(motd(&entries, now, SignedDuration::from_mins(10)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_config_header() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"# eha-config {"allowed_suffixes":[".test"],"default_expire_minutes":60,"managed_block":"after_header"}
+127.0.0.1   localhost"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.test"])?,
+            now: Some("2029-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        let output = args.run()?.unwrap_or_default();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("127.0.0.1\tfoo.test\t# eha {\"expiry\":\"2029-01-01T01:00:00Z\""));
+        assert_eq!(lines[2], "127.0.0.1   localhost");
+
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local"])?,
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.validate().unwrap_err().to_string(), "name must end in .test");
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_adds_header_once() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost")?;
+        let args = Args {
+            subcommand: Subcommand::Init {
+                timer: false,
+                unit_dir: "/etc/systemd/system".to_string(),
+            },
+            test: false,
+            state_dir: Some(state.path().join("eha").to_string_lossy().to_string()),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?, Some(format!("initialised {}", f.path().to_string_lossy())));
+        let expected = format!("{}\n127.0.0.1   localhost", init::STARTER_HEADER);
+        assert_eq!(std::fs::read_to_string(f.path())?, expected);
+        assert!(state.path().join("eha").is_dir());
+        args.run()?;
+        assert_eq!(std::fs::read_to_string(f.path())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_labels_and_selector() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"labels":{"env":"staging","owner":"core"}}
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null,"labels":{"env":"staging"}}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "--selector", "env=staging,owner!=core"])?,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(output.starts_with("127.0.0.1\tfoo.local\t"));
+        assert_eq!(output.lines().count(), 1);
+
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "list", "--selector", "env=staging,owner!=core"])?,
+            now: Some("2029-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(output.starts_with("bar.local\t"));
+        assert_eq!(output.lines().count(), 1);
+
+        args.now = Some("2029-12-31T23:30:00Z".parse()?);
+        for command in ["renew", "renew-all"] {
+            args.subcommand = Subcommand::try_parse_from(["eha", command, "--expire", "1h", "--selector", "owner"])?;
+            let output = args.run()?.unwrap_or_default();
+            assert!(output.contains(r#"foo.local	# eha {"expiry":"2030-01-01T00:30:00Z""#));
+            assert!(output.contains(r#"bar.local	# eha {"expiry":"2030-01-01T00:00:00Z""#));
+        }
+        assert!(Subcommand::try_parse_from(["eha", "renew", "foo.local", "--selector", "owner"]).is_err());
+        assert!(Subcommand::try_parse_from(["eha", "renew"]).is_err());
+
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "baz.local", "--tag", "project-x", "--label", "env=dev"])?,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(output
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .contains(r#""labels":{"env":"dev"},"tags":["project-x"]"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_records_tags_and_provenance() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        // metadata from before tags and provenance were recorded still parses
+        f.write_all(br##"127.0.0.1	old.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}"##)?;
+        let meta_of = |name: &str| -> Result<SupportedMeta, Error> {
+            let content = std::fs::read_to_string(f.path())?;
+            match parse_content(content.as_bytes()).into_iter().find(|e| e.has_name(name)) {
+                Some(Supported { meta, .. }) => Ok(*meta),
+                _ => Err(anyhow!("no entry {}", name)),
+            }
+        };
+        let old = meta_of("old.local")?;
+        assert!(old.tags.is_empty() && old.host.is_none() && old.command.is_empty() && old.created_at.is_none());
+
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local", "--tag", "project-x", "--tag", "ci"])?,
+            test: false,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        args.run()?;
+        let meta = meta_of("foo.local")?;
+        assert_eq!(meta.tags, ["project-x", "ci"]);
+        assert_eq!(meta.host, identity::hostname());
+        assert_eq!(meta.command, std::env::args().collect::<Vec<String>>());
+        assert_eq!(meta.created_at, args.now);
+        assert!(meta.renewed_at.is_empty());
+
+        args.now = Some("2030-01-01T12:00:00Z".parse()?);
+        args.subcommand = Subcommand::try_parse_from(["eha", "renew", "foo.local", "--expire", "2d"])?;
+        args.run()?;
+        let meta = meta_of("foo.local")?;
+        assert_eq!(meta.renewed_at, [args.now.expect("now is set")]);
+        assert_eq!(meta.created_at, Some("2030-01-01T00:00:00Z".parse()?));
+
+        args.now = Some("2030-01-02T12:00:00Z".parse()?);
+        args.subcommand = Subcommand::try_parse_from(["eha", "list", "--tag", "ci"])?;
+        let output = args.run()?.unwrap_or_default();
+        assert!(output.starts_with("foo.local\t2030-01-03T12:00:00Z\tin 1d\t1d 12h\t"), "{}", output);
+        assert_eq!(output.lines().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shorthand_and_aliases() -> Result<(), Error> {
+        let mut args = Args::try_parse_from(["eha", "--test", "foo.local", "-e", "60"])?;
+        args.expand_shorthand()?;
+        assert!(matches!(args.subcommand, Subcommand::Add { ref names, expire_minutes: Some(60), .. } if names == &["foo.local"]));
+        let mut args = Args::try_parse_from(["eha", "lsit"])?;
+        assert_eq!(args.expand_shorthand().unwrap_err().to_string(), "unrecognized subcommand 'lsit'");
+        assert!(matches!(
+            Args::try_parse_from(["eha", "rm", "foo.local"])?.subcommand,
+            Subcommand::Remove { .. }
+        ));
+        assert!(matches!(
+            Args::try_parse_from(["eha", "gc"])?.subcommand,
+            Subcommand::RemoveExpired { .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sandbox_leaves_input_untouched() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n127.0.0.1\tfoo.local\t# eha {\"expiry\":\"2030-01-01T00:00:00Z\",\"comment\":null}\n")?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "foo.local"])?,
+            test: false,
+            sandbox: true,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(
+            output.contains("@@ -1,2 +1 @@\n 127.0.0.1   localhost\n-127.0.0.1\tfoo.local\t# eha "),
+            "{}",
+            output
+        );
+        assert!(std::fs::read_to_string(f.path())?.contains("foo.local"));
+        assert!(!std::fs::read_dir(state.path())?.any(|e| e.is_ok_and(|e| e.file_name().to_string_lossy().starts_with("eha-sandbox-"))));
+
+        // with --keep-sandbox the copy stays behind to look at, even when the command fails
+        let args = Args {
+            subcommand: Subcommand::Show {
+                name: "missing.local".to_string(),
+            },
+            keep_sandbox: true,
+            ..args
+        };
+        assert!(args.run().is_err());
+        let kept = std::fs::read_dir(state.path())?
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name().to_string_lossy().starts_with("eha-sandbox-"))
+            .expect("the sandbox should be kept");
+        assert!(std::fs::read_to_string(kept.path().join("hosts"))?.contains("foo.local"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_outside_other_managers() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n# profile.on dev\n127.0.0.1 dev.local")?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local"])?,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert!(lines[1].starts_with("127.0.0.1\tfoo.local\t"));
+        assert_eq!(lines[2], "# profile.on dev");
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_undo_and_apply_outside_other_managers() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n# profile.on dev\n127.0.0.1 dev.local\n")?;
+        let mut manifest = NamedTempFile::new()?;
+        manifest.write_all(br#"{"entries":[{"name":"foo.local"}]}"#)?;
+        let placed = || -> Result<bool, Error> {
+            let content = std::fs::read_to_string(f.path())?;
+            let lines = content.lines().collect::<Vec<&str>>();
+            Ok(lines[1].starts_with("127.0.0.1\tfoo.local\t") && lines[2] == "# profile.on dev")
+        };
+        let mut args = Args {
+            subcommand: Subcommand::Apply {
+                manifest: manifest.path().to_string_lossy().to_string(),
+                check: false,
+                prune: false,
+            },
+            test: false,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        args.run()?;
+        assert!(placed()?);
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "foo.local"])?;
+        args.run()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "restore", "foo.local"])?;
+        args.run()?;
+        assert!(placed()?);
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "foo.local"])?;
+        args.run()?;
+        args.subcommand = Subcommand::Undo;
+        args.run()?;
+        assert!(placed()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_write_is_cleaned_up() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let args = Args {
+            subcommand: Subcommand::RemoveExpired {
+                dry_run: false,
+                at: None,
+                tag: None,
+            },
+            test: false,
+            ..test_args(&f, &state)
+        };
+        let state_dir = PathBuf::from(args.state_dir.clone().unwrap_or_default());
+        let temp = NamedTempFile::new_in(state.path())?.into_temp_path().keep()?;
+        Journal::new(&state_dir, f.path()).begin("add foo.local".to_string(), Some(temp.clone()), 0)?;
+
+        args.run()?;
+        assert!(!temp.exists());
+        assert!(std::fs::read_dir(&state_dir)?.all(|e| e.is_ok_and(|e| !e.file_name().to_string_lossy().starts_with("journal"))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exists_and_show_with_index() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+# 127.0.0.1	off.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1	old.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}
+"##,
+        )?;
+        for index in [false, true] {
+            let mut args = Args {
+                subcommand: Subcommand::Exists {
+                    name: "foo.local".to_string(),
+                },
+                now: Some("2029-06-01T00:00:00Z".parse()?),
+                index,
+                ..test_args(&f, &state)
+            };
+            assert!(args.run()?.is_none());
+            for name in ["off.local", "old.local", "missing.local"] {
+                args.subcommand = Subcommand::Exists { name: name.to_string() };
+                assert_eq!(args.run().unwrap_err().to_string(), format!("no entry with name {}", name));
+            }
+            args.subcommand = Subcommand::Show {
+                name: "off.local".to_string(),
+            };
+            assert!(args
+                .run()?
+                .unwrap_or_default()
+                .starts_with("name: off.local (disabled)\nip: 127.0.0.1\n"));
+            args.output = Output::Json;
+            assert!(args.run()?.unwrap_or_default().contains(r#""disabled": true"#));
+            args.subcommand = Subcommand::Show {
+                name: "missing.local".to_string(),
+            };
+            assert!(args.run().is_err());
+        }
+        assert!(std::fs::read_dir(state.path())?.any(|e| e.is_ok_and(|e| e.file_name().to_string_lossy().starts_with("index-"))));
+
+        // removing a missing name leaves the file alone, while removing one that is there goes through the usual write
+        let before = read_content(&f.path().to_string_lossy())?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "missing.local"])?,
+            now: Some("2029-06-01T00:00:00Z".parse()?),
+            index: true,
+            test: false,
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.is_none());
+        assert_eq!(read_content(&f.path().to_string_lossy())?, before);
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "foo.local"])?;
+        args.run()?;
+        assert!(!String::from_utf8(read_content(&f.path().to_string_lossy())?)?.contains("foo.local"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T01:05:00Z","comment":"hello world","created_at":"2029-12-28T20:00:00Z","project":"myapp"}
+# 127.0.0.1	bar.local	# eha {"expiry":"2029-01-01T00:00:00Z"}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::List { tag: None, selector: None },
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?,
+            Some(
+                "foo.local\t2030-01-01T01:05:00Z\tin 1h 5m\t3d 4h\tmyapp\thello world\nbar.local\t2029-01-01T00:00:00Z\texpired, disabled"
+                    .to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_renew_keeps_comment() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T01:00:00Z","comment":"added by alice"}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::Renew {
+                name: Some("foo.local".to_string()),
+                expire_minutes: Some(120),
+                selector: None,
+            },
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        assert_eq!(
+            args.run()?,
+            Some(
+                r##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T02:00:00Z","comment":"added by alice","renewed_at":["2030-01-01T00:00:00Z"]}"##
+                    .to_string()
+            )
+        );
+        // renewing never shortens an entry
+        let args = Args {
+            subcommand: Subcommand::Renew {
+                name: Some("foo.local".to_string()),
+                expire_minutes: Some(30),
+                selector: None,
+            },
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert!(args
+            .run()?
+            .unwrap_or_default()
+            .contains(r#"{"expiry":"2030-01-01T01:00:00Z","comment":"added by alice"}"#));
+        let args = Args {
+            subcommand: Subcommand::Renew {
+                name: Some("bar.local".to_string()),
+                expire_minutes: None,
+                selector: None,
+            },
+            ..test_args(&f, &state)
+        };
+        assert!(args.run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_expire_minutes() -> Result<(), Error> {
+        assert_eq!(parse_expire_minutes("90")?, 90);
+        assert_eq!(parse_expire_minutes("90m")?, 90);
+        assert_eq!(parse_expire_minutes("12h")?, 720);
+        assert_eq!(parse_expire_minutes("7d")?, 10080);
+        assert_eq!(parse_expire_minutes("30s")?, 1);
+        assert!(parse_expire_minutes("-1h").is_err());
+        assert!(parse_expire_minutes("soon").is_err());
+        let args = Args::try_parse_from(["eha", "add", "foo.local", "--expire", "2h"])?;
+        assert!(matches!(
+            args.subcommand,
+            Subcommand::Add {
+                expire_minutes: Some(120),
+                ..
+            }
+        ));
+        let args = Args::try_parse_from(["eha", "add", "foo.local", "--expire-minutes", "5"])?;
+        assert!(matches!(
+            args.subcommand,
+            Subcommand::Add {
+                expire_minutes: Some(5),
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_expire_at() -> Result<(), Error> {
+        let f = NamedTempFile::new()?;
+        let state = tempdir()?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "demo.local", "--expire-at", "2030-01-01T17:00:00Z"])?,
+            now: Some("2030-01-01T09:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        assert!(args.run()?.unwrap_or_default().contains(r#""expiry":"2030-01-01T17:00:00Z""#));
+
+        let args = Args {
+            now: Some("2030-01-02T00:00:00Z".parse()?),
+            ..args
+        };
+        assert!(args.validate().is_err());
+        assert!(Args::try_parse_from(["eha", "add", "demo.local", "-e", "5", "--expire-at", "2030-01-01T17:00:00Z"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_with_ip() -> Result<(), Error> {
+        let f = NamedTempFile::new()?;
+        let state = tempdir()?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "nas.local", "--expire", "60", "--ip", "192.168.1.20"])?,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(output.starts_with("192.168.1.20\tnas.local\t# eha {"));
+        assert!(output.contains(r#""ip":"192.168.1.20""#));
+        assert!(matches!(Entry::try_from(output.as_str())?, Supported { meta, .. } if meta.ip == Some("192.168.1.20".parse()?)));
+        assert!(Args::try_parse_from(["eha", "add", "nas.local", "--ip", "192.168.1"]).is_err());
+
+        // a policy can keep names on loopback addresses, whether given with --ip or the config files
+        let mut policy = NamedTempFile::new()?;
+        policy.write_all(b"allow_non_loopback = false\n")?;
+        args.policy_file = Some(policy.path().to_string_lossy().to_string());
+        assert!(args
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .starts_with("names can only point at loopback addresses by the policy in"));
+        if let Subcommand::Add { ip, .. } = &mut args.subcommand {
+            *ip = None;
+        }
+        args.validate()?;
+        args.config.default_ip = Some("10.0.0.1".parse()?);
+        assert!(args.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dual_stack_entry() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let state_dir = tempdir()?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local", "--expire", "60", "--ipv6"])?,
+            test: false,
+            state_dir: Some(state_dir.path().to_string_lossy().to_string()),
+            ..test_args(&f, &state)
+        };
+        assert!(args.run()?.is_none());
+        let added = std::fs::read_to_string(f.path())?;
+        let lines = added.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3, "{:?}", added);
+        assert!(lines[1].starts_with("127.0.0.1\tfoo.local\t# eha {"));
+        assert!(lines[2].starts_with("::1\tfoo.local\t# eha {"));
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "foo.local"])?;
+        assert!(args.run()?.is_none());
+        assert_eq!(std::fs::read_to_string(f.path())?, "127.0.0.1   localhost\n");
+
+        args.subcommand = Subcommand::Restore {
+            name: Some("foo.local".to_string()),
+            expire_minutes: None,
+            list: false,
+            backup: None,
+        };
+        assert!(args.run()?.is_none());
+        assert_eq!(std::fs::read_to_string(f.path())?, added);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aliases_on_one_line() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from([
+                "eha",
+                "add",
+                "name.local",
+                "--expire",
+                "60",
+                "--alias",
+                "www.name.local",
+                "--alias",
+                "api.name.local",
+            ])?,
+            test: false,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        assert!(args.run()?.is_none());
+        let content = std::fs::read_to_string(f.path())?;
+        assert!(
+            content.contains("127.0.0.1\tname.local www.name.local api.name.local\t# eha {"),
+            "{:?}",
+            content
+        );
+        let entries = parse_content(content.as_bytes());
+        assert!(matches!(&entries[1], Supported { name, meta, .. } if name == "name.local" && meta.aliases.len() == 2));
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "api.name.local"])?;
+        assert!(args.run()?.is_none());
+        assert_eq!(std::fs::read_to_string(f.path())?, "127.0.0.1   localhost\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_multiple_names() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let mut args = Args::try_parse_from(["eha", "add", "a.local", "b.local", "c.local", "--expire", "2h"])?;
+        args.input_file = f.path().to_string_lossy().to_string();
+        args.state_dir = test_args(&f, &state).state_dir;
+        args.validate()?;
+        assert!(args.run()?.is_none());
+        let content = std::fs::read_to_string(f.path())?;
+        let names = parse_content(content.as_bytes())
+            .into_iter()
+            .filter_map(|e| match e {
+                Supported { name, .. } => Some(name),
+                Other(_) => None,
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["a.local", "b.local", "c.local"]);
+
+        let args = Args::try_parse_from(["eha", "add", "a.local", "b.local", "--alias", "www.a.local"])?;
+        assert!(args.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_names_from_file() -> Result<(), Error> {
+        let mut names = NamedTempFile::new()?;
+        names.write_all(b"# services\napi.local\n\n  web.local  \n")?;
+        let mut args = Args::try_parse_from(["eha", "add", "db.local", "--from-file", &names.path().to_string_lossy()])?;
+        args.read_names_file()?;
+        assert!(
+            matches!(&args.subcommand, Subcommand::Add { names, from_file: None, .. } if names == &["db.local", "api.local", "web.local"])
+        );
+
+        names.write_all(b"bad_name.local\n")?;
+        let f = NamedTempFile::new()?;
+        let mut args = Args::try_parse_from(["eha", "add", "--from-file", &names.path().to_string_lossy()])?;
+        args.input_file = f.path().to_string_lossy().to_string();
+        args.read_names_file()?;
+        assert!(args.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
 10.0.0.9    other.name
-127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##;
-        f.write_all(input.as_bytes())?;
+# 127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
         let args = Args {
-            subcommand: Subcommand::RemoveExpired,
-            input_file: f.path().to_string_lossy().to_string(),
-            test: true,
+            subcommand: Subcommand::Clear,
+            now: Some("2029-01-01T00:00:00Z".parse()?),
+            test: false,
+            yes: true,
+            ..test_args(&f, &state)
         };
-        args.validate()?;
-        let content = args.run()?.unwrap_or_default();
-        println!("{}", content);
-        assert_eq!(content, input);
+        assert!(args.run()?.is_none());
+        assert_eq!(std::fs::read_to_string(f.path())?, "127.0.0.1   localhost\n10.0.0.9    other.name");
         Ok(())
     }
 
     #[test]
-    fn test_remove_expired_while_adding() -> Result<(), Error> {
-        let mut f = NamedTempFile::new()?;
+    fn test_remove_by_glob() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
         f.write_all(
-            br##"# some leading comments followed by whitespace
+            br##"127.0.0.1   localhost
+127.0.0.1	myapp-1.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1	other.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1	myapp-2.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "myapp-*.local"])?,
+            now: Some("2029-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?,
+            Some(
+                r##"127.0.0.1   localhost
+127.0.0.1	other.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##
+                    .to_string()
+            )
+        );
+        Ok(())
+    }
 
-127.0.0.1   localhost
-10.0.0.9    other.name
-127.0.0.1	foo.local	# eha {"expiry":"2001-01-01T00:00:00Z","comment":"hello world"}"##,
+    #[test]
+    fn test_remove_by_regex() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	pr-12.ci.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1	pr-x.ci.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##,
         )?;
+        let parsed = Args::try_parse_from(["eha", "remove", "--regex", r"^pr-[0-9]+\.ci\.local$"])?;
         let args = Args {
-            subcommand: Subcommand::Add {
-                name: "thing.local".to_string(),
-                expire_minutes: 1,
-            },
-            input_file: f.path().to_string_lossy().to_string(),
-            test: true,
+            subcommand: parsed.subcommand,
+            now: Some("2029-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
         };
-        args.validate()?;
-        let content = args.run()?.unwrap_or_default();
-        println!("{}", content);
-        assert!(content.contains("127.0.0.1\tthing.local\t# eha {"));
-        assert!(!content.contains("127.0.0.1\tfoo.local\t# eha {"));
+        assert_eq!(
+            args.run()?,
+            Some(
+                r##"127.0.0.1   localhost
+127.0.0.1	pr-x.ci.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##
+                    .to_string()
+            )
+        );
+        assert!(Args::try_parse_from(["eha", "remove", "--regex", "(pr"]).is_err());
+
+        let long = format!("{}b.local", "a".repeat(40));
+        for (pattern, name, matched) in [
+            (r"^pr-[0-9]+\.ci\.local$", "pr-123.ci.local", true),
+            (r"^pr-[0-9]+\.ci\.local$", "pr-.ci.local", false),
+            (r"^pr-[0-9]+\.ci\.local$", "pr-123.ci.localhost", false),
+            (r"^pr-[0-9]+\.ci\.local$", "xpr-1.ci.local", false),
+            ("staging", "api.staging.local", true),
+            (r"^(api|web)\d{1,2}\.", "web42.local", true),
+            (r"^(api|web)\d{1,2}\.", "web123.local", false),
+            (r"^[^.]+-v?[a-f]*\.local$", "myapp-vbee.local", true),
+            (r"^(a*)*b\.local$", "aaab.local", true),
+            // nested repeats must not take exponential time
+            ("^(a|aa)*$", long.as_str(), false),
+        ] {
+            std::fs::write(
+                f.path(),
+                format!(r#"127.0.0.1	{}	# eha {{"expiry":"2030-01-01T00:00:00Z","comment":null}}"#, name),
+            )?;
+            let args = Args {
+                subcommand: Subcommand::try_parse_from(["eha", "remove", "--regex", pattern])?,
+                now: Some("2029-01-01T00:00:00Z".parse()?),
+                ..test_args(&f, &state)
+            };
+            let output = args.run()?.unwrap_or_default();
+            assert_eq!(!output.contains(name), matched, "{} {}", pattern, name);
+        }
+        for invalid in ["(a", "a)", "[a-", "*a", r"\q", "a{2,1}"] {
+            assert!(
+                Subcommand::try_parse_from(["eha", "remove", "--regex", invalid]).is_err(),
+                "{}",
+                invalid
+            );
+        }
         Ok(())
     }
 
     #[test]
-    fn test_remove_entry() -> Result<(), Error> {
-        let mut f = NamedTempFile::new()?;
+    fn test_add_existing_name_refreshes_it() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
         f.write_all(
-            br##"# some leading comments followed by whitespace
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":"old","created_at":"2029-12-01T00:00:00Z"}"##,
+        )?;
+        let mut args = Args::try_parse_from(["eha", "add", "foo.local", "--expire", "1h"])?;
+        args = Args {
+            subcommand: args.subcommand,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        let entries = parse_content(output.as_bytes());
+        assert_eq!(entries.len(), 2, "{:?}", output);
+        match &entries[1] {
+            Supported { meta, .. } => {
+                assert_eq!(meta.expiry, Some("2030-01-01T01:00:00Z".parse()?));
+                assert_ne!(meta.comment.as_deref(), Some("old"));
+                assert_eq!(meta.created_at, Some("2029-12-01T00:00:00Z".parse()?));
+            }
+            Other(_) => panic!("expected a managed entry"),
+        }
 
-127.0.0.1   localhost
-10.0.0.9    other.name
-127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":"hello world"}"##,
+        if let Subcommand::Add { no_clobber, .. } = &mut args.subcommand {
+            *no_clobber = true;
+        }
+        assert!(args.run().is_err());
+
+        // the options given again are merged into the existing lines
+        f.as_file_mut().set_len(0)?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":"old","tags":["web"]}
+::1	foo.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":"old","ip":"::1","tags":["web"]}
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":"old"}"##,
+        )?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "foo.local", "--ip", "10.0.0.5", "--tag", "api"])?;
+        let output = args.run()?.unwrap_or_default();
+        let entries = parse_content(output.as_bytes());
+        // a name with an address of its own isn't dual stack, so the ::1 line goes
+        assert_eq!(entries.len(), 3, "{:?}", output);
+        match &entries[1] {
+            Supported { name, meta, .. } => {
+                assert_eq!(name, "foo.local");
+                assert_eq!(meta.ip, Some("10.0.0.5".parse()?));
+                assert_eq!(meta.tags, vec!["web", "api"]);
+                assert_ne!(meta.comment.as_deref(), Some("old"));
+            }
+            Other(_) => panic!("expected a managed entry"),
+        }
+
+        // --ipv6 brings the ::1 line back
+        f.as_file_mut().set_len(0)?;
+        f.write_all(output.as_bytes())?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "foo.local", "--ipv6", "--label", "env=dev"])?;
+        let output = args.run()?.unwrap_or_default();
+        assert!(
+            output.contains("10.0.0.5\tfoo.local") && output.contains("::1\tfoo.local"),
+            "{}",
+            output
+        );
+        assert_eq!(output.matches(r#""labels":{"env":"dev"}"#).count(), 2, "{}", output);
+
+        // an alias can't take the name of another entry
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "foo.local", "--alias", "bar.local"])?;
+        assert!(args.run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_renew_all() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	a.local	# eha {"expiry":"2030-01-01T00:10:00Z","comment":null}
+127.0.0.1	b.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}
+127.0.0.1	c.local	# eha {"expiry":"2030-01-01T00:20:00Z","comment":null}
+# 127.0.0.1	d.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":null}
+127.0.0.1	e.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null}"##,
         )?;
         let args = Args {
-            subcommand: Subcommand::Remove {
-                name: "foo.local".to_string(),
+            subcommand: Subcommand::RenewAll {
+                expire_minutes: Some(1440),
+                selector: None,
             },
-            input_file: f.path().to_string_lossy().to_string(),
-            test: true,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
         };
         args.validate()?;
-        let content = args.run()?.unwrap_or_default();
-        println!("{}", content);
         assert_eq!(
-            content,
-            r##"# some leading comments followed by whitespace
+            args.run()?,
+            Some(
+                r##"127.0.0.1   localhost
+127.0.0.1	a.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null,"renewed_at":["2030-01-01T00:00:00Z"]}
+127.0.0.1	c.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null,"renewed_at":["2030-01-01T00:00:00Z"]}
+# 127.0.0.1	d.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":null}
+127.0.0.1	e.local	# eha {"expiry":"2030-02-01T00:00:00Z","comment":null}"##
+                    .to_string()
+            )
+        );
+        Ok(())
+    }
 
-127.0.0.1   localhost
-10.0.0.9    other.name"##
+    #[test]
+    fn test_set_expiry() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(br##"127.0.0.1	foo.local	# eha {"expiry":"2030-01-01T00:10:00Z","comment":"keep me"}"##)?;
+        for (value, expected) in [("2h", "2030-01-01T02:00:00Z"), ("2030-06-01T12:00:00Z", "2030-06-01T12:00:00Z")] {
+            let args = Args {
+                subcommand: Subcommand::SetExpiry {
+                    name: "foo.local".to_string(),
+                    expiry: parse_expiry(value)?,
+                },
+                now: Some("2030-01-01T00:00:00Z".parse()?),
+                ..test_args(&f, &state)
+            };
+            args.validate()?;
+            assert_eq!(
+                args.run()?,
+                Some(format!(
+                    r##"127.0.0.1	foo.local	# eha {{"expiry":"{}","comment":"keep me","renewed_at":["2030-01-01T00:00:00Z"]}}"##,
+                    expected
+                ))
+            );
+        }
+        let args = Args {
+            subcommand: Subcommand::SetExpiry {
+                name: "bar.local".to_string(),
+                expiry: parse_expiry("2h")?,
+            },
+            ..test_args(&f, &state)
+        };
+        assert!(args.run().is_err());
+        assert!(parse_expiry("soon").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_permanent_entry() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let parsed = Args::try_parse_from(["eha", "add", "team.local", "--no-expire"])?;
+        let mut args = Args {
+            subcommand: parsed.subcommand,
+            test: false,
+            ..test_args(&f, &state)
+        };
+        args.validate()?;
+        assert!(args.run()?.is_none());
+        assert!(std::fs::read_to_string(f.path())?.contains(r#"# eha {"expiry":null,"#));
+
+        args.subcommand = Subcommand::RemoveExpired {
+            dry_run: false,
+            at: None,
+            tag: None,
+        };
+        args.now = Some("2100-01-01T00:00:00Z".parse()?);
+        assert!(args.run()?.is_none());
+        assert!(std::fs::read_to_string(f.path())?.contains("team.local"));
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "team.local"])?;
+        assert!(args.run()?.is_none());
+        assert!(!std::fs::read_to_string(f.path())?.contains("team.local"));
+        assert!(Args::try_parse_from(["eha", "add", "team.local", "--no-expire", "-e", "5"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_show() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1	foo.local www.foo.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":"hello","aliases":["www.foo.local"],"user":"alice","uid":1000,"host":"laptop","command":["eha","add","foo.local","--comment","it's mine"]}"##,
+        )?;
+        let mut args = Args {
+            subcommand: Subcommand::Show {
+                name: "www.foo.local".to_string(),
+            },
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?,
+            Some(
+                r##"name: foo.local
+aliases: www.foo.local
+ip: 127.0.0.1
+expiry: 2030-01-01T00:30:00Z (in 30m)
+comment: hello
+user: alice (uid 1000)
+host: laptop
+command: eha add foo.local --comment 'it'\''s mine'
+line: 127.0.0.1	foo.local www.foo.local	# eha {"expiry":"2030-01-01T00:30:00Z","comment":"hello","aliases":["www.foo.local"],"user":"alice","uid":1000,"host":"laptop","command":["eha","add","foo.local","--comment","it's mine"]}"##
+                    .to_string()
+            )
         );
+        args.subcommand = Subcommand::Show {
+            name: "bar.local".to_string(),
+        };
+        assert!(args.run().is_err());
         Ok(())
     }
 
     #[test]
-    fn test_overwrite_file() -> Result<(), Error> {
-        let mut f = NamedTempFile::new()?;
+    fn test_status() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
         f.write_all(
-            br##"# some leading comments followed by whitespace
+            br##"127.0.0.1   localhost
+127.0.0.1	a.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}
+127.0.0.1	b.local	# eha {"expiry":"2030-01-01T02:00:00Z","comment":null}
+127.0.0.1	c.local	# eha {"expiry":"2030-01-01T00:45:00Z","comment":null}
+::1	c.local	# eha {"expiry":"2030-01-01T00:45:00Z","comment":null,"ip":"::1"}"##,
+        )?;
+        let args = Args {
+            subcommand: Subcommand::Status,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run()?,
+            Some("entries: 3\nexpired: 1\nnext expiry: c.local at 2030-01-01T00:45:00Z (in 45m)\nwritable: yes".to_string())
+        );
+        Ok(())
+    }
 
-127.0.0.1   localhost
-10.0.0.9    other.name"##,
+    #[test]
+    fn test_diff() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n127.0.0.1   other\n127.0.0.1   more\n127.0.0.1   last\n")?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local"])?,
+            diff: true,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        let diff = args.run()?.unwrap_or_default();
+        let p = f.path().to_string_lossy();
+        assert!(
+            diff.starts_with(&format!(
+                "--- {}\n+++ {}\n@@ -2,3 +2,4 @@\n 127.0.0.1   other\n 127.0.0.1   more\n 127.0.0.1   last\n+127.0.0.1\tfoo.local\t# eha ",
+                p, p
+            )),
+            "{}",
+            diff
+        );
+        assert_eq!(std::fs::read_to_string(f.path())?.lines().count(), 4);
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove-expired"])?;
+        assert_eq!(args.run()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}
+"##,
+        )?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove-expired"])?,
+            check: true,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        assert_eq!(args.run()?, None);
+
+        args.now = Some("2030-01-03T00:00:00Z".parse()?);
+        assert_eq!(
+            args.run().unwrap_err().to_string(),
+            format!(
+                "{} would change: expired foo.local at 2030-01-02T00:00:00Z",
+                f.path().to_string_lossy()
+            )
+        );
+        assert!(std::fs::read_to_string(f.path())?.contains("foo.local"));
+        assert_eq!(exit_code(&args.run().unwrap_err(), true), 1);
+
+        // the dry run of remove-expired and tf-plan return early, but are checked the same way
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove-expired", "--dry-run"])?;
+        let err = args.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} would change: expired foo.local at 2030-01-02T00:00:00Z",
+                f.path().to_string_lossy()
+            )
+        );
+        assert_eq!(exit_code(&err, true), 1);
+        args.subcommand = Subcommand::TfPlan;
+        args.terraform_query = Some(BTreeMap::from([("names".to_string(), "bar.local".to_string())]));
+        let err = args.run().unwrap_err();
+        assert!(err.to_string().ends_with("would change: added bar.local"), "{}", err);
+        assert_eq!(exit_code(&err, true), 1);
+        args.now = Some("2030-01-01T00:00:00Z".parse()?);
+        args.terraform_query = Some(BTreeMap::from([("names".to_string(), "foo.local".to_string())]));
+        assert!(args.run()?.unwrap_or_default().contains(r#""changed":"false""#));
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove-expired", "--dry-run"])?;
+        assert_eq!(args.run()?, None);
+
+        // any other failure exits with 2, so that it can't be mistaken for a change
+        args.subcommand = Subcommand::try_parse_from(["eha", "renew", "missing.local"])?;
+        let err = args.run().unwrap_err();
+        assert_eq!((exit_code(&err, true), exit_code(&err, false)), (2, 1));
+
+        // while help and the version are successes, and usage errors keep clap's status
+        for (argv, code) in [
+            (["eha", "--check", "--help"], 0),
+            (["eha", "--check", "--version"], 0),
+            (["eha", "--check", "--bogus"], 2),
+        ] {
+            let err = Error::new(Args::try_parse_from(argv).unwrap_err());
+            assert_eq!((exit_code(&err, true), exit_code(&err, false)), (code, code), "{:?}", argv);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_table() -> Result<(), Error> {
+        let content = br##"127.0.0.1   localhost
+127.0.0.1	old.local	# eha {"expiry":"2029-12-30T00:00:00Z","comment":null}
+127.0.0.1	soon.local	# eha {"expiry":"2030-01-01T00:45:00Z","comment":"demo","project":"shop"}
+127.0.0.1	later.local	# eha {"expiry":"2030-01-02T03:12:00Z","comment":null}
+# 127.0.0.1	off.local	# eha {"expiry":null,"comment":null}
+127.0.0.1	forever.local	# eha {"expiry":null,"comment":null}"##;
+        let entries = parse_content(content);
+        let now = "2030-01-01T00:00:00Z".parse()?;
+        assert_eq!(
+            list_table(&entries, now, |_| true, false).unwrap_or_default(),
+            "NAME           STATE          EXPIRES           PROJECT  COMMENT
+old.local      expired        expired 2d ago
+soon.local     expiring soon  expires in 45m    shop     demo
+later.local    active         expires in 1d 3h
+off.local      disabled
+forever.local  active         never expires"
+        );
+        let coloured = list_table(&entries, now, |_| true, true).unwrap_or_default();
+        assert!(coloured.contains("soon.local     \x1b[33mexpiring soon\x1b[0m  expires in 45m"));
+        assert_eq!(list_table(&entries, now, |m| has_tag(m, Some("missing")), true), None);
+
+        assert_eq!(humanize(SignedDuration::from_mins(192)), "3h 12m");
+        assert_eq!(humanize(SignedDuration::from_hours(48)), "2d");
+        assert_eq!(humanize(SignedDuration::from_hours(3)), "3h");
+        assert_eq!(humanize(SignedDuration::from_secs(30)), "1m");
+        assert_eq!(humanize(SignedDuration::from_secs(-30)), "0m");
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_json() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	a.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}
+127.0.0.1	b.local	# eha {"expiry":"2030-01-01T02:00:00Z","comment":"hi","tags":["x"]}
+"##,
+        )?;
+        let mut args = Args {
+            subcommand: Subcommand::Status,
+            output: Output::Json,
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            ..test_args(&f, &state)
+        };
+        let json = |args: &Args| -> Result<serde_json::Value, Error> { Ok(serde_json::from_str(&args.run()?.unwrap_or_default())?) };
+        assert_eq!(
+            json(&args)?,
+            serde_json::json!({
+                "entries": 2,
+                "expired": 1,
+                "next_expiry": {"name": "b.local", "at": "2030-01-01T02:00:00Z"},
+                "writable": true,
+            })
+        );
+
+        args.subcommand = Subcommand::List {
+            tag: Some("x".to_string()),
+            selector: None,
+        };
+        let listed = json(&args)?;
+        assert_eq!(listed.as_array().map(Vec::len), Some(1));
+        assert_eq!(listed[0]["name"], "b.local");
+        assert_eq!(listed[0]["comment"], "hi");
+        assert_eq!(listed[0]["expired"], false);
+        args.subcommand = Subcommand::Show {
+            name: "a.local".to_string(),
+        };
+        assert_eq!(json(&args)?[0]["expired"], true);
+
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "c.local"])?;
+        let report = json(&args)?;
+        assert_eq!(report["changed"], true);
+        assert_eq!(
+            report["changes"],
+            serde_json::json!([
+                {"name": "a.local", "action": "expired", "expiry_before": "2029-01-01T00:00:00Z", "expiry_after": null},
+                {"name": "c.local", "action": "added", "expiry_before": null, "expiry_after": "2030-01-02T00:00:00Z"},
+            ])
+        );
+        assert!(report["content"].as_str().is_some_and(|c| c.contains("c.local")));
+
+        args.test = false;
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove-expired"])?;
+        args.run()?;
+        let report = json(&args)?;
+        assert_eq!(report["changed"], false);
+        assert_eq!(report["content"], serde_json::Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_by_tag() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1   localhost
+127.0.0.1	foo.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null,"tags":["project-x"]}
+127.0.0.1	bar.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null}
+127.0.0.1	old.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null,"tags":["project-x"]}
+127.0.0.1	stale.local	# eha {"expiry":"2029-01-01T00:00:00Z","comment":null}"##,
         )?;
+        let now = Some("2030-01-01T00:00:00Z".parse()?);
         let args = Args {
-            subcommand: Subcommand::Add {
-                name: "foo.local".to_string(),
-                expire_minutes: 1,
+            subcommand: Subcommand::List {
+                tag: Some("project-x".to_string()),
+                selector: None,
             },
-            input_file: f.path().to_string_lossy().to_string(),
+            now,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(output.starts_with("foo.local\t"));
+        assert!(output.lines().nth(1).unwrap_or_default().starts_with("old.local\t"));
+        assert_eq!(output.lines().count(), 2);
+
+        let args = Args {
+            subcommand: Subcommand::RemoveExpired {
+                dry_run: false,
+                at: None,
+                tag: Some("project-x".to_string()),
+            },
+            now,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(!output.contains("old.local"));
+        assert!(output.contains("stale.local"));
+
+        let args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "--tag", "project-x"])?,
+            now,
+            ..test_args(&f, &state)
+        };
+        let output = args.run()?.unwrap_or_default();
+        assert!(!output.contains("foo.local"));
+        assert!(output.contains("bar.local"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_defaults() -> Result<(), Error> {
+        let f = NamedTempFile::new()?;
+        let config = Config {
+            input_file: Some(f.path().to_string_lossy().to_string()),
+            default_ip: Some("127.0.0.2".parse()?),
+            ..Config::default()
+        };
+        let matches = Args::command().try_get_matches_from(["eha", "--test", "add", "foo.local"])?;
+        let mut args = Args::from_arg_matches(&matches)?;
+        args.apply_config(config.clone(), &matches);
+        assert_eq!(args.input_file, f.path().to_string_lossy());
+        assert!(args.run()?.unwrap_or_default().starts_with("127.0.0.2\tfoo.local\t"));
+
+        // flags win over the config
+        let matches = Args::command().try_get_matches_from(["eha", "--input-file", "/tmp/other", "list"])?;
+        let mut args = Args::from_arg_matches(&matches)?;
+        args.apply_config(config, &matches);
+        assert_eq!(args.input_file, "/tmp/other");
+        Ok(())
+    }
+
+    #[test]
+    fn test_suffix_flag() -> Result<(), Error> {
+        let args = Args::try_parse_from([
+            "eha",
+            "--input-file",
+            "/nonexistent/hosts",
+            "--suffix",
+            ".test,.dev.internal",
+            "add",
+            "api.dev.internal",
+        ])?;
+        assert_eq!(args.suffixes, [".test", ".dev.internal"]);
+        args.validate()?;
+        let args = Args::try_parse_from(["eha", "--input-file", "/nonexistent/hosts", "--suffix", ".test", "add", "api.local"])?;
+        assert_eq!(args.validate().unwrap_err().to_string(), "name must end in .test");
+        for invalid in ["test", ".", "..test"] {
+            assert!(parse_suffix(invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_respects_max_expiry() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(br##"127.0.0.1	foo.local	# eha {"expiry":null,"comment":null}"##)?;
+        let mut policy = NamedTempFile::new()?;
+        policy.write_all(b"max_expire_minutes = 60\n")?;
+        let state_dir = tempdir()?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "remove", "foo.local"])?,
             test: false,
+            state_dir: Some(state_dir.path().to_string_lossy().to_string()),
+            policy_file: Some(policy.path().to_string_lossy().to_string()),
+            ..test_args(&f, &state)
+        };
+        args.run()?;
+
+        args.subcommand = Subcommand::Restore {
+            name: Some("foo.local".to_string()),
+            expire_minutes: None,
+            list: false,
+            backup: None,
+        };
+        let err = args.run().unwrap_err();
+        assert_eq!(err.to_string(), "entry foo.local can't be restored with its original expiry");
+        assert!(err.root_cause().to_string().contains("can't be permanent"));
+
+        args.subcommand = Subcommand::Restore {
+            name: Some("foo.local".to_string()),
+            expire_minutes: Some(30),
+            list: false,
+            backup: None,
         };
         args.validate()?;
-        assert!(args.run()?.is_none());
+        args.run()?;
+        Ok(())
+    }
 
-        let mut f2 = File::open(f.path())?;
-        let mut content = String::new();
-        f2.read_to_string(&mut content)?;
-        assert!(content.contains("127.0.0.1\tfoo.local\t# eha {"));
+    #[test]
+    fn test_max_entries() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(
+            br##"127.0.0.1	new.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null,"created_at":"2029-12-31T00:00:00Z"}
+127.0.0.1	old.local	# eha {"expiry":"2030-01-02T00:00:00Z","comment":null,"created_at":"2029-12-01T00:00:00Z"}"##,
+        )?;
+        let mut policy = NamedTempFile::new()?;
+        policy.write_all(b"max_entries = 2\n")?;
+        let mut args = Args::try_parse_from(["eha", "add", "foo.local"])?;
+        args = Args {
+            now: Some("2030-01-01T00:00:00Z".parse()?),
+            policy_file: Some(policy.path().to_string_lossy().to_string()),
+            subcommand: args.subcommand,
+            ..test_args(&f, &state)
+        };
+        assert!(args
+            .run()
+            .unwrap_err()
+            .to_string()
+            .ends_with("consider removing the oldest: old.local, new.local"));
+
+        // refreshing or removing entries is still allowed
+        args.subcommand = Subcommand::try_parse_from(["eha", "add", "new.local"])?;
+        args.run()?;
+        args.subcommand = Subcommand::try_parse_from(["eha", "remove", "old.local"])?;
+        args.run()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_unchanged() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"127.0.0.1   localhost\n")?;
+        let args = test_args(&f, &state);
+        let content = read_content(&args.input_file)?;
+        args.check_unchanged(&content)?;
+
+        // same length, different content
+        std::fs::write(f.path(), "127.0.0.1   localhosx\n")?;
+        let e = args.check_unchanged(&content).unwrap_err();
+        assert!(file_changed(&e));
+        let temp = PathBuf::from(format!("{}.tmp", f.path().to_string_lossy()));
+        let e = replace_file(f.path(), &temp, b"", || args.check_unchanged(&content)).unwrap_err();
+        assert!(file_changed(&e));
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read_to_string(f.path())?, "127.0.0.1   localhosx\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_sentinel() -> Result<(), Error> {
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        f.write_all(b"# eha-locked: bob, frozen for the audit\n127.0.0.1   localhost\n")?;
+        let mut args = Args {
+            subcommand: Subcommand::try_parse_from(["eha", "add", "foo.local"])?,
+            ..test_args(&f, &state)
+        };
+        assert_eq!(
+            args.run().unwrap_err().to_string(),
+            format!(
+                "{} is locked against changes by eha on line 1: bob, frozen for the audit, pass --override-lock to change it anyway",
+                f.path().to_string_lossy()
+            )
+        );
+        args.override_lock = true;
+        assert!(args.run()?.unwrap_or_default().contains("foo.local"));
 
+        // reading is still fine
+        args.override_lock = false;
+        args.subcommand = Subcommand::List { tag: None, selector: None };
+        args.run()?;
         Ok(())
     }
 }
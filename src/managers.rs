@@ -0,0 +1,188 @@
+/// A tool that manages part or all of a hosts file, recognised by the marker comments it writes.
+struct Manager {
+    name: &'static str,
+    start: &'static str,
+    /// The marker at the end of the section, or None if the tool regenerates the whole file.
+    end: Option<&'static str>,
+}
+
+const MANAGERS: &[Manager] = &[
+    Manager {
+        name: "Docker Desktop",
+        start: "# Added by Docker Desktop",
+        end: Some("# End of section"),
+    },
+    Manager {
+        name: "hostctl",
+        start: "# profile.on ",
+        end: Some("# end"),
+    },
+    Manager {
+        name: "hostctl",
+        start: "# profile.off ",
+        end: Some("# end"),
+    },
+    Manager {
+        name: "Ansible",
+        start: "# BEGIN ANSIBLE MANAGED BLOCK",
+        end: Some("# END ANSIBLE MANAGED BLOCK"),
+    },
+    Manager {
+        name: "vagrant-hostmanager",
+        start: "## vagrant-hostmanager-start",
+        end: Some("## vagrant-hostmanager-end"),
+    },
+    Manager {
+        name: "Cloudflare WARP",
+        start: "# BEGIN Cloudflare WARP",
+        end: Some("# END Cloudflare WARP"),
+    },
+    Manager {
+        name: "WSL",
+        start: "# This file was automatically generated by WSL",
+        end: None,
+    },
+    Manager {
+        name: "Kubernetes",
+        start: "# Kubernetes-managed hosts file",
+        end: None,
+    },
+];
+
+/// A run of lines owned by another tool, from the line of its start marker to the line of its end marker.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Section {
+    pub(crate) manager: &'static str,
+    pub(crate) start: usize,
+    /// The line of the end marker, or None if the section runs to the end of the file.
+    pub(crate) end: Option<usize>,
+    /// Whether the tool regenerates the whole file rather than just its section.
+    pub(crate) whole_file: bool,
+}
+
+/// Finds the sections of other hosts file managers in the given lines.
+pub(crate) fn find_sections<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut open: Option<(&Manager, usize)> = None;
+    for (i, line) in lines.into_iter().enumerate() {
+        let line = line.trim();
+        match open {
+            Some((m, start)) if m.end.is_some_and(|e| line.starts_with(e)) => {
+                sections.push(Section {
+                    manager: m.name,
+                    start,
+                    end: Some(i),
+                    whole_file: false,
+                });
+                open = None;
+            }
+            Some(_) => {}
+            None => {
+                if let Some(m) = MANAGERS.iter().find(|m| line.starts_with(m.start)) {
+                    match m.end {
+                        Some(_) => open = Some((m, i)),
+                        None => sections.push(Section {
+                            manager: m.name,
+                            start: i,
+                            end: None,
+                            whole_file: true,
+                        }),
+                    }
+                }
+            }
+        }
+    }
+    if let Some((m, start)) = open {
+        sections.push(Section {
+            manager: m.name,
+            start,
+            end: None,
+            whole_file: false,
+        });
+    }
+    sections
+}
+
+/// Moves a position that new lines would be inserted at out of any section owned by another tool, to just after the
+/// end of the section, or to before it if the section runs to the end of the file.
+pub(crate) fn outside_sections(sections: &[Section], position: usize) -> usize {
+    for s in sections.iter().filter(|s| !s.whole_file && position > s.start) {
+        match s.end {
+            Some(end) if position <= end => return end + 1,
+            None => return s.start,
+            _ => {}
+        }
+    }
+    position
+}
+
+/// Returns a warning for each tool that regenerates the whole file and would drop eha's entries.
+pub(crate) fn clobber_warnings(sections: &[Section]) -> Vec<String> {
+    sections
+        .iter()
+        .filter(|s| s.whole_file)
+        .map(|s| {
+            format!(
+                "warning: this hosts file is regenerated by {}, which will drop entries added by eha; consider keeping \
+                 them in a manifest and running `eha apply` again after it regenerates",
+                s.manager
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_sections() {
+        let content = "127.0.0.1   localhost
+# Added by Docker Desktop
+192.168.1.2 host.docker.internal
+# End of section
+# profile.on dev
+127.0.0.1 dev.local";
+        let sections = find_sections(content.lines());
+        assert_eq!(
+            sections,
+            vec![
+                Section {
+                    manager: "Docker Desktop",
+                    start: 1,
+                    end: Some(3),
+                    whole_file: false
+                },
+                Section {
+                    manager: "hostctl",
+                    start: 4,
+                    end: None,
+                    whole_file: false
+                },
+            ]
+        );
+        assert_eq!(outside_sections(&sections, 1), 1);
+        assert_eq!(outside_sections(&sections, 2), 4);
+        assert_eq!(outside_sections(&sections, 6), 4);
+        assert!(clobber_warnings(&sections).is_empty());
+
+        let content = "127.0.0.1   localhost
+# BEGIN Cloudflare WARP
+162.159.36.1 warp.local
+# END Cloudflare WARP";
+        let sections = find_sections(content.lines());
+        assert_eq!(
+            sections,
+            vec![Section {
+                manager: "Cloudflare WARP",
+                start: 1,
+                end: Some(3),
+                whole_file: false
+            }]
+        );
+        assert_eq!(outside_sections(&sections, 2), 4);
+
+        let sections = find_sections(["# This file was automatically generated by WSL. To stop it, add [network]"]);
+        assert_eq!(clobber_warnings(&sections).len(), 1);
+    }
+}
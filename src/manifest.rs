@@ -0,0 +1,222 @@
+use crate::policy::Policy;
+use crate::Entry::Supported;
+use crate::{new_entry, new_meta, validate_entry, validate_expire_minutes, Entry};
+use anyhow::{anyhow, Context, Error};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Add;
+
+/// A manifest describes the entries that should be present in the hosts file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    pub name: String,
+    #[serde(default = "default_expire_minutes")]
+    pub expire_minutes: usize,
+}
+
+fn default_expire_minutes() -> usize {
+    1440
+}
+
+impl Manifest {
+    /// Reads a json or yaml manifest from the given path, or from stdin if the path is "-", and validates it against the policy.
+    pub fn load(path: &str, policy: &Policy) -> Result<Self, Error> {
+        let mut raw = String::new();
+        if path == "-" {
+            std::io::stdin()
+                .read_to_string(&mut raw)
+                .context("failed to read manifest from stdin")?;
+        } else {
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut raw))
+                .with_context(|| format!("failed to read manifest {}", path))?;
+        }
+        let manifest = Self::parse(&raw)?;
+        manifest.validate(policy)?;
+        Ok(manifest)
+    }
+
+    /// Parses a manifest written as a json object, or otherwise as yaml.
+    pub fn parse(raw: &str) -> Result<Self, Error> {
+        match raw.trim_start().starts_with('{') {
+            true => serde_json::from_str(raw).context("failed to parse json manifest"),
+            false => serde_norway::from_str(raw).context("failed to parse yaml manifest"),
+        }
+    }
+
+    /// Builds a manifest from a Terraform external data source query read from stdin. The protocol only allows string
+    /// values, so names are given as a comma separated list with optional expire_minutes and prune keys.
+    pub fn from_terraform_query(query: &BTreeMap<String, String>, policy: &Policy) -> Result<(Self, bool), Error> {
+        let expire_minutes = match query.get("expire_minutes") {
+            Some(m) => m.parse().context("expire_minutes must be a number")?,
+            None => default_expire_minutes(),
+        };
+        let prune = match query.get("prune").map(String::as_str) {
+            Some("true") => true,
+            Some("false") | Some("") | None => false,
+            Some(other) => return Err(anyhow!("prune must be true or false, not {}", other)),
+        };
+        let entries = query
+            .get("names")
+            .map(String::as_str)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(|n| ManifestEntry {
+                name: n.to_string(),
+                expire_minutes,
+            })
+            .collect();
+        let manifest = Manifest { entries };
+        manifest.validate(policy)?;
+        Ok((manifest, prune))
+    }
+
+    pub fn validate(&self, policy: &Policy) -> Result<(), Error> {
+        for e in &self.entries {
+            // manifest entries always point at 127.0.0.1
+            validate_entry(&e.name, None, policy)
+                .and_then(|_| validate_expire_minutes(e.expire_minutes, policy))
+                .with_context(|| format!("invalid manifest entry {}", e.name))?;
+        }
+        Ok(())
+    }
+
+    /// Reconciles the entries with the manifest. Missing entries are added and disabled entries are enabled. Entries
+    /// that are already present are renewed when their expiry is later than the manifest allows, or when less than half
+    /// of the manifest's expiry is left, so that applying regularly keeps them alive while applying twice in a row
+    /// changes nothing. When pruning, entries added by eha that are not in the manifest are removed. Added entries are
+    /// inserted at the given position and recorded with the given source. Returns a description of each change made.
+    pub fn apply(&self, entries: &mut Vec<Entry>, position: usize, now: Timestamp, prune: bool, source: &str) -> Vec<String> {
+        let mut changes = Vec::new();
+        let mut position = position.min(entries.len());
+        for me in &self.entries {
+            let duration = SignedDuration::from_mins(me.expire_minutes as i64);
+            let expiry = now.add(duration);
+            let (mut found, mut enabled, mut renewed) = (false, false, false);
+            // every line of a dual stack name is reconciled together
+            for e in entries.iter_mut() {
+                if let Supported { name, meta, disabled, raw } = e {
+                    if *name != me.name {
+                        continue;
+                    }
+                    found = true;
+                    if *disabled {
+                        *disabled = false;
+                        *raw = None;
+                        enabled = true;
+                    }
+                    if meta.expiry.is_none_or(|e| e > expiry || e.duration_since(now) < duration / 2) {
+                        meta.renew(Some(expiry), now);
+                        *raw = None;
+                        renewed = true;
+                    }
+                }
+            }
+            if !found {
+                entries.insert(position, new_entry(&me.name, new_meta(Some(expiry), now, source)));
+                position += 1;
+                changes.push(format!("added {}", me.name));
+            }
+            if enabled {
+                changes.push(format!("enabled {}", me.name));
+            }
+            if renewed {
+                changes.push(format!("renewed {} until {}", me.name, expiry));
+            }
+        }
+        if prune {
+            entries.retain(|e| match e {
+                Supported { name, .. } if !self.entries.iter().any(|me| me.name == *name) => {
+                    changes.push(format!("removed {}", name));
+                    false
+                }
+                _ => true,
+            });
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_manifest() -> Result<(), Error> {
+        let content = r##"127.0.0.1   localhost
+# 127.0.0.1	a.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1	b.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}
+127.0.0.1	c.local	# eha {"expiry":"2030-01-01T00:00:00Z","comment":null}"##;
+        let mut entries = content.lines().map(Entry::try_from).collect::<Result<Vec<Entry>, Error>>()?;
+        let manifest: Manifest = serde_json::from_str(r#"{"entries":[{"name":"a.local"},{"name":"b.local"},{"name":"d.local"}]}"#)?;
+        manifest.validate(&Policy::default())?;
+        let changes = manifest.apply(&mut entries, 1, "2029-12-31T12:00:00Z".parse()?, true, "apply");
+        assert_eq!(changes, vec!["enabled a.local", "added d.local", "removed c.local"]);
+        assert!(entries[1].has_name("d.local"));
+        assert!(manifest
+            .apply(&mut entries, 4, "2029-12-31T12:00:00Z".parse()?, true, "apply")
+            .is_empty());
+
+        // entries about to expire are renewed, as are those that outlive the manifest's expiry
+        let changes = manifest.apply(&mut entries, 4, "2029-12-31T23:00:00Z".parse()?, true, "apply");
+        assert_eq!(
+            changes,
+            vec![
+                "renewed a.local until 2030-01-01T23:00:00Z",
+                "renewed b.local until 2030-01-01T23:00:00Z"
+            ]
+        );
+        let manifest: Manifest = serde_json::from_str(r#"{"entries":[{"name":"b.local","expire_minutes":60}]}"#)?;
+        let changes = manifest.apply(&mut entries, 4, "2029-12-31T23:00:00Z".parse()?, false, "apply");
+        assert_eq!(changes, vec!["renewed b.local until 2030-01-01T00:00:00Z"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_terraform_query() -> Result<(), Error> {
+        let query: BTreeMap<String, String> = serde_json::from_str(r#"{"names":"a.local, b.local","expire_minutes":"60"}"#)?;
+        let (manifest, prune) = Manifest::from_terraform_query(&query, &Policy::default())?;
+        assert!(!prune);
+        assert_eq!(
+            manifest
+                .entries
+                .iter()
+                .map(|e| (e.name.as_str(), e.expire_minutes))
+                .collect::<Vec<_>>(),
+            vec![("a.local", 60), ("b.local", 60)]
+        );
+        let query: BTreeMap<String, String> = serde_json::from_str(r#"{"names":"a.local","prune":"yes"}"#)?;
+        assert!(Manifest::from_terraform_query(&query, &Policy::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_manifest() -> Result<(), Error> {
+        let manifest: Manifest = serde_json::from_str(r#"{"entries":[{"name":"a.com"}]}"#)?;
+        assert!(manifest.validate(&Policy::default()).is_err());
+        assert!(serde_json::from_str::<Manifest>(r#"{"entries":[{"nme":"a.local"}]}"#).is_err());
+        assert!(Manifest::parse("entries:\n  - nme: a.local\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_yaml_manifest() -> Result<(), Error> {
+        let manifest = Manifest::parse("# lab names\nentries:\n  - name: a.local\n  - name: b.local\n    expire_minutes: 60\n")?;
+        let json = Manifest::parse(r#"{"entries":[{"name":"a.local"},{"name":"b.local","expire_minutes":60}]}"#)?;
+        assert_eq!(serde_json::to_string(&manifest)?, serde_json::to_string(&json)?);
+        assert_eq!(manifest.entries[0].expire_minutes, 1440);
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use crate::events::{Event, Snapshot};
+use crate::HostsFile;
+use anyhow::{anyhow, Error};
+use jiff::Timestamp;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// A handle on a hosts file for async code, such as a daemon that keeps names for as long as it runs. Every operation
+/// does the blocking file IO of [`HostsFile`] on a worker thread and resolves once that is done, so it doesn't block
+/// the runtime polling it and works with any runtime. The handle is cheap to clone and can be shared between tasks.
+#[derive(Debug, Clone)]
+pub struct AsyncHostsFile {
+    path: PathBuf,
+}
+
+impl AsyncHostsFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        AsyncHostsFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the hosts file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the hosts file, see [`HostsFile::load`].
+    pub fn load(&self) -> Task<HostsFile> {
+        let path = self.path.clone();
+        spawn_blocking(move || HostsFile::load(path))
+    }
+
+    /// Loads the hosts file, makes the change and saves it, resolving to what the change returned. Nothing is written
+    /// if the change fails. Like [`HostsFile::save`], this fails with [`crate::FileChanged`] if something else changed
+    /// the file in the meantime, and can then be tried again.
+    pub fn update<T: Send + 'static>(&self, change: impl FnOnce(&mut HostsFile) -> Result<T, Error> + Send + 'static) -> Task<T> {
+        let path = self.path.clone();
+        spawn_blocking(move || {
+            let mut hosts = HostsFile::load(path)?;
+            let result = change(&mut hosts)?;
+            hosts.save()?;
+            Ok(result)
+        })
+    }
+
+    /// Subscribes to changes to the hosts file, which a worker thread checks for at the given interval until the
+    /// subscription is dropped. See [`Watch::changed`] for what each change resolves to.
+    pub fn watch(&self, interval: Duration) -> Watch {
+        let path = self.path.to_string_lossy().to_string();
+        let shared = channel();
+        let sender = Sender(shared.clone());
+        let spawned = spawn_worker(move || {
+            let mut previous = Timestamp::now();
+            let mut snapshot = match Snapshot::read(&path) {
+                Ok(s) => s,
+                Err(e) => return sender.send(Err(e)),
+            };
+            sender.send(Ok(Snapshot::default().diff(&snapshot, previous, previous)));
+            while !sender.dropped() {
+                std::thread::sleep(interval);
+                let now = Timestamp::now();
+                let next = match Snapshot::read(&path) {
+                    Ok(s) => s,
+                    Err(e) => return sender.send(Err(e)),
+                };
+                let events = snapshot.diff(&next, previous, now);
+                if !events.is_empty() {
+                    sender.send(Ok(events));
+                }
+                (snapshot, previous) = (next, now);
+            }
+        });
+        if let Err(e) = spawned {
+            lock(&shared).queue.push_back(Err(e));
+        }
+        Watch(shared)
+    }
+}
+
+/// The results a worker thread has sent that haven't been received yet, shared with the side waiting for them.
+struct Channel<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    /// Whether the worker thread has stopped, so that nothing more will be sent.
+    finished: bool,
+    /// Whether the receiving side is gone, so that the worker thread can stop.
+    dropped: bool,
+}
+
+type Shared<T> = Arc<Mutex<Channel<T>>>;
+
+fn channel<T>() -> Shared<T> {
+    Arc::new(Mutex::new(Channel {
+        queue: VecDeque::new(),
+        waker: None,
+        finished: false,
+        dropped: false,
+    }))
+}
+
+/// A panicking worker thread doesn't leave anything inconsistent behind, so a poisoned lock is used as it is.
+fn lock<T>(shared: &Shared<T>) -> MutexGuard<'_, Channel<T>> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn poll_recv<T>(shared: &Shared<T>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+    let mut channel = lock(shared);
+    if let Some(value) = channel.queue.pop_front() {
+        return Poll::Ready(Some(value));
+    }
+    if channel.finished {
+        return Poll::Ready(None);
+    }
+    channel.waker = Some(cx.waker().clone());
+    Poll::Pending
+}
+
+/// The sending side held by a worker thread. It marks the channel as finished when dropped, including when the worker
+/// panics, so that the receiving side isn't left waiting forever.
+struct Sender<T>(Shared<T>);
+
+impl<T> Sender<T> {
+    fn send(&self, value: T) {
+        let mut channel = lock(&self.0);
+        channel.queue.push_back(value);
+        if let Some(waker) = channel.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn dropped(&self) -> bool {
+        lock(&self.0).dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = lock(&self.0);
+        channel.finished = true;
+        if let Some(waker) = channel.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn spawn_worker(work: impl FnOnce() + Send + 'static) -> Result<(), Error> {
+    std::thread::Builder::new()
+        .name("eha-worker".to_string())
+        .spawn(work)
+        .map(|_| ())
+        .map_err(|e| Error::new(e).context("failed to start worker thread"))
+}
+
+/// Runs blocking work on a worker thread of its own, returning a future that resolves to its result.
+pub fn spawn_blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Task<T> {
+    let shared = channel();
+    let sender = Sender(shared.clone());
+    if let Err(e) = spawn_worker(move || sender.send(work())) {
+        lock(&shared).queue.push_back(Err(e));
+    }
+    Task(shared)
+}
+
+/// The result of blocking work running on a worker thread, see [`spawn_blocking`]. Dropping it doesn't stop the work.
+pub struct Task<T>(Shared<Result<T, Error>>);
+
+impl<T> Future for Task<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_recv(&self.0, cx).map(|r| r.unwrap_or_else(|| Err(anyhow!("the worker thread panicked"))))
+    }
+}
+
+/// A subscription to changes to a hosts file, see [`AsyncHostsFile::watch`]. Dropping it stops the worker thread.
+pub struct Watch(Shared<Result<Vec<Event>, Error>>);
+
+impl Watch {
+    /// Waits for the next change to the file, resolving to the events that describe it, in the same form as those of
+    /// `eha events`. The first change resolves once watching has started, with an added event for every entry already
+    /// in the file, if there are any. The file is no longer watched after a change fails to be read, and every change
+    /// after that resolves to None.
+    pub fn changed(&mut self) -> Next<'_> {
+        Next(self)
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        lock(&self.0).dropped = true;
+    }
+}
+
+/// The next change of a [`Watch`].
+pub struct Next<'a>(&'a Watch);
+
+impl Future for Next<'_> {
+    type Output = Option<Result<Vec<Event>, Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_recv(&self.0 .0, cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_meta;
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::task::Wake;
+    use std::thread::Thread;
+    use tempfile::{tempdir, NamedTempFile};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Runs a future to completion on the current thread, standing in for the runtime of the embedding application.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_async_hosts_file() -> Result<(), Error> {
+        assert_send_sync::<AsyncHostsFile>();
+        assert_send_sync::<Task<HostsFile>>();
+        assert_send_sync::<Watch>();
+
+        let state = tempdir()?;
+        let mut f = NamedTempFile::new_in(state.path())?;
+        writeln!(f, r##"127.0.0.1	a.local	# eha {{"expiry":"2030-01-01T00:00:00Z","comment":null}}"##)?;
+        let hosts = AsyncHostsFile::new(f.path());
+        assert!(block_on(hosts.load())?.query("a.local")?.is_some());
+
+        let mut watch = hosts.watch(Duration::from_millis(10));
+        let names = |events: Vec<Event>| {
+            events
+                .into_iter()
+                .map(|e| (e.event, e.name.unwrap_or_default()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(block_on(watch.changed()).expect("watching should start")?),
+            [("added", "a.local".to_string())]
+        );
+
+        // the update runs on a worker thread while the caller awaits it
+        let caller = std::thread::current().id();
+        let now = Timestamp::now();
+        let worker = block_on(hosts.update(move |h| {
+            h.add("b.local", new_meta(None, now, "test"))?;
+            Ok(std::thread::current().id())
+        }))?;
+        assert_ne!(worker, caller);
+        assert_eq!(
+            names(block_on(watch.changed()).expect("the file should still be watched")?),
+            [("added", "b.local".to_string())]
+        );
+
+        // a failed change leaves the file as it was, and a panicking one resolves to an error
+        let before = std::fs::read(f.path())?;
+        assert!(block_on(hosts.update(|h| h.remove("missing.local"))).is_err());
+        assert!(block_on(hosts.update(|_| -> Result<(), Error> { panic!("change failed") })).is_err());
+        assert_eq!(std::fs::read(f.path())?, before);
+
+        // the file can be awaited from other threads, and watching stops once it can't be read
+        let (tx, rx) = mpsc::channel();
+        let shared = hosts.clone();
+        std::thread::spawn(move || tx.send(block_on(shared.load()).map(|h| h.content().to_vec()).ok()));
+        assert_eq!(rx.recv()?, Some(before));
+        std::fs::remove_file(f.path())?;
+        assert!(block_on(watch.changed()).expect("the error should be sent").is_err());
+        assert!(block_on(watch.changed()).is_none());
+        Ok(())
+    }
+}
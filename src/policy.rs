@@ -0,0 +1,283 @@
+use crate::config::Config;
+use crate::header::FileConfig;
+use anyhow::{anyhow, Context, Error};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The default location of the admin managed policy file.
+pub const DEFAULT_POLICY_FILE: &str = "/etc/eha/policy.toml";
+
+/// An admin managed policy restricting which entries can be added.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Names must end in one of these suffixes. Defaults to .local and .localhost.
+    allowed_suffixes: Option<Vec<String>>,
+    /// Patterns of names that can't be added, where * matches any run of characters and ? matches one character.
+    #[serde(default)]
+    denied_patterns: Vec<String>,
+    /// The longest expiry any entry can be given.
+    max_expire_minutes: Option<usize>,
+    /// The most names eha may manage in the hosts file at once.
+    max_entries: Option<usize>,
+    /// Whether names may point at addresses other than loopback ones like 127.0.0.1 and ::1. Defaults to true.
+    allow_non_loopback: Option<bool>,
+    /// Prevents --policy-file from replacing the default policy file.
+    #[serde(default)]
+    locked: bool,
+    /// The file the policy was read from, to point at in errors.
+    #[serde(skip)]
+    path: Option<String>,
+}
+
+impl Policy {
+    /// Loads the effective policy. The default policy file applies unless another path is given and the default policy
+    /// isn't locked. A missing policy file allows everything eha normally allows.
+    pub fn load(override_path: Option<&str>) -> Result<Self, Error> {
+        let default = Self::load_file(Path::new(DEFAULT_POLICY_FILE), true)?;
+        match override_path {
+            Some(_) if default.as_ref().is_some_and(|p| p.locked) => Err(anyhow!(
+                "the policy in {} is locked and can't be replaced with --policy-file",
+                DEFAULT_POLICY_FILE
+            )),
+            Some(p) => Ok(Self::load_file(Path::new(p), false)?.unwrap_or_default()),
+            None => Ok(default.unwrap_or_default()),
+        }
+    }
+
+    fn load_file(path: &Path, require_root: bool) -> Result<Option<Self>, Error> {
+        let p = path.to_string_lossy();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::new(e).context(format!("failed to read policy file {}", p))),
+        };
+        // the ownership is checked on the open file, so that the file can't be swapped for another before it is read
+        #[cfg(unix)]
+        if require_root {
+            use std::os::unix::fs::MetadataExt;
+            let meta = file.metadata().with_context(|| format!("failed to stat policy file {}", p))?;
+            if meta.uid() != 0 || meta.mode() & 0o022 != 0 {
+                return Err(anyhow!("policy file {} must be owned by root and only writable by root", p));
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = require_root;
+        let mut raw = String::new();
+        file.read_to_string(&mut raw)
+            .with_context(|| format!("failed to read policy file {}", p))?;
+        let mut policy: Self = toml::from_str(&raw).with_context(|| format!("failed to parse policy file {}", p))?;
+        policy.path = Some(p.to_string());
+        Ok(Some(policy))
+    }
+
+    /// Uses the allowed suffixes given with --suffix when the policy doesn't set any.
+    pub fn with_flag_suffixes(mut self, suffixes: &[String]) -> Self {
+        if self.allowed_suffixes.is_none() && !suffixes.is_empty() {
+            self.allowed_suffixes = Some(suffixes.to_vec());
+        }
+        self
+    }
+
+    /// Uses the allowed suffixes from the hosts file header when neither the policy nor --suffix set any.
+    pub fn with_file_defaults(mut self, config: &FileConfig) -> Self {
+        if self.allowed_suffixes.is_none() {
+            self.allowed_suffixes = config.allowed_suffixes.clone();
+        }
+        self
+    }
+
+    /// Uses the allowed suffixes from the config files when neither the policy nor the hosts file header set any.
+    pub fn with_config_defaults(mut self, config: &Config) -> Self {
+        if self.allowed_suffixes.is_none() {
+            self.allowed_suffixes = config.allowed_suffixes.clone();
+        }
+        self
+    }
+
+    /// Returns the suffixes names must end in.
+    pub fn allowed_suffixes(&self) -> Vec<&str> {
+        match &self.allowed_suffixes {
+            Some(s) => s.iter().map(String::as_str).collect(),
+            None => vec![".local", ".localhost"],
+        }
+    }
+
+    /// Returns an error if the policy doesn't allow a name.
+    pub fn check_name(&self, name: &str) -> Result<(), Error> {
+        let suffixes = self.allowed_suffixes();
+        if !suffixes.iter().any(|s| name.ends_with(s)) {
+            return Err(anyhow!("name must end in {}", suffixes.join(" or ")));
+        }
+        if let Some(p) = self.denied_patterns.iter().find(|p| glob_match(p, name)) {
+            return Err(anyhow!("name {} is denied by the policy pattern {}", name, p));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the policy doesn't allow pointing a name at an address, where None is the default 127.0.0.1.
+    pub fn check_ip(&self, ip: Option<IpAddr>) -> Result<(), Error> {
+        match ip {
+            Some(ip) if !ip.is_loopback() && self.allow_non_loopback == Some(false) => Err(anyhow!(
+                "names can only point at loopback addresses by the policy in {}, not {}",
+                self.source(),
+                ip
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if the policy limits expiries, since entries without one would outlive any limit.
+    pub fn check_no_expire(&self) -> Result<(), Error> {
+        match self.max_expire_minutes {
+            Some(max) => Err(anyhow!(
+                "entries must expire within {} minutes by the policy in {}, so they can't be permanent",
+                max,
+                self.source()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns an error if the policy doesn't allow an expiry.
+    pub fn check_expire_minutes(&self, expire_minutes: usize) -> Result<(), Error> {
+        match self.max_expire_minutes {
+            Some(max) if expire_minutes > max => Err(anyhow!(
+                "an expiry of {} minutes is longer than the {} minutes allowed by the policy in {}, ask for a shorter one \
+                 with --expire",
+                expire_minutes,
+                max,
+                self.source()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if the policy doesn't allow this many managed names, suggesting the given oldest ones to remove.
+    pub fn check_entry_count(&self, count: usize, oldest: &[&str]) -> Result<(), Error> {
+        match self.max_entries {
+            Some(max) if count > max => Err(anyhow!(
+                "this would make {} entries, more than the {} allowed by the policy in {}, consider removing the oldest: {}",
+                count,
+                max,
+                self.source(),
+                oldest.join(", ")
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn source(&self) -> &str {
+        self.path.as_deref().unwrap_or(DEFAULT_POLICY_FILE)
+    }
+}
+
+/// Matches a name against a pattern where * matches any run of characters and ? matches a single character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    // the position of the last * seen and the name position it was matched against, used to backtrack
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.local", "foo.local"));
+        assert!(glob_match("myapp-*.local", "myapp-pr-12.local"));
+        assert!(glob_match("a?c.local", "abc.local"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("myapp-*.local", "other.local"));
+        assert!(!glob_match("a?c.local", "ac.local"));
+        assert!(!glob_match("*.localhost", "foo.local"));
+    }
+
+    #[test]
+    fn test_policy_checks() -> Result<(), Error> {
+        let policy: Policy = toml::from_str(
+            r#"
+allowed_suffixes = [".test", ".local"]
+denied_patterns = ["prod*"]
+max_expire_minutes = 60
+max_entries = 2
+allow_non_loopback = false
+"#,
+        )?;
+        policy.check_name("foo.test")?;
+        policy.check_name("foo.local")?;
+        assert!(policy.check_no_expire().is_err());
+        assert_eq!(
+            policy.check_name("foo.localhost").unwrap_err().to_string(),
+            "name must end in .test or .local"
+        );
+        assert!(policy.check_name("prod-db.local").is_err());
+        policy.check_ip(None)?;
+        policy.check_ip(Some("127.0.0.2".parse()?))?;
+        policy.check_ip(Some("::1".parse()?))?;
+        assert_eq!(
+            policy.check_ip(Some("192.168.1.10".parse()?)).unwrap_err().to_string(),
+            "names can only point at loopback addresses by the policy in /etc/eha/policy.toml, not 192.168.1.10"
+        );
+        Policy::default().check_ip(Some("192.168.1.10".parse()?))?;
+        policy.check_expire_minutes(60)?;
+        assert_eq!(
+            policy.check_expire_minutes(61).unwrap_err().to_string(),
+            "an expiry of 61 minutes is longer than the 60 minutes allowed by the policy in /etc/eha/policy.toml, ask for \
+             a shorter one with --expire"
+        );
+        assert!(toml::from_str::<Policy>("unknown = true").is_err());
+        policy.check_entry_count(2, &[])?;
+        assert!(policy
+            .check_entry_count(3, &["a.local"])
+            .unwrap_err()
+            .to_string()
+            .ends_with("the oldest: a.local"));
+
+        // an admin's suffixes can't be widened with --suffix
+        let policy = policy.with_flag_suffixes(&[".dev.internal".to_string()]);
+        assert!(policy.check_name("foo.dev.internal").is_err());
+        let policy = Policy::default().with_flag_suffixes(&[".dev.internal".to_string()]);
+        policy.check_name("foo.dev.internal")?;
+        assert!(policy.check_name("foo.local").is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_file() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("policy.toml");
+        assert!(Policy::load_file(&path, true)?.is_none());
+        std::fs::write(&path, "max_entries = 3\n")?;
+        assert_eq!(Policy::load_file(&path, false)?.and_then(|p| p.max_entries), Some(3));
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))?;
+        assert!(Policy::load_file(&path, true)
+            .unwrap_err()
+            .to_string()
+            .ends_with("must be owned by root and only writable by root"));
+        Ok(())
+    }
+}
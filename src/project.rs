@@ -0,0 +1,77 @@
+use std::path::Path;
+
+/// Returns the name of the git repository containing the given directory, or None outside of one. The name comes from
+/// the url of the origin remote when there is one, like `astromechza/eha` for `git@github.com:astromechza/eha.git`,
+/// and is otherwise the name of the directory the repository is in. This reads the repository's files directly so that
+/// it works without git installed.
+pub(crate) fn detect(dir: &Path) -> Option<String> {
+    let root = dir.ancestors().find(|d| d.join(".git").exists())?;
+    std::fs::read_to_string(root.join(".git").join("config"))
+        .ok()
+        .and_then(|config| origin_url(&config))
+        .and_then(|url| name_from_url(&url))
+        .or_else(|| root.file_name().map(|n| n.to_string_lossy().to_string()))
+}
+
+/// Finds the url of the origin remote in a git config file.
+fn origin_url(config: &str) -> Option<String> {
+    let mut in_origin = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_origin = line == r#"[remote "origin"]"#;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_origin) {
+            if key.trim() == "url" {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Takes the owner and repository from a remote url, or just the repository if the url has no owner.
+fn name_from_url(url: &str) -> Option<String> {
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+    // scp-like urls separate the host from the path with a colon
+    let path = match path.split_once("://") {
+        Some((_, rest)) => rest.split_once('/').map_or("", |(_, p)| p),
+        None => path.rsplit_once(':').map_or(path, |(_, p)| p),
+    };
+    let mut parts = path.rsplit('/').filter(|p| !p.is_empty());
+    let repo = parts.next()?;
+    Some(match parts.next() {
+        Some(owner) => format!("{}/{}", owner, repo),
+        None => repo.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_detect_project() -> Result<(), Error> {
+        assert_eq!(
+            name_from_url("git@github.com:astromechza/eha.git").as_deref(),
+            Some("astromechza/eha")
+        );
+        assert_eq!(name_from_url("https://gitlab.com/group/sub/app/").as_deref(), Some("sub/app"));
+        assert_eq!(name_from_url("/srv/git/app.git").as_deref(), Some("git/app"));
+        assert_eq!(name_from_url("https://example.com/"), None);
+
+        let dir = tempdir()?;
+        let repo = dir.path().join("myapp");
+        std::fs::create_dir_all(repo.join(".git"))?;
+        std::fs::create_dir_all(repo.join("src"))?;
+        assert_eq!(detect(&repo.join("src")).as_deref(), Some("myapp"));
+
+        std::fs::write(
+            repo.join(".git").join("config"),
+            "[core]\n\tbare = false\n[remote \"upstream\"]\n\turl = git@github.com:other/fork.git\n[remote \"origin\"]\n\turl = \
+             git@github.com:me/myapp.git\n",
+        )?;
+        assert_eq!(detect(&repo.join("src")).as_deref(), Some("me/myapp"));
+        Ok(())
+    }
+}
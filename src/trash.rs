@@ -0,0 +1,64 @@
+use crate::SupportedMeta;
+use anyhow::{Context, Error};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// An entry that was removed by eha, kept around so that it can be restored with its original metadata.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    pub name: String,
+    pub removed_at: Timestamp,
+    #[serde(default)]
+    pub disabled: bool,
+    pub meta: SupportedMeta,
+}
+
+/// The trash is a json file in the state directory holding recently removed entries, oldest first.
+pub struct Trash {
+    path: PathBuf,
+    entries: Vec<TrashedEntry>,
+}
+
+impl Trash {
+    /// Loads the trash from the state directory, dropping anything removed more than the given number of days ago. A
+    /// missing trash file is treated as an empty trash.
+    pub fn load(state_dir: &Path, now: Timestamp, retention_days: usize) -> Result<Self, Error> {
+        let path = state_dir.join("trash.json");
+        let mut entries: Vec<TrashedEntry> = match File::open(&path) {
+            Ok(f) => serde_json::from_reader(f).with_context(|| format!("failed to parse trash file {}", path.to_string_lossy()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(Error::new(e).context(format!("failed to read trash file {}", path.to_string_lossy()))),
+        };
+        let cutoff = now - SignedDuration::from_hours(24 * retention_days as i64);
+        entries.retain(|e| e.removed_at > cutoff);
+        Ok(Trash { path, entries })
+    }
+
+    pub fn push(&mut self, entry: TrashedEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Takes the most recently removed entry with the given name out of the trash, along with any other lines for the
+    /// same name that were removed with it, such as the ::1 line of a dual stack name.
+    pub fn take(&mut self, name: &str) -> Vec<TrashedEntry> {
+        let Some(removed_at) = self.entries.iter().rev().find(|e| e.name == name).map(|e| e.removed_at) else {
+            return Vec::new();
+        };
+        let (taken, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|e| e.name == name && e.removed_at == removed_at);
+        self.entries = kept;
+        taken
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).context("failed to create state directory")?;
+        }
+        let file = File::create(&self.path).context("failed to create trash file")?;
+        serde_json::to_writer(file, &self.entries).context("failed to write trash file")
+    }
+}
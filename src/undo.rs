@@ -0,0 +1,109 @@
+use anyhow::{Context, Error};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// The managed lines that the last change to a hosts file added and removed, which is enough to reverse it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) command: String,
+    pub(crate) target: PathBuf,
+    pub(crate) at: Timestamp,
+    pub(crate) added: Vec<String>,
+    pub(crate) removed: Vec<String>,
+}
+
+impl Change {
+    /// Builds the change between the managed lines of a file before and after a write, or None if they are the same.
+    /// A line that was modified counts as removed and added again.
+    pub(crate) fn between(command: String, target: &Path, at: Timestamp, before: &[String], after: &[String]) -> Option<Self> {
+        let (added, removed) = (missing_from(after, before), missing_from(before, after));
+        (!added.is_empty() || !removed.is_empty()).then(|| Change {
+            command,
+            target: target.to_path_buf(),
+            at,
+            added,
+            removed,
+        })
+    }
+}
+
+/// Returns the lines of one list that the other doesn't have, counting repeated lines.
+fn missing_from(lines: &[String], other: &[String]) -> Vec<String> {
+    let mut other = other.iter().collect::<Vec<&String>>();
+    lines
+        .iter()
+        .filter(|l| match other.iter().position(|o| o == l) {
+            Some(i) => {
+                other.swap_remove(i);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// The last change made by eha is kept in `undo.json` in the state directory, replacing the one before it.
+pub(crate) struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub(crate) fn new(state_dir: &Path) -> Self {
+        History {
+            path: state_dir.join("undo.json"),
+        }
+    }
+
+    /// Returns the last change, if any was recorded.
+    pub(crate) fn last(&self) -> Result<Option<Change>, Error> {
+        match File::open(&self.path) {
+            Ok(f) => serde_json::from_reader(f).with_context(|| format!("failed to parse undo history {}", self.path.to_string_lossy())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::new(e).context(format!("failed to read undo history {}", self.path.to_string_lossy()))),
+        }
+    }
+
+    /// Records a change as the last one.
+    pub(crate) fn record(&self, change: &Change) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).context("failed to create state directory")?;
+        }
+        let mut file = File::create(&self.path).context("failed to create undo history")?;
+        file.write_all(&serde_json::to_vec(change)?)
+            .context("failed to write undo history")?;
+        file.sync_all().context("failed to sync undo history")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_record_change() -> Result<(), Error> {
+        let lines = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let now: Timestamp = "2030-01-01T00:00:00Z".parse()?;
+        let target = Path::new("/etc/hosts");
+        assert_eq!(
+            Change::between("add".to_string(), target, now, &lines(&["a", "b"]), &lines(&["b", "a"])),
+            None
+        );
+
+        let change = Change::between("renew".to_string(), target, now, &lines(&["a", "b", "b"]), &lines(&["b", "c"]));
+        let change = change.expect("lines changed");
+        assert_eq!(change.added, lines(&["c"]));
+        assert_eq!(change.removed, lines(&["a", "b"]));
+
+        let state = tempdir()?;
+        let history = History::new(&state.path().join("eha"));
+        assert_eq!(history.last()?, None);
+        history.record(&change)?;
+        assert_eq!(history.last()?, Some(change));
+        Ok(())
+    }
+}
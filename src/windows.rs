@@ -0,0 +1,163 @@
+/// The registry key holding the directory Windows reads the hosts file from.
+#[cfg(windows)]
+const TCPIP_PARAMETERS_KEY: &str = r"HKLM\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters";
+
+/// Returns the path of the hosts file. Some corporate images relocate it, so the DataBasePath registry value is
+/// preferred over the usual location under %SystemRoot%.
+#[cfg(windows)]
+pub(crate) fn hosts_path() -> String {
+    use std::process::Command;
+
+    let dir = Command::new("reg")
+        .args(["query", TCPIP_PARAMETERS_KEY, "/v", "DataBasePath"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| parse_reg_value(&String::from_utf8_lossy(&o.stdout), "DataBasePath"))
+        .map(|v| expand_env_vars(&v, |k| std::env::var(k).ok()))
+        .or_else(|| std::env::var("SystemRoot").ok().map(|r| format!(r"{}\System32\drivers\etc", r)))
+        .unwrap_or_else(|| r"C:\Windows\System32\drivers\etc".to_string());
+    format!(r"{}\hosts", dir.trim_end_matches('\\'))
+}
+
+/// Returns true if eha is running with Administrator rights, which writing the hosts file usually needs.
+#[cfg(windows)]
+pub(crate) fn is_elevated() -> bool {
+    #[link(name = "shell32")]
+    extern "system" {
+        fn IsUserAnAdmin() -> i32;
+    }
+    // SAFETY: IsUserAnAdmin has no preconditions.
+    unsafe { IsUserAnAdmin() != 0 }
+}
+
+/// Runs eha again with the given arguments in an elevated process, which asks the user through UAC, and returns its
+/// exit code. The elevated process gets its own console window.
+#[cfg(windows)]
+pub(crate) fn relaunch_elevated(args: &[String]) -> Result<i32, anyhow::Error> {
+    use anyhow::Context;
+    use std::process::Command;
+
+    let exe = std::env::current_exe().context("failed to find the eha executable")?;
+    eprintln!("relaunching eha with Administrator rights");
+    let status = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command"])
+        .arg(elevate_script(&exe.to_string_lossy(), args))
+        .status()
+        .context("failed to run powershell to relaunch eha with Administrator rights")?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Builds the PowerShell command that starts eha elevated, waits for it, and exits with its exit code. --elevate is
+/// dropped from the arguments so that the elevated eha can't relaunch itself again.
+fn elevate_script(exe: &str, args: &[String]) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let args = args
+        .iter()
+        .filter(|a| *a != "--elevate")
+        .map(|a| quote(&quote_arg(a)))
+        .collect::<Vec<String>>();
+    let arg_list = match args.is_empty() {
+        true => String::new(),
+        false => format!(" -ArgumentList {}", args.join(",")),
+    };
+    format!(
+        "$p = Start-Process -FilePath {}{} -Verb RunAs -Wait -PassThru; exit $p.ExitCode",
+        quote(exe),
+        arg_list
+    )
+}
+
+/// Quotes an argument for a Windows command line, which Start-Process joins its argument list into without quoting.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    let mut out = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // backslashes before a quote escape each other, and the quote needs one more
+                out.push_str(&"\\".repeat(backslashes * 2 + 1));
+                out.push('"');
+                backslashes = 0;
+            }
+            c => {
+                out.push_str(&"\\".repeat(backslashes));
+                out.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    out.push_str(&"\\".repeat(backslashes * 2));
+    out.push('"');
+    out
+}
+
+/// Finds the data of a string value in the output of `reg query`.
+fn parse_reg_value(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let rest = line.trim_start().strip_prefix(name)?;
+        let (_, value) = rest.split_once("REG_EXPAND_SZ").or_else(|| rest.split_once("REG_SZ"))?;
+        Some(value.trim().to_string()).filter(|v| !v.is_empty())
+    })
+}
+
+/// Expands %NAME% environment variables, leaving unknown ones as they are like Windows does.
+fn expand_env_vars(value: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        match rest[start + 1..].find('%') {
+            Some(len) => {
+                let name = &rest[start + 1..start + 1 + len];
+                match lookup(name) {
+                    Some(v) => out.push_str(&v),
+                    None => out.push_str(&rest[start..start + len + 2]),
+                }
+                rest = &rest[start + len + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hosts_dir_from_registry() {
+        let output = "\r\nHKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\r\n    DataBasePath    \
+                      REG_EXPAND_SZ    %SystemRoot%\\System32\\drivers\\etc\r\n\r\n";
+        let value = parse_reg_value(output, "DataBasePath").expect("value should be found");
+        assert_eq!(value, r"%SystemRoot%\System32\drivers\etc");
+        let lookup = |k: &str| (k == "SystemRoot").then(|| r"D:\Windows".to_string());
+        assert_eq!(expand_env_vars(&value, lookup), r"D:\Windows\System32\drivers\etc");
+        assert_eq!(expand_env_vars("%Unknown%\\etc%", lookup), "%Unknown%\\etc%");
+        assert_eq!(parse_reg_value("ERROR: not found", "DataBasePath"), None);
+    }
+
+    #[test]
+    fn test_elevate_script() {
+        assert_eq!(quote_arg("remove-expired"), "remove-expired");
+        assert_eq!(quote_arg(""), r#""""#);
+        assert_eq!(quote_arg(r"C:\My Hosts\hosts"), r#""C:\My Hosts\hosts""#);
+        assert_eq!(quote_arg(r#"say "hi"\"#), r#""say \"hi\"\\""#);
+
+        let args = ["--elevate", "add", "it's.local", "--comment", "a b"].map(String::from);
+        assert_eq!(
+            elevate_script(r"C:\bin\eha.exe", &args),
+            r#"$p = Start-Process -FilePath 'C:\bin\eha.exe' -ArgumentList 'add','it''s.local','--comment','"a b"' -Verb RunAs -Wait -PassThru; exit $p.ExitCode"#
+        );
+        assert!(!elevate_script("eha", &[]).contains("-ArgumentList"));
+    }
+}
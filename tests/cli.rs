@@ -0,0 +1,48 @@
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+/// Runs eha with a config home of its own and none of the EHA_ variables of the environment the tests run in, plus
+/// the given ones, and returns what it printed.
+fn eha(home: &Path, env: &[(&str, &str)], argv: &[&str]) -> String {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_eha"));
+    for (key, _) in std::env::vars_os().filter(|(k, _)| k.to_string_lossy().starts_with("EHA_")) {
+        command.env_remove(key);
+    }
+    let output = command
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_STATE_HOME", home.join("state"))
+        .env("HOME", home)
+        .envs(env.iter().copied())
+        .args(argv)
+        .output()
+        .expect("eha should run");
+    assert!(output.status.success(), "eha failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).trim_end().to_string()
+}
+
+#[test]
+fn test_env_overrides_config_and_flags_override_env() {
+    let home = tempdir().expect("tempdir should be created");
+    std::fs::create_dir_all(home.path().join("config/eha")).expect("config dir should be created");
+    std::fs::write(
+        home.path().join("config/eha/config.toml"),
+        "input_file = \"/from/config\"\ndefault_expire_minutes = 45\n",
+    )
+    .expect("config should be written");
+    let get = |env: &[(&str, &str)], flags: &[&str]| {
+        let argv = |key| flags.iter().copied().chain(["config", "get", key]).collect::<Vec<_>>();
+        (
+            eha(home.path(), env, &argv("input_file")),
+            eha(home.path(), env, &argv("default_expire_minutes")),
+        )
+    };
+
+    assert_eq!(get(&[], &[]), ("/from/config".to_string(), "45".to_string()));
+    let env = [("EHA_INPUT_FILE", "/from/env"), ("EHA_DEFAULT_EXPIRE", "2h")];
+    assert_eq!(get(&env, &[]), ("/from/env".to_string(), "120".to_string()));
+    assert_eq!(
+        get(&env, &["--input-file", "/from/flag", "--default-expire", "30"]),
+        ("/from/flag".to_string(), "30".to_string())
+    );
+}